@@ -0,0 +1,24 @@
+//! Element definitions of complex types and resources.
+
+/// Definition of an element of a complex type or resource, as far as needed
+/// to work with its JSON form. Look it up via the `element_definition`
+/// function of the version's `elements` module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ElementDefinition {
+	/// Type codes of the element, e.g. `date` or `HumanName`. Choice elements
+	/// like `value[x]` have multiple types.
+	pub types: &'static [&'static str],
+	/// Path of the element whose definition the element re-uses, e.g.
+	/// `Questionnaire.item` for `Questionnaire.item.item`.
+	pub content_reference: Option<&'static str>,
+	/// Whether the element is a list.
+	pub is_array: bool,
+}
+
+impl ElementDefinition {
+	/// Whether the element is a choice element like `value[x]`.
+	#[must_use]
+	pub const fn is_choice(&self) -> bool {
+		self.types.len() > 1
+	}
+}
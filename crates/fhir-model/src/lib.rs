@@ -6,6 +6,7 @@
 mod bundle;
 mod concepts;
 mod date_time;
+mod element;
 mod error;
 mod identifiable_resource;
 #[cfg(feature = "r4b")]
@@ -23,7 +24,7 @@ use base64::prelude::{Engine, BASE64_STANDARD};
 use serde::{Deserialize, Serialize};
 pub use time;
 
-pub use self::{date_time::*, error::*, references::*};
+pub use self::{date_time::*, element::*, error::*, references::*};
 
 /// Run a macro for all activated FHIR versions to implement similar things for
 /// different FHIR versions.
@@ -1,6 +1,7 @@
 //! Revision 5 types of FHIR.
 
 pub mod codes;
+pub mod elements;
 pub mod resources;
 pub mod types;
 
@@ -0,0 +1,6 @@
+//! Element definitions.
+
+#[rustfmt::skip] // Too much for rustfmt
+mod generated;
+
+pub use generated::*;
@@ -0,0 +1,446 @@
+//! Structural diff of resources, reporting the changed elements with
+//! FHIRPath paths, e.g. to audit the changes between versions of a resource.
+//! The changes are derived from the `FHIRPath Patch` diff, see
+//! [diff_fhirpath].
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+	patch::{
+		common_bounds, diff_fhirpath, element::Element, value_at, FhirPathPatchOperation,
+		PatchError,
+	},
+	version::FhirVersion,
+};
+
+/// Kind of change of an element.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChangeKind {
+	/// The element was added.
+	Added,
+	/// The element was removed.
+	Removed,
+	/// The value of the element was changed.
+	Changed,
+}
+
+/// A change of an element between two resources.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+	/// FHIRPath path of the element, e.g. `Patient.name[0].given[1]` or
+	/// `Observation.value` for `valueQuantity`. List indices refer to the old
+	/// resource for removed elements and to the new resource otherwise. The
+	/// resource type alone stands for the whole resource, e.g. when it was
+	/// deleted.
+	pub path: String,
+	/// Kind of change.
+	pub kind: ChangeKind,
+	/// Old value of the element in its FHIR JSON form.
+	pub old: Option<Value>,
+	/// New value of the element in its FHIR JSON form.
+	pub new: Option<Value>,
+}
+
+/// The changes a version of a resource made to its previous version.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VersionChanges {
+	/// Version ID of the newer version.
+	pub version_id: Option<String>,
+	/// Changes to the previous version.
+	pub changes: Vec<Change>,
+}
+
+/// Options for diffing resources. By default, `meta.versionId` and
+/// `meta.lastUpdated` are ignored, as they change with every version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffOptions {
+	/// Paths of elements to ignore, without resource type and list indices.
+	ignored: BTreeSet<String>,
+}
+
+impl Default for DiffOptions {
+	fn default() -> Self {
+		Self::empty().ignore("meta.versionId").ignore("meta.lastUpdated")
+	}
+}
+
+impl DiffOptions {
+	/// Options without any ignored elements.
+	#[must_use]
+	pub const fn empty() -> Self {
+		Self { ignored: BTreeSet::new() }
+	}
+
+	/// Ignore changes of the element at the path, its children and its
+	/// primitive extension. The path is given without resource type and list
+	/// indices, e.g. `meta.lastUpdated`, `text` or `name.period`.
+	#[must_use]
+	pub fn ignore(mut self, path: impl Into<String>) -> Self {
+		self.ignored.insert(path.into());
+		self
+	}
+
+	/// Compute the changes that turn `old` into `new`, comparing their JSON
+	/// form. Works for any resource, including the `Resource` enums. Lists are
+	/// compared element by element after skipping the common start and end.
+	/// Choice elements are reported as a whole, e.g. `Patient.deceased`.
+	/// Changes of primitive extensions, e.g. `_birthDate`, are reported as
+	/// changes of the element, e.g. `Patient.birthDate`, with the JSON values
+	/// of the extensions as old and new value.
+	pub fn diff<V: FhirVersion, R: Serialize>(
+		&self,
+		old: &R,
+		new: &R,
+	) -> Result<Vec<Change>, PatchError> {
+		let mut old = serde_json::to_value(old)?;
+		let mut new = serde_json::to_value(new)?;
+		for ignored in &self.ignored {
+			remove_element(&mut old, ignored);
+			remove_element(&mut new, ignored);
+		}
+
+		// `FHIRPath Patch` cannot express primitive extensions, so they are
+		// compared separately.
+		let resource_type =
+			new.get("resourceType").and_then(Value::as_str).unwrap_or_default().to_owned();
+		let mut extension_changes = Vec::new();
+		diff_primitive_extensions::<V>(
+			&mut old,
+			&mut new,
+			&resource_type,
+			&Element::root(&resource_type),
+			&mut extension_changes,
+		)?;
+
+		// Number of list elements added per path so far, for their indices.
+		let mut added = BTreeMap::<String, usize>::new();
+		let mut changes = Vec::new();
+		for operation in diff_fhirpath::<V, _>(&old, &new)? {
+			let change = match operation {
+				FhirPathPatchOperation::Add { path, name, value, .. } => {
+					let mut path = format!("{path}.{name}");
+					if Element::resolve::<V>(&path)?.is_array() {
+						let index = added.entry(path.clone()).or_default();
+						path = format!("{path}[{index}]");
+						*index += 1;
+					}
+					Change { path, kind: ChangeKind::Added, old: None, new: Some(value) }
+				}
+				FhirPathPatchOperation::Insert { path, value, index, .. } => Change {
+					path: format!("{path}[{index}]"),
+					kind: ChangeKind::Added,
+					old: None,
+					new: Some(value),
+				},
+				FhirPathPatchOperation::Delete { path } => Change {
+					old: value_at::<V>(&old, &path).cloned(),
+					path,
+					kind: ChangeKind::Removed,
+					new: None,
+				},
+				FhirPathPatchOperation::Replace { path, value, .. } => Change {
+					old: value_at::<V>(&old, &path).cloned(),
+					path,
+					kind: ChangeKind::Changed,
+					new: Some(value),
+				},
+				// Not generated by diffs.
+				FhirPathPatchOperation::Move { .. } => continue,
+			};
+			changes.push(change);
+		}
+		changes.append(&mut extension_changes);
+		Ok(changes)
+	}
+
+	/// Compute the changes between two versions of a resource, where `None`
+	/// stands for a deleted or not yet created version. Creations and
+	/// deletions are reported as a single change of the whole resource.
+	pub fn diff_versions<V: FhirVersion, R: Serialize>(
+		&self,
+		old: Option<&R>,
+		new: Option<&R>,
+	) -> Result<Vec<Change>, PatchError> {
+		let (kind, old, new) = match (old, new) {
+			(Some(old), Some(new)) => return self.diff::<V, R>(old, new),
+			(None, None) => return Ok(Vec::new()),
+			(None, Some(new)) => (ChangeKind::Added, None, Some(serde_json::to_value(new)?)),
+			(Some(old), None) => (ChangeKind::Removed, Some(serde_json::to_value(old)?), None),
+		};
+		let path = old
+			.as_ref()
+			.or(new.as_ref())
+			.and_then(|resource| resource.get("resourceType"))
+			.and_then(Value::as_str)
+			.unwrap_or_default()
+			.to_owned();
+		Ok(vec![Change { path, kind, old, new }])
+	}
+}
+
+/// Compute the changes that turn `old` into `new` with the default
+/// [DiffOptions].
+pub fn changes<V: FhirVersion, R: Serialize>(old: &R, new: &R) -> Result<Vec<Change>, PatchError> {
+	DiffOptions::default().diff::<V, R>(old, new)
+}
+
+/// Remove the element at the path without resource type and list indices from
+/// the JSON value, and its parents if they become empty.
+fn remove_element(value: &mut Value, path: &str) {
+	match value {
+		Value::Array(list) => {
+			for value in list {
+				remove_element(value, path);
+			}
+		}
+		Value::Object(object) => match path.split_once('.') {
+			Some((key, rest)) => {
+				let Some(child) = object.get_mut(key) else { return };
+				remove_element(child, rest);
+				let is_empty = child.as_object().is_some_and(serde_json::Map::is_empty);
+				if is_empty {
+					object.remove(key);
+				}
+			}
+			None => {
+				object.remove(path);
+				object.remove(&format!("_{path}"));
+			}
+		},
+		_ => {}
+	}
+}
+
+/// Remove the extensions of primitive elements, e.g. `_birthDate`, from the
+/// JSON values of the element and collect their changes as changes of the
+/// element. Descends into the same pairs of values as the `FHIRPath Patch`
+/// diff, so it does not encounter any primitive extensions afterwards.
+fn diff_primitive_extensions<V: FhirVersion>(
+	old: &mut Value,
+	new: &mut Value,
+	path: &str,
+	element: &Element,
+	changes: &mut Vec<Change>,
+) -> Result<(), PatchError> {
+	if old == new {
+		return Ok(());
+	}
+
+	match (old, new) {
+		// Resources, e.g. `contained`, are replaced as a whole.
+		(Value::Object(old), Value::Object(new)) if !element.is_resource() => {
+			let extension_keys: BTreeSet<String> =
+				old.keys().chain(new.keys()).filter(|key| key.starts_with('_')).cloned().collect();
+			for key in extension_keys {
+				let old_value = old.remove(&key);
+				let new_value = new.remove(&key);
+				if old_value == new_value {
+					continue;
+				}
+				let (name, _) =
+					element.json_child::<V>(key.strip_prefix('_').unwrap_or_default())?;
+				let kind = match (&old_value, &new_value) {
+					(None, _) => ChangeKind::Added,
+					(_, None) => ChangeKind::Removed,
+					_ => ChangeKind::Changed,
+				};
+				changes.push(Change {
+					path: format!("{path}.{name}"),
+					kind,
+					old: old_value,
+					new: new_value,
+				});
+			}
+
+			for (key, old_value) in old.iter_mut() {
+				let Some(new_value) = new.get_mut(key) else { continue };
+				if key.as_str() == "resourceType" || old_value == new_value {
+					continue;
+				}
+				let (name, child) = element.json_child::<V>(key)?;
+				// Choice elements are replaced as a whole.
+				if !child.is_choice() {
+					let child_path = format!("{path}.{name}");
+					diff_primitive_extensions::<V>(
+						old_value,
+						new_value,
+						&child_path,
+						&child,
+						changes,
+					)?;
+				}
+			}
+		}
+		(Value::Array(old), Value::Array(new)) => {
+			let (prefix, suffix) = common_bounds(old, new);
+			let old_changed_len = old.len() - prefix - suffix;
+			let new_changed_len = new.len() - prefix - suffix;
+			let pairs = old
+				.iter_mut()
+				.zip(new.iter_mut())
+				.enumerate()
+				.skip(prefix)
+				.take(old_changed_len.min(new_changed_len));
+			for (index, (old_value, new_value)) in pairs {
+				let element_path = format!("{path}[{index}]");
+				diff_primitive_extensions::<V>(
+					old_value,
+					new_value,
+					&element_path,
+					element,
+					changes,
+				)?;
+			}
+		}
+		_ => {}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+
+	use super::*;
+
+	#[cfg(feature = "r5")]
+	#[test]
+	fn change_report() -> Result<(), PatchError> {
+		use crate::version::FhirR5;
+
+		let old = json!({
+			"resourceType": "Patient",
+			"meta": { "versionId": "1", "lastUpdated": "2024-01-01T00:00:00Z" },
+			"active": true,
+			"name": [{ "family": "Doe", "given": ["John"] }],
+			"text": { "status": "generated", "div": "<div>John</div>" },
+		});
+		let new = json!({
+			"resourceType": "Patient",
+			"meta": { "versionId": "2", "lastUpdated": "2024-01-02T00:00:00Z" },
+			"active": false,
+			"name": [{ "family": "Doe", "given": ["John", "Jim"] }],
+			"telecom": [{ "value": "1" }],
+			"text": { "status": "generated", "div": "<div>John Jim</div>" },
+		});
+
+		let changes = DiffOptions::default().ignore("text").diff::<FhirR5, _>(&old, &new)?;
+		assert_eq!(
+			changes,
+			vec![
+				Change {
+					path: "Patient.active".to_owned(),
+					kind: ChangeKind::Changed,
+					old: Some(json!(true)),
+					new: Some(json!(false)),
+				},
+				Change {
+					path: "Patient.name[0].given[1]".to_owned(),
+					kind: ChangeKind::Added,
+					old: None,
+					new: Some(json!("Jim")),
+				},
+				Change {
+					path: "Patient.telecom[0]".to_owned(),
+					kind: ChangeKind::Added,
+					old: None,
+					new: Some(json!({ "value": "1" })),
+				},
+			]
+		);
+
+		let changes = DiffOptions::empty().diff::<FhirR5, _>(&new, &old)?;
+		let paths: Vec<_> = changes.iter().map(|change| change.path.as_str()).collect();
+		assert_eq!(
+			paths,
+			[
+				"Patient.active",
+				"Patient.meta.lastUpdated",
+				"Patient.meta.versionId",
+				"Patient.name[0].given[1]",
+				"Patient.telecom[0]",
+				"Patient.text.div",
+			]
+		);
+		assert_eq!(changes.get(4).map(|change| change.kind), Some(ChangeKind::Removed));
+		Ok(())
+	}
+
+	#[cfg(feature = "r5")]
+	#[test]
+	fn change_report_of_choice_and_deletion() -> Result<(), PatchError> {
+		use crate::version::FhirR5;
+
+		let old = json!({ "resourceType": "Patient", "deceasedBoolean": false });
+		let new = json!({ "resourceType": "Patient", "deceasedDateTime": "2024-01-01" });
+
+		let changes = changes::<FhirR5, _>(&old, &new)?;
+		assert_eq!(
+			changes,
+			vec![Change {
+				path: "Patient.deceased".to_owned(),
+				kind: ChangeKind::Changed,
+				old: Some(json!(false)),
+				new: Some(json!("2024-01-01")),
+			}]
+		);
+
+		let changes = DiffOptions::default().diff_versions::<FhirR5, _>(Some(&old), None)?;
+		assert_eq!(
+			changes,
+			vec![Change {
+				path: "Patient".to_owned(),
+				kind: ChangeKind::Removed,
+				old: Some(old),
+				new: None,
+			}]
+		);
+		Ok(())
+	}
+
+	#[cfg(feature = "r5")]
+	#[test]
+	fn change_report_of_primitive_extensions() -> Result<(), PatchError> {
+		use crate::version::FhirR5;
+
+		let extension = |value: &str| json!({ "extension": [{ "url": "http://example.com", "valueString": value }] });
+		let old = json!({
+			"resourceType": "Patient",
+			"birthDate": "2000-01-01",
+			"name": [{ "given": ["John", "Jim"], "_given": [null, extension("a")] }],
+		});
+		let new = json!({
+			"resourceType": "Patient",
+			"birthDate": "2000-01-01",
+			"_birthDate": extension("b"),
+			"name": [{ "given": ["John", "Jim"], "_given": [null, extension("c")] }],
+		});
+
+		let changes = changes::<FhirR5, _>(&old, &new)?;
+		assert_eq!(
+			changes,
+			vec![
+				Change {
+					path: "Patient.birthDate".to_owned(),
+					kind: ChangeKind::Added,
+					old: None,
+					new: Some(extension("b")),
+				},
+				Change {
+					path: "Patient.name[0].given".to_owned(),
+					kind: ChangeKind::Changed,
+					old: Some(json!([null, extension("a")])),
+					new: Some(json!([null, extension("c")])),
+				},
+			]
+		);
+
+		let changes = DiffOptions::default().ignore("birthDate").diff::<FhirR5, _>(&new, &old)?;
+		let paths: Vec<_> = changes.iter().map(|change| change.path.as_str()).collect();
+		assert_eq!(paths, ["Patient.name[0].given"]);
+		Ok(())
+	}
+}
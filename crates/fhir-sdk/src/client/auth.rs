@@ -33,6 +33,14 @@ impl AuthCallback {
 	}
 }
 
+impl LoginManager for AuthCallback {
+	type Error = AuthCallbackError;
+
+	async fn authenticate(&mut self, client: reqwest::Client) -> Result<HeaderValue, Self::Error> {
+		Self::authenticate(self, client).await
+	}
+}
+
 /// Trait to be implemented for the
 /// [`ClientBuilder::auth_callback`](super::builder::ClientBuilder::auth_callback).
 /// You can implement the functions as `async fn`, no need for `impl Future`.
@@ -0,0 +1,504 @@
+//! Blocking (synchronous) client API on top of the async client.
+
+use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc};
+
+use fhir_model::{for_all_versions, WrongResourceType};
+use futures::{Stream, StreamExt};
+use reqwest::{StatusCode, Url};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::runtime::Runtime;
+
+use super::{
+	BatchResponse, BatchTransaction, Client, ClientBuilder, Error, GraphUpload, HistoryEntry,
+	LoginManager, Page, PatchViaFhir, PatchViaJson, SearchParameters, ServerCapabilities,
+};
+use crate::{
+	changes::{DiffOptions, VersionChanges},
+	extensions::AnyResource,
+	version::{fhir_version, DefaultVersion, FhirVersion},
+};
+
+/// Blocking FHIR REST client, mirroring the API of the async [Client]. It runs
+/// the async client on an internal runtime with one worker thread, shared by
+/// all clones of the client.
+///
+/// Must not be used within an async context, as blocking on the runtime would
+/// panic there. Use the async [Client] instead.
+pub struct BlockingClient<V = DefaultVersion> {
+	/// The async client.
+	client: Client<V>,
+	/// Runtime to run the requests on.
+	runtime: Arc<Runtime>,
+}
+
+impl<V: FhirVersion> BlockingClient<V> {
+	/// Create a new client with default settings.
+	pub fn new(base_url: Url) -> Result<Self, Error> {
+		Self::from_async(Client::new(base_url)?)
+	}
+
+	/// Create a blocking client wrapping the async client, e.g. built via
+	/// [Client::builder].
+	pub fn from_async(client: Client<V>) -> Result<Self, Error> {
+		let runtime = tokio::runtime::Builder::new_multi_thread()
+			.worker_threads(1)
+			.thread_name("fhir-sdk-blocking")
+			.enable_all()
+			.build()
+			.map_err(Error::Runtime)?;
+		Ok(Self { client, runtime: Arc::new(runtime) })
+	}
+
+	/// Get the wrapped async client.
+	#[must_use]
+	pub const fn as_async(&self) -> &Client<V> {
+		&self.client
+	}
+
+	/// Get the configured base URL.
+	#[must_use]
+	pub fn base_url(&self) -> &Url {
+		self.client.base_url()
+	}
+
+	/// Run the future to completion on the internal runtime, e.g. to send
+	/// requests of the async API that are not mirrored here, like
+	/// `client.block_on(client.as_async().system_history().send())`.
+	pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+		self.runtime.block_on(future)
+	}
+
+	/// Send a custom HTTP request using the client's HTTP machinery, see
+	/// [Client::send_custom_request]. The response body is read into memory.
+	pub fn send_custom_request<F>(&self, make_request: F) -> Result<http::Response<Vec<u8>>, Error>
+	where
+		F: FnOnce(&reqwest::Client) -> reqwest::RequestBuilder + Send,
+	{
+		self.block_on(async {
+			let response = self.client.send_custom_request(make_request).await?;
+			let mut http_response = http::Response::new(Vec::new());
+			*http_response.status_mut() = response.status();
+			*http_response.headers_mut() = response.headers().clone();
+			*http_response.body_mut() = response.bytes().await?.to_vec();
+			Ok(http_response)
+		})
+	}
+}
+
+impl<V: FhirVersion> BlockingClient<V>
+where
+	(StatusCode, V::OperationOutcome): Into<Error>,
+{
+	/// Get the server's capabilities. Fails if the respective FHIR version is
+	/// not supported at all.
+	pub fn capabilities(&self) -> Result<V::CapabilityStatement, Error> {
+		self.block_on(self.client.capabilities())
+	}
+
+	/// Get the server's cached capabilities, see [Client::server_capabilities].
+	pub fn server_capabilities(&self) -> Result<Arc<ServerCapabilities>, Error> {
+		self.block_on(self.client.server_capabilities())
+	}
+
+	/// Read the current version of a specific FHIR resource.
+	pub fn read<R: AnyResource<V> + DeserializeOwned>(&self, id: &str) -> Result<Option<R>, Error> {
+		self.block_on(self.client.read(id))
+	}
+
+	/// Read a specific version of a specific FHIR resource.
+	pub fn read_version<R: AnyResource<V> + DeserializeOwned>(
+		&self,
+		id: &str,
+		version_id: &str,
+	) -> Result<Option<R>, Error> {
+		self.block_on(self.client.read_version(id, version_id))
+	}
+
+	/// Read the resource that is targeted in the reference.
+	pub fn read_referenced(&self, reference: &V::Reference) -> Result<V::Resource, Error> {
+		self.block_on(self.client.read_referenced(reference))
+	}
+
+	/// Retrieve the history of the specified resource type or a specific
+	/// resource.
+	pub fn history<R>(&self, id: Option<&str>) -> Result<BlockingPage<V, R>, Error>
+	where
+		R: AnyResource<V> + TryFrom<V::Resource, Error = WrongResourceType> + 'static,
+		for<'a> &'a R: TryFrom<&'a V::Resource>,
+	{
+		let page = self.block_on(self.client.history(id))?;
+		Ok(BlockingPage::new(page, self.runtime.clone()))
+	}
+
+	/// Compute the changes between consecutive versions of a resource, see
+	/// [Client::history_changes].
+	pub fn history_changes<R>(
+		&self,
+		id: &str,
+		options: DiffOptions,
+	) -> Result<Box<dyn Iterator<Item = Result<VersionChanges, Error>> + Send>, Error>
+	where
+		R: AnyResource<V> + TryFrom<V::Resource, Error = WrongResourceType> + Serialize + 'static,
+		for<'a> &'a R: TryFrom<&'a V::Resource>,
+	{
+		let changes = self.block_on(self.client.history_changes::<R>(id, options))?;
+		Ok(Box::new(BlockingIter::new(changes, self.runtime.clone())))
+	}
+
+	/// Create a new FHIR resource on the FHIR server. Returns the resource ID
+	/// and version ID.
+	pub fn create<R: AnyResource<V> + Serialize + Send + Sync>(
+		&self,
+		resource: &R,
+	) -> Result<(String, Option<String>), Error> {
+		self.block_on(self.client.create(resource))
+	}
+
+	/// Update a FHIR resource (or create it if it did not exist), see
+	/// [Client::update]. Returns whether the resource was created and the
+	/// version ID.
+	pub fn update<R: AnyResource<V> + Serialize + Send + Sync>(
+		&self,
+		resource: &R,
+		conditional: bool,
+	) -> Result<(bool, String), Error> {
+		self.block_on(self.client.update(resource, conditional))
+	}
+
+	/// Delete a FHIR resource on the server.
+	pub fn delete(&self, resource_type: V::ResourceType, id: &str) -> Result<(), Error> {
+		self.block_on(self.client.delete(resource_type, id))
+	}
+
+	/// Search for FHIR resources of any type given the query parameters.
+	pub fn search_all(
+		&self,
+		queries: SearchParameters,
+	) -> Result<BlockingPage<V, V::Resource>, Error> {
+		let page = self.block_on(self.client.search_all(queries))?;
+		Ok(BlockingPage::new(page, self.runtime.clone()))
+	}
+
+	/// Search for FHIR resources of a given type given the query parameters.
+	pub fn search<R>(&self, queries: SearchParameters) -> Result<BlockingPage<V, R>, Error>
+	where
+		R: AnyResource<V> + TryFrom<V::Resource, Error = WrongResourceType> + 'static,
+		for<'a> &'a R: TryFrom<&'a V::Resource>,
+	{
+		let page = self.block_on(self.client.search(queries))?;
+		Ok(BlockingPage::new(page, self.runtime.clone()))
+	}
+
+	/// Search for FHIR resources via a custom request, see
+	/// [Client::search_custom].
+	pub fn search_custom<R>(
+		&self,
+		make_request: impl FnOnce(&reqwest::Client) -> reqwest::RequestBuilder + Send,
+	) -> Result<BlockingPage<V, R>, Error>
+	where
+		R: TryFrom<V::Resource> + Send + Sync + 'static,
+		for<'a> &'a R: TryFrom<&'a V::Resource>,
+	{
+		let page = self.block_on(self.client.search_custom(make_request))?;
+		Ok(BlockingPage::new(page, self.runtime.clone()))
+	}
+
+	/// Patch a FHIR resource via the `FHIRPath Patch` method. The operations
+	/// are added to the [PatchViaFhir] builder in the closure.
+	pub fn patch_via_fhir<'a>(
+		&self,
+		resource_type: V::ResourceType,
+		id: &'a str,
+		make_patch: impl FnOnce(PatchViaFhir<'a, V>) -> Result<PatchViaFhir<'a, V>, Error>,
+	) -> Result<(), Error> {
+		let patch = make_patch(self.client.patch_via_fhir(resource_type, id))?;
+		self.block_on(patch.send())
+	}
+
+	/// Patch a FHIR resource via the
+	/// [`JSON Patch`](https://datatracker.ietf.org/doc/html/rfc6902) method.
+	/// The operations are added to the [PatchViaJson] builder in the closure.
+	pub fn patch_via_json<'a>(
+		&self,
+		resource_type: V::ResourceType,
+		id: &'a str,
+		make_patch: impl FnOnce(PatchViaJson<'a, V>) -> Result<PatchViaJson<'a, V>, Error>,
+	) -> Result<(), Error> {
+		let patch = make_patch(self.client.patch_via_json(resource_type, id))?;
+		self.block_on(patch.send())
+	}
+
+	/// Send a batch request. The entries are added to the [BatchTransaction]
+	/// builder in the closure.
+	pub fn batch(
+		&self,
+		make_batch: impl FnOnce(BatchTransaction<V>) -> Result<BatchTransaction<V>, Error>,
+	) -> Result<BatchResponse<V>, Error> {
+		let batch = make_batch(self.client.batch())?;
+		self.block_on(batch.send())
+	}
+
+	/// Send a transaction request. The entries are added to the
+	/// [BatchTransaction] builder in the closure.
+	pub fn transaction(
+		&self,
+		make_transaction: impl FnOnce(BatchTransaction<V>) -> Result<BatchTransaction<V>, Error>,
+	) -> Result<BatchResponse<V>, Error> {
+		let transaction = make_transaction(self.client.transaction())?;
+		self.block_on(transaction.send())
+	}
+
+	/// Upload resources that reference each other via their local IDs, see
+	/// [Client::upload_graph]. The upload is configured via the
+	/// [GraphUpload] builder in the closure.
+	pub fn upload_graph(
+		&self,
+		resources: Vec<V::Resource>,
+		make_upload: impl FnOnce(GraphUpload<V>) -> GraphUpload<V>,
+	) -> Result<HashMap<String, String>, Error> {
+		let upload = make_upload(self.client.upload_graph(resources));
+		self.block_on(upload.send())
+	}
+}
+
+impl<V> Clone for BlockingClient<V> {
+	fn clone(&self) -> Self {
+		Self { client: self.client.clone(), runtime: self.runtime.clone() }
+	}
+}
+
+impl<V: FhirVersion> From<BlockingClient<V>> for Client<V> {
+	fn from(client: BlockingClient<V>) -> Self {
+		client.client
+	}
+}
+
+impl<V> std::fmt::Debug for BlockingClient<V> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("BlockingClient").field("client", &self.client).finish_non_exhaustive()
+	}
+}
+
+impl<V, ACB> ClientBuilder<V, ACB>
+where
+	V: FhirVersion,
+{
+	/// Finalize building a [BlockingClient].
+	pub fn build_blocking(self) -> Result<BlockingClient<V>, Error>
+	where
+		ACB: LoginManager + 'static,
+	{
+		BlockingClient::from_async(self.build()?)
+	}
+}
+
+/// Blocking wrapper around a [Page] of results, paging via iterators instead of
+/// streams.
+pub struct BlockingPage<V: FhirVersion, R> {
+	/// The async page.
+	page: Page<V, R>,
+	/// Runtime to fetch further pages on.
+	runtime: Arc<Runtime>,
+}
+
+impl<V: FhirVersion, R> BlockingPage<V, R>
+where
+	(StatusCode, V::OperationOutcome): Into<Error>,
+	R: TryFrom<V::Resource> + Send + Sync + 'static,
+	for<'a> &'a R: TryFrom<&'a V::Resource>,
+{
+	/// Wrap the page.
+	const fn new(page: Page<V, R>, runtime: Arc<Runtime>) -> Self {
+		Self { page, runtime }
+	}
+
+	/// Get the wrapped async page.
+	#[must_use]
+	pub fn into_async(self) -> Page<V, R> {
+		self.page
+	}
+
+	/// Get the next page URL, if there is one.
+	pub fn next_page_url(&self) -> Option<&String> {
+		self.page.next_page_url()
+	}
+
+	/// Fetch the next page and return it.
+	pub fn next_page(&self) -> Option<Result<Self, Error>> {
+		let next_page = self.runtime.block_on(self.page.next_page())?;
+		Some(next_page.map(|page| Self::new(page, self.runtime.clone())))
+	}
+
+	/// Get the `total` field, indicating the total number of results.
+	pub fn total(&self) -> Option<u32> {
+		self.page.total()
+	}
+
+	/// Get the inner Bundle.
+	pub fn into_inner(self) -> V::Bundle {
+		self.page.into_inner()
+	}
+
+	/// Get the entries of this page, ignoring entries whenever there is no
+	/// `resource` in the entry.
+	pub fn entries(&self) -> impl Iterator<Item = &V::Resource> + Send {
+		self.page.entries()
+	}
+
+	/// Get the matches of this page, ignoring entries whenever there is no
+	/// `resource` in the entry or the resource is not of the requested type.
+	pub fn matches(&self) -> impl Iterator<Item = &R> + Send {
+		self.page.matches()
+	}
+
+	/// Iterate through all entries across pages, fetching further pages as
+	/// needed.
+	pub fn all_entries(self) -> impl Iterator<Item = Result<V::Resource, Error>> {
+		BlockingIter::new(self.page.all_entries(), self.runtime)
+	}
+
+	/// Iterate through all matches across pages, fetching further pages as
+	/// needed.
+	pub fn all_matches(self) -> impl Iterator<Item = Result<R, Error>> {
+		BlockingIter::new(self.page.all_matches(), self.runtime)
+	}
+
+	/// Iterate through all history entries across pages, including deletions.
+	pub fn all_history_entries(self) -> impl Iterator<Item = Result<HistoryEntry<V>, Error>> {
+		BlockingIter::new(self.page.all_history_entries(), self.runtime)
+	}
+}
+
+impl<V: FhirVersion, R> Clone for BlockingPage<V, R> {
+	fn clone(&self) -> Self {
+		Self { page: self.page.clone(), runtime: self.runtime.clone() }
+	}
+}
+
+impl<V: FhirVersion, R> std::fmt::Debug for BlockingPage<V, R> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("BlockingPage").field("page", &self.page).finish_non_exhaustive()
+	}
+}
+
+/// Iterator over a stream, blocking on the runtime for each item.
+struct BlockingIter<S> {
+	/// The stream.
+	stream: Pin<Box<S>>,
+	/// Runtime to poll the stream on.
+	runtime: Arc<Runtime>,
+}
+
+impl<S: Stream> BlockingIter<S> {
+	/// Wrap the stream.
+	fn new(stream: S, runtime: Arc<Runtime>) -> Self {
+		Self { stream: Box::pin(stream), runtime }
+	}
+}
+
+impl<S: Stream> Iterator for BlockingIter<S> {
+	type Item = S::Item;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.runtime.block_on(self.stream.next())
+	}
+}
+
+/// Implement the operations of the async client for the appropriate versions.
+macro_rules! impl_blocking_operations {
+	($version:ident) => {
+		mod $version {
+			use fhir_model::$version::resources::{Bundle, Patient};
+
+			use super::*;
+
+			/// Selected FHIR version.
+			type Version = fhir_version!($version);
+
+			impl BlockingClient<Version> {
+				/// Operation `$everything` on `Encounter`, returning a Bundle with all
+				/// resources for an `Encounter` record.
+				pub fn operation_encounter_everything(&self, id: &str) -> Result<Bundle, Error> {
+					self.block_on(self.client.operation_encounter_everything(id))
+				}
+
+				/// Operation `$everything` on `Patient`, returning a Bundle with all
+				/// resources for an `Patient` record.
+				pub fn operation_patient_everything(&self, id: &str) -> Result<Bundle, Error> {
+					self.block_on(self.client.operation_patient_everything(id))
+				}
+
+				/// Operation `$match` on `Patient`, returning matches for Patient
+				/// records based on a given incomplete Patient resource.
+				pub fn operation_patient_match(
+					&self,
+					patient: Patient,
+					only_certain: bool,
+					count: i32,
+				) -> Result<Bundle, Error> {
+					self.block_on(self.client.operation_patient_match(patient, only_certain, count))
+				}
+			}
+		}
+	};
+}
+mod operations {
+	//! Module for avoidance of conflicts.
+	use super::*;
+	for_all_versions!(impl_blocking_operations);
+}
+
+/// Implement the subscription operations of the async client for the
+/// appropriate versions.
+macro_rules! impl_blocking_subscription_operations {
+	// These versions do not have that operation.
+	(stu3) => {};
+	(r4b) => {};
+	// Implement it for all others.
+	($version:ident) => {
+		mod $version {
+			use fhir_model::$version::{
+				codes::SubscriptionPayloadContent,
+				resources::{Bundle, SubscriptionStatus},
+			};
+
+			use super::*;
+
+			/// Selected FHIR version.
+			type Version = fhir_version!($version);
+
+			impl BlockingClient<Version> {
+				/// Operation `$status` on `Subscription`, returning the
+				/// `SubcriptionStatus`.
+				pub fn operation_subscription_status(
+					&self,
+					id: &str,
+				) -> Result<SubscriptionStatus, Error> {
+					self.block_on(self.client.operation_subscription_status(id))
+				}
+
+				/// Operation `$events` on `Subscription`, returning the previous
+				/// notifications that were triggered by a topic.
+				pub fn operation_subscription_events(
+					&self,
+					id: &str,
+					events_since: Option<i64>,
+					events_until: Option<i64>,
+					content: Option<SubscriptionPayloadContent>,
+				) -> Result<Bundle, Error> {
+					self.block_on(self.client.operation_subscription_events(
+						id,
+						events_since,
+						events_until,
+						content,
+					))
+				}
+			}
+		}
+	};
+}
+mod subscription_operations {
+	//! Module for avoidance of conflicts.
+	use super::*;
+	for_all_versions!(impl_blocking_subscription_operations);
+}
@@ -1,10 +1,13 @@
 //! Builder implementation for the client.
 
-use std::marker::PhantomData;
+use std::{marker::PhantomData, sync::Arc};
 
 use reqwest::Url;
 
-use super::{auth::AuthCallback, Client, Error, LoginManager, RequestSettings};
+use super::{
+	auth::AuthCallback, default_middlewares, limit::RequestLimiter, Client, Error, HttpTransport,
+	LoginManager, Middleware, RequestSettings, ReqwestTransport, ResponseCache,
+};
 use crate::version::{DefaultVersion, FhirVersion};
 
 /// Default user agent of this client.
@@ -16,13 +19,34 @@ pub struct ClientBuilder<Version = DefaultVersion, ACB = ()> {
 	base_url: Option<Url>,
 	/// Reqwest Client
 	client: Option<reqwest::Client>,
+	/// HTTP transport sending the requests.
+	transport: Option<Arc<dyn HttpTransport>>,
 	/// User agent to use for requests.
 	user_agent: Option<String>,
+	/// Client certificate for mutual TLS.
+	identity: Option<reqwest::Identity>,
+	/// Additionally trusted root certificates.
+	root_certificates: Vec<reqwest::Certificate>,
+	/// Minimum TLS version.
+	min_tls_version: Option<reqwest::tls::Version>,
+	/// Maximum TLS version.
+	max_tls_version: Option<reqwest::tls::Version>,
 	/// Request settings.
 	request_settings: Option<RequestSettings>,
 	/// Auth callback.
 	auth_callback: Option<ACB>,
+	/// Cache for read responses.
+	cache: Option<Arc<dyn ResponseCache>>,
+	/// Rate limit as requests per second and burst.
+	rate_limit: Option<(u32, u32)>,
+	/// Maximum number of requests in flight.
+	max_in_flight: Option<usize>,
+	/// Middlewares wrapping every request.
+	middlewares: Vec<Arc<dyn Middleware>>,
 
+	/// Whether to check requests against the server's capabilities before
+	/// sending them.
+	check_capabilities: bool,
 	/// Whether to error if the server responds with a different major FHIR
 	/// version.
 	error_on_version_mismatch: bool,
@@ -31,6 +55,9 @@ pub struct ClientBuilder<Version = DefaultVersion, ACB = ()> {
 	/// Reasoning is to avoid search results and references to resources on other
 	/// servers when this is not wanted.
 	error_on_origin_mismatch: bool,
+	/// Whether to additionally error before sending a request outside of the
+	/// base URL's path on the same origin.
+	error_on_path_mismatch: bool,
 
 	/// FHIR version.
 	version: PhantomData<Version>,
@@ -44,11 +71,22 @@ where
 		Self {
 			base_url: None,
 			client: None,
+			transport: None,
 			user_agent: None,
+			identity: None,
+			root_certificates: Vec::new(),
+			min_tls_version: None,
+			max_tls_version: None,
 			request_settings: None,
 			auth_callback: None,
+			cache: None,
+			rate_limit: None,
+			max_in_flight: None,
+			middlewares: default_middlewares(),
+			check_capabilities: false,
 			error_on_version_mismatch: true,
 			error_on_origin_mismatch: true,
+			error_on_path_mismatch: false,
 			version: PhantomData,
 		}
 	}
@@ -65,21 +103,66 @@ where
 		self
 	}
 
-	/// Reqwest client
+	/// Reqwest client, used to build requests and, unless a custom transport
+	/// is set, to send them. Building fails if settings of the Reqwest client
+	/// are set as well, i.e. the user agent and the TLS settings.
 	#[must_use]
 	pub fn client(mut self, client: reqwest::Client) -> Self {
 		self.client = Some(client);
 		self
 	}
 
-	/// User agent to use for requests. This is ignored if a Reqwest client is
-	/// passed to the builder using the client() method.
+	/// Send all requests via a custom HTTP transport instead of the Reqwest
+	/// client, see [HttpTransport]. Pass an `Arc` to keep access to the
+	/// transport.
+	#[must_use]
+	pub fn transport(mut self, transport: impl HttpTransport + 'static) -> Self {
+		self.transport = Some(Arc::new(transport));
+		self
+	}
+
+	/// User agent to use for requests. Cannot be combined with a Reqwest
+	/// client passed via [Self::client].
 	#[must_use]
 	pub fn user_agent(mut self, user_agent: String) -> Self {
 		self.user_agent = Some(user_agent);
 		self
 	}
 
+	/// Client certificate to authenticate with via mutual TLS, e.g. from
+	/// [reqwest::Identity::from_pkcs8_pem] or
+	/// [reqwest::Identity::from_pkcs12_der]. Like the other TLS settings, it
+	/// only applies to the default transport and cannot be combined with a
+	/// Reqwest client passed via [Self::client].
+	#[must_use]
+	pub fn identity(mut self, identity: reqwest::Identity) -> Self {
+		self.identity = Some(identity);
+		self
+	}
+
+	/// Trust an additional root certificate, e.g. of a private CA from
+	/// [reqwest::Certificate::from_pem]. The system's root certificates stay
+	/// trusted.
+	#[must_use]
+	pub fn add_root_certificate(mut self, certificate: reqwest::Certificate) -> Self {
+		self.root_certificates.push(certificate);
+		self
+	}
+
+	/// Minimum TLS version to connect with.
+	#[must_use]
+	pub const fn min_tls_version(mut self, version: reqwest::tls::Version) -> Self {
+		self.min_tls_version = Some(version);
+		self
+	}
+
+	/// Maximum TLS version to connect with.
+	#[must_use]
+	pub const fn max_tls_version(mut self, version: reqwest::tls::Version) -> Self {
+		self.max_tls_version = Some(version);
+		self
+	}
+
 	/// Request settings.
 	#[must_use]
 	pub fn request_settings(mut self, settings: RequestSettings) -> Self {
@@ -108,17 +191,29 @@ where
 		ClientBuilder {
 			base_url: self.base_url,
 			client: self.client,
+			transport: self.transport,
 			user_agent: self.user_agent,
+			identity: self.identity,
+			root_certificates: self.root_certificates,
+			min_tls_version: self.min_tls_version,
+			max_tls_version: self.max_tls_version,
 			request_settings: self.request_settings,
 			auth_callback: Some(login_manager),
+			cache: self.cache,
+			rate_limit: self.rate_limit,
+			max_in_flight: self.max_in_flight,
+			middlewares: self.middlewares,
 			version: self.version,
+			check_capabilities: self.check_capabilities,
 			error_on_version_mismatch: self.error_on_version_mismatch,
 			error_on_origin_mismatch: self.error_on_origin_mismatch,
+			error_on_path_mismatch: self.error_on_path_mismatch,
 		}
 	}
 
 	/// Disable errors if the server responds with a different major FHIR
-	/// version.
+	/// version. The check is done by the [VersionCheck](super::VersionCheck) middleware, so
+	/// removing it from the chain disables the check as well.
 	#[must_use]
 	pub const fn allow_version_mismatch(mut self) -> Self {
 		self.error_on_version_mismatch = false;
@@ -128,18 +223,150 @@ where
 	/// Disable errors blocking to send a request to a different server than is
 	/// configured in the base URL. Also not applies to custom requests!
 	/// Reasoning is to avoid search results and references to resources on other
-	/// servers when this is not wanted.
+	/// servers when this is not wanted. The check is done by the [OriginCheck](super::OriginCheck)
+	/// middleware, so removing it from the chain disables the check as well.
 	#[must_use]
 	pub const fn allow_origin_mismatch(mut self) -> Self {
 		self.error_on_origin_mismatch = false;
 		self
 	}
 
+	/// Additionally reject requests to paths outside of the base URL's path on
+	/// the same origin, e.g. to other tenants of a server hosting one FHIR
+	/// endpoint per tenant. Like the origin check, it is done by the
+	/// [OriginCheck](super::OriginCheck) middleware and disabled by
+	/// [Self::allow_origin_mismatch].
+	#[must_use]
+	pub const fn restrict_to_base_path(mut self) -> Self {
+		self.error_on_path_mismatch = true;
+		self
+	}
+
+	/// Make the client capability-aware: Before sending a request, check that
+	/// the server supports its interaction or operation according to its
+	/// CapabilityStatement, failing with [Error::Unsupported] otherwise. The
+	/// capabilities are fetched on the first request and cached, see
+	/// [Client::server_capabilities]. Search parameters and requests to other
+	/// servers are not checked.
+	#[must_use]
+	pub const fn check_capabilities(mut self) -> Self {
+		self.check_capabilities = true;
+		self
+	}
+
+	/// Cache read responses and revalidate them via `If-None-Match` and
+	/// `If-Modified-Since`, see [ResponseCache]. Use e.g. a
+	/// [MemoryCache](super::MemoryCache). Pass an `Arc` to keep access to the
+	/// cache.
+	#[must_use]
+	pub fn cache(mut self, cache: impl ResponseCache + 'static) -> Self {
+		self.cache = Some(Arc::new(cache));
+		self
+	}
+
+	/// Limit the rate of requests to `per_second` requests per second on
+	/// average, allowing bursts of up to `burst` requests (token bucket).
+	/// Requests exceeding the limit wait before they are sent. The limit is
+	/// shared across clones of the client, see [Client::limit_metrics] for the
+	/// queueing delay. Retries of a request are not limited separately.
+	#[must_use]
+	pub const fn rate_limit(mut self, per_second: u32, burst: u32) -> Self {
+		self.rate_limit = Some((per_second, burst));
+		self
+	}
+
+	/// Limit the number of requests in flight at the same time. Further
+	/// requests wait until previous ones got their response. The limit is
+	/// shared across clones of the client.
+	#[must_use]
+	pub const fn max_concurrent_requests(mut self, max: usize) -> Self {
+		self.max_in_flight = Some(max);
+		self
+	}
+
+	/// Add a middleware wrapping every request, after the previously added
+	/// ones, see [Middleware]. By default, the chain consists of the
+	/// [default_middlewares].
+	#[must_use]
+	pub fn middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+		self.middlewares.push(Arc::new(middleware));
+		self
+	}
+
+	/// Replace the whole middleware chain, e.g. to reorder or disable the
+	/// built-in middlewares of [default_middlewares]. The first middleware is
+	/// the outermost one.
+	#[must_use]
+	pub fn middlewares(mut self, middlewares: Vec<Arc<dyn Middleware>>) -> Self {
+		self.middlewares = middlewares;
+		self
+	}
+
+	/// Get the configured Reqwest client or build a new one with the user
+	/// agent and TLS settings.
+	fn http_client(&self) -> Result<reqwest::Client, Error> {
+		match &self.client {
+			Some(client) => {
+				if self.user_agent.is_some() {
+					return Err(Error::BuilderConflict("user_agent"));
+				}
+				if self.identity.is_some() {
+					return Err(Error::BuilderConflict("identity"));
+				}
+				if !self.root_certificates.is_empty() {
+					return Err(Error::BuilderConflict("root_certificates"));
+				}
+				if self.min_tls_version.is_some() || self.max_tls_version.is_some() {
+					return Err(Error::BuilderConflict("tls_version"));
+				}
+				Ok(client.clone())
+			}
+			None => {
+				let user_agent = self.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT);
+				let mut builder = reqwest::Client::builder().user_agent(user_agent);
+				if let Some(identity) = &self.identity {
+					builder = builder.identity(identity.clone());
+				}
+				for certificate in &self.root_certificates {
+					builder = builder.add_root_certificate(certificate.clone());
+				}
+				if let Some(version) = self.min_tls_version {
+					builder = builder.min_tls_version(version);
+				}
+				if let Some(version) = self.max_tls_version {
+					builder = builder.max_tls_version(version);
+				}
+				Ok(builder.build()?)
+			}
+		}
+	}
+
+	/// Prepare the builder as template for the clients of multiple tenants.
+	/// The Reqwest client is built, so that the connection pool is shared, and
+	/// the cache is removed, so that responses are not shared between tenants.
+	pub(crate) fn into_tenant_template(mut self) -> Result<Self, Error> {
+		self.client = Some(self.http_client()?);
+		// Already applied to the built client.
+		self.user_agent = None;
+		self.identity = None;
+		self.root_certificates.clear();
+		self.min_tls_version = None;
+		self.max_tls_version = None;
+		self.cache = None;
+		Ok(self)
+	}
+
+	/// Get the request settings that would be used.
+	pub(crate) fn get_request_settings(&self) -> RequestSettings {
+		self.request_settings.clone().unwrap_or_default()
+	}
+
 	/// Finalize building the client.
 	pub fn build(self) -> Result<Client<V>, Error>
 	where
 		ACB: LoginManager + 'static,
 	{
+		let client = self.http_client()?;
 		let Some(base_url) = self.base_url else {
 			return Err(Error::BuilderMissingField("base_url"));
 		};
@@ -147,23 +374,24 @@ where
 			return Err(Error::UrlCannotBeBase);
 		}
 
-		let client = match self.client {
-			Some(client) => client,
-			None => {
-				let user_agent = self.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT);
-				reqwest::Client::builder().user_agent(user_agent).build()?
-			}
-		};
-
+		let transport =
+			self.transport.unwrap_or_else(|| Arc::new(ReqwestTransport::new(client.clone())));
 		let request_settings = self.request_settings.unwrap_or_default();
 
 		let data = super::ClientData {
 			base_url,
 			client,
+			transport,
 			request_settings: std::sync::Mutex::new(request_settings),
 			auth_callback: tokio::sync::Mutex::new(self.auth_callback.map(AuthCallback::new)),
+			cache: self.cache,
+			limiter: RequestLimiter::new(self.rate_limit, self.max_in_flight),
+			middlewares: self.middlewares,
+			capabilities: tokio::sync::Mutex::new(None),
+			check_capabilities: self.check_capabilities,
 			error_on_version_mismatch: self.error_on_version_mismatch,
 			error_on_origin_mismatch: self.error_on_origin_mismatch,
+			error_on_path_mismatch: self.error_on_path_mismatch,
 		};
 		Ok(Client::from(data))
 	}
@@ -177,12 +405,23 @@ where
 		Self {
 			base_url: self.base_url.clone(),
 			client: self.client.clone(),
+			transport: self.transport.clone(),
 			user_agent: self.user_agent.clone(),
+			identity: self.identity.clone(),
+			root_certificates: self.root_certificates.clone(),
+			min_tls_version: self.min_tls_version,
+			max_tls_version: self.max_tls_version,
 			request_settings: self.request_settings.clone(),
 			auth_callback: self.auth_callback.clone(),
+			cache: self.cache.clone(),
+			rate_limit: self.rate_limit,
+			max_in_flight: self.max_in_flight,
+			middlewares: self.middlewares.clone(),
 			version: self.version,
+			check_capabilities: self.check_capabilities,
 			error_on_version_mismatch: self.error_on_version_mismatch,
 			error_on_origin_mismatch: self.error_on_origin_mismatch,
+			error_on_path_mismatch: self.error_on_path_mismatch,
 		}
 	}
 }
@@ -192,11 +431,22 @@ impl<V, ACB> std::fmt::Debug for ClientBuilder<V, ACB> {
 		f.debug_struct("ClientBuilder")
 			.field("base_url", &self.base_url)
 			.field("client", &self.client)
+			.field("transport", &self.transport.as_ref().map(|_| "<transport>"))
 			.field("user_agent", &self.user_agent)
+			.field("identity", &self.identity.as_ref().map(|_| "<identity>"))
+			.field("root_certificates", &self.root_certificates.len())
+			.field("min_tls_version", &self.min_tls_version)
+			.field("max_tls_version", &self.max_tls_version)
 			.field("request_settings", &self.request_settings)
 			.field("auth_callback", &self.auth_callback.as_ref().map(|_| "<login_manager>"))
+			.field("cache", &self.cache.as_ref().map(|_| "<cache>"))
+			.field("rate_limit", &self.rate_limit)
+			.field("max_in_flight", &self.max_in_flight)
+			.field("middlewares", &self.middlewares.len())
+			.field("check_capabilities", &self.check_capabilities)
 			.field("error_on_version_mismatch", &self.error_on_version_mismatch)
 			.field("error_on_origin_mismatch", &self.error_on_origin_mismatch)
+			.field("error_on_path_mismatch", &self.error_on_path_mismatch)
 			.field("version", &std::any::type_name::<V>())
 			.finish()
 	}
@@ -0,0 +1,167 @@
+//! Response caching for read requests.
+
+use std::{
+	collections::{BTreeMap, HashMap},
+	sync::{Arc, Mutex},
+};
+
+use reqwest::header::HeaderValue;
+
+/// A cached response body together with the validators to revalidate it with
+/// the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedResponse {
+	/// Raw response body.
+	pub body: String,
+	/// `ETag` header of the response, sent as `If-None-Match` on revalidation.
+	pub etag: Option<HeaderValue>,
+	/// `Last-Modified` header of the response, sent as `If-Modified-Since` on
+	/// revalidation.
+	pub last_modified: Option<HeaderValue>,
+}
+
+/// Cache for responses of read requests, keyed by the FHIR version and the
+/// request URL, e.g. `4.3.0 https://server/fhir/Patient/1`. Set it via
+/// [`ClientBuilder::cache`](super::ClientBuilder::cache).
+///
+/// Cached responses are always revalidated with the server, so a `304 Not
+/// Modified` response saves the client from downloading the resource again.
+/// The cached body is parsed again for every read. Entries are removed after
+/// the same client successfully updated, patched or deleted the resource.
+pub trait ResponseCache: Send + Sync {
+	/// Get the cached response for the key, if there is one.
+	fn get(&self, key: &str) -> Option<CachedResponse>;
+	/// Insert or replace the cached response for the key.
+	fn insert(&self, key: String, response: CachedResponse);
+	/// Remove the cached response for the key.
+	fn remove(&self, key: &str);
+	/// Remove all cached responses.
+	fn clear(&self);
+}
+
+impl<C: ResponseCache + ?Sized> ResponseCache for Arc<C> {
+	fn get(&self, key: &str) -> Option<CachedResponse> {
+		(**self).get(key)
+	}
+
+	fn insert(&self, key: String, response: CachedResponse) {
+		(**self).insert(key, response);
+	}
+
+	fn remove(&self, key: &str) {
+		(**self).remove(key);
+	}
+
+	fn clear(&self) {
+		(**self).clear();
+	}
+}
+
+/// In-memory [ResponseCache], evicting the least recently used entry once the
+/// capacity is reached.
+#[derive(Debug)]
+pub struct MemoryCache {
+	/// Maximum number of entries.
+	capacity: usize,
+	/// Cache content.
+	inner: Mutex<MemoryCacheInner>,
+}
+
+/// Content of the [MemoryCache].
+#[derive(Debug, Default)]
+struct MemoryCacheInner {
+	/// Counter to order the entries by last usage.
+	tick: u64,
+	/// Cached responses with their last usage.
+	entries: HashMap<String, (u64, CachedResponse)>,
+	/// Keys ordered by last usage.
+	usage: BTreeMap<u64, String>,
+}
+
+impl MemoryCache {
+	/// Create a new cache holding up to `capacity` responses.
+	#[must_use]
+	pub fn new(capacity: usize) -> Self {
+		Self { capacity, inner: Mutex::new(MemoryCacheInner::default()) }
+	}
+
+	/// Lock the inner cache content.
+	fn lock(&self) -> std::sync::MutexGuard<'_, MemoryCacheInner> {
+		#[allow(clippy::expect_used)] // only happens on panics, so we can panic again.
+		self.inner.lock().expect("mutex poisened")
+	}
+}
+
+impl ResponseCache for MemoryCache {
+	fn get(&self, key: &str) -> Option<CachedResponse> {
+		let mut inner = self.lock();
+		inner.tick += 1;
+		let tick = inner.tick;
+		let (last_used, response) = inner.entries.get_mut(key)?;
+		let previous = std::mem::replace(last_used, tick);
+		let response = response.clone();
+		inner.usage.remove(&previous);
+		inner.usage.insert(tick, key.to_owned());
+		Some(response)
+	}
+
+	fn insert(&self, key: String, response: CachedResponse) {
+		let mut inner = self.lock();
+		inner.tick += 1;
+		let tick = inner.tick;
+		if let Some((previous, _)) = inner.entries.insert(key.clone(), (tick, response)) {
+			inner.usage.remove(&previous);
+		}
+		inner.usage.insert(tick, key);
+
+		while inner.entries.len() > self.capacity {
+			let Some((_, key)) = inner.usage.pop_first() else { break };
+			inner.entries.remove(&key);
+		}
+	}
+
+	fn remove(&self, key: &str) {
+		let mut inner = self.lock();
+		if let Some((last_used, _)) = inner.entries.remove(key) {
+			inner.usage.remove(&last_used);
+		}
+	}
+
+	fn clear(&self) {
+		let mut inner = self.lock();
+		inner.entries.clear();
+		inner.usage.clear();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn response(body: &str) -> CachedResponse {
+		CachedResponse {
+			body: body.to_owned(),
+			etag: Some(HeaderValue::from_static("W/\"1\"")),
+			last_modified: None,
+		}
+	}
+
+	#[test]
+	fn memory_cache_evicts_least_recently_used() {
+		let cache = MemoryCache::new(2);
+
+		cache.insert("a".to_owned(), response("a"));
+		cache.insert("b".to_owned(), response("b"));
+		assert_eq!(cache.get("a"), Some(response("a")));
+
+		cache.insert("c".to_owned(), response("c"));
+		assert_eq!(cache.get("b"), None);
+		assert_eq!(cache.get("a"), Some(response("a")));
+		assert_eq!(cache.get("c"), Some(response("c")));
+
+		cache.remove("a");
+		assert_eq!(cache.get("a"), None);
+		cache.clear();
+		assert_eq!(cache.get("c"), None);
+	}
+}
@@ -0,0 +1,368 @@
+//! Capability-aware mode, checking requests against the server's
+//! CapabilityStatement.
+
+use std::{
+	collections::{HashMap, HashSet},
+	sync::Arc,
+};
+
+use reqwest::{header, Method, Url};
+use serde::Serialize;
+use serde_json::Value;
+
+use super::{Client, Error};
+use crate::version::FhirVersion;
+
+/// RESTful interaction of the FHIR API, as listed in the CapabilityStatement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Interaction {
+	/// Read the current version of a resource.
+	Read,
+	/// Read a specific version of a resource.
+	Vread,
+	/// Update a resource.
+	Update,
+	/// Patch a resource.
+	Patch,
+	/// Delete a resource.
+	Delete,
+	/// History of a resource instance.
+	HistoryInstance,
+	/// History of all resources of a type.
+	HistoryType,
+	/// Create a resource.
+	Create,
+	/// Search resources of a type.
+	SearchType,
+	/// Transaction bundle, system-level.
+	Transaction,
+	/// Batch bundle, system-level.
+	Batch,
+	/// Search across all resource types, system-level.
+	SearchSystem,
+	/// History of all resources, system-level.
+	HistorySystem,
+}
+
+impl Interaction {
+	/// Code of the interaction in the CapabilityStatement.
+	#[must_use]
+	pub const fn code(self) -> &'static str {
+		match self {
+			Self::Read => "read",
+			Self::Vread => "vread",
+			Self::Update => "update",
+			Self::Patch => "patch",
+			Self::Delete => "delete",
+			Self::HistoryInstance => "history-instance",
+			Self::HistoryType => "history-type",
+			Self::Create => "create",
+			Self::SearchType => "search-type",
+			Self::Transaction => "transaction",
+			Self::Batch => "batch",
+			Self::SearchSystem => "search-system",
+			Self::HistorySystem => "history-system",
+		}
+	}
+
+	/// Whether the interaction is on the whole system instead of a resource
+	/// type.
+	#[must_use]
+	pub const fn is_system(self) -> bool {
+		matches!(self, Self::Transaction | Self::Batch | Self::SearchSystem | Self::HistorySystem)
+	}
+}
+
+/// Capabilities of a resource type.
+#[derive(Debug, Clone, Default)]
+struct ResourceCapabilities {
+	/// Codes of the supported interactions.
+	interactions: HashSet<String>,
+	/// Names of the supported search parameters.
+	search_params: Vec<String>,
+	/// Names of the supported operations, without `$`.
+	operations: HashSet<String>,
+}
+
+/// Capabilities of the server, extracted from the `server` REST entries of its
+/// CapabilityStatement. Retrieve them via [Client::server_capabilities].
+#[derive(Debug, Clone, Default)]
+pub struct ServerCapabilities {
+	/// Capabilities by resource type.
+	resources: HashMap<String, ResourceCapabilities>,
+	/// Codes of the supported system-level interactions.
+	interactions: HashSet<String>,
+	/// Names of the supported system-level operations, without `$`.
+	operations: HashSet<String>,
+}
+
+impl ServerCapabilities {
+	/// Extract the capabilities from a CapabilityStatement of any FHIR
+	/// version, e.g. the result of [Client::capabilities].
+	pub fn from_statement(statement: &impl Serialize) -> Result<Self, Error> {
+		let statement = serde_json::to_value(statement)?;
+		let mut capabilities = Self::default();
+
+		let rests = list(&statement, "rest")
+			.filter(|rest| rest.get("mode").and_then(Value::as_str) == Some("server"));
+		for rest in rests {
+			capabilities.interactions.extend(names(rest, "interaction", "code"));
+			capabilities.operations.extend(names(rest, "operation", "name").map(operation_name));
+
+			for resource in list(rest, "resource") {
+				let Some(resource_type) = resource.get("type").and_then(Value::as_str) else {
+					continue;
+				};
+				let entry = capabilities.resources.entry(resource_type.to_owned()).or_default();
+				entry.interactions.extend(names(resource, "interaction", "code"));
+				entry.search_params.extend(names(resource, "searchParam", "name"));
+				entry.operations.extend(names(resource, "operation", "name").map(operation_name));
+			}
+		}
+
+		Ok(capabilities)
+	}
+
+	/// Whether the server supports the resource type at all.
+	#[must_use]
+	pub fn supports_resource(&self, resource_type: impl AsRef<str>) -> bool {
+		self.resources.contains_key(resource_type.as_ref())
+	}
+
+	/// Whether the server supports the interaction on the resource type.
+	/// System-level interactions (see [Interaction::is_system]) are checked
+	/// independently of the resource type.
+	#[must_use]
+	pub fn supports_interaction(
+		&self,
+		resource_type: impl AsRef<str>,
+		interaction: Interaction,
+	) -> bool {
+		if interaction.is_system() {
+			self.interactions.contains(interaction.code())
+		} else {
+			self.resources
+				.get(resource_type.as_ref())
+				.is_some_and(|resource| resource.interactions.contains(interaction.code()))
+		}
+	}
+
+	/// Names of the search parameters the server supports for the resource
+	/// type. Common parameters like `_id` are often not listed.
+	#[must_use]
+	pub fn supported_search_params(&self, resource_type: impl AsRef<str>) -> &[String] {
+		self.resources
+			.get(resource_type.as_ref())
+			.map_or(&[], |resource| resource.search_params.as_slice())
+	}
+
+	/// Whether the server supports the operation on the system or on any
+	/// resource type. The name can be given with or without `$`, e.g.
+	/// `$everything`.
+	#[must_use]
+	pub fn supports_operation(&self, name: &str) -> bool {
+		let name = name.trim_start_matches('$');
+		self.operations.contains(name)
+			|| self.resources.values().any(|resource| resource.operations.contains(name))
+	}
+
+	/// Whether the server supports the operation on the resource type or its
+	/// instances. The name can be given with or without `$`.
+	#[must_use]
+	pub fn supports_resource_operation(&self, resource_type: impl AsRef<str>, name: &str) -> bool {
+		let name = name.trim_start_matches('$');
+		self.resources
+			.get(resource_type.as_ref())
+			.is_some_and(|resource| resource.operations.contains(name))
+	}
+
+	/// Whether the server supports the operation on the system level. The name
+	/// can be given with or without `$`.
+	#[must_use]
+	pub fn supports_system_operation(&self, name: &str) -> bool {
+		self.operations.contains(name.trim_start_matches('$'))
+	}
+
+	/// Check that the server supports what the request requires.
+	fn check(&self, required: &Required) -> Result<(), Error> {
+		match required {
+			Required::Interaction(resource_type, interaction) => {
+				let resource_type = resource_type.as_deref().unwrap_or_default();
+				if self.supports_interaction(resource_type, *interaction) {
+					Ok(())
+				} else if interaction.is_system() {
+					Err(Error::Unsupported(format!(
+						"interaction `{}` by the server",
+						interaction.code()
+					)))
+				} else {
+					Err(Error::Unsupported(format!(
+						"interaction `{}` on `{resource_type}` by the server",
+						interaction.code()
+					)))
+				}
+			}
+			Required::Operation(Some(resource_type), name) => {
+				if self.supports_resource_operation(resource_type, name) {
+					Ok(())
+				} else {
+					Err(Error::Unsupported(format!(
+						"operation `${name}` on `{resource_type}` by the server"
+					)))
+				}
+			}
+			Required::Operation(None, name) => {
+				if self.supports_system_operation(name) {
+					Ok(())
+				} else {
+					Err(Error::Unsupported(format!("operation `${name}` by the server")))
+				}
+			}
+		}
+	}
+}
+
+/// Iterate the elements of the list field of the JSON object.
+fn list<'a>(value: &'a Value, field: &str) -> impl Iterator<Item = &'a Value> {
+	value.get(field).and_then(Value::as_array).into_iter().flatten()
+}
+
+/// Iterate the string field of the elements of the list field of the JSON
+/// object.
+fn names<'a>(value: &'a Value, field: &str, name: &'a str) -> impl Iterator<Item = String> + 'a {
+	list(value, field).filter_map(move |element| element.get(name)?.as_str().map(str::to_owned))
+}
+
+/// Normalize the operation name to not include `$`.
+fn operation_name(name: String) -> String {
+	name.trim_start_matches('$').to_owned()
+}
+
+/// Capability a request requires from the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Required {
+	/// Interaction on the resource type or, if none, the system.
+	Interaction(Option<String>, Interaction),
+	/// Operation on the resource type or, if none, the system, without `$`.
+	Operation(Option<String>, String),
+}
+
+impl Required {
+	/// Classify the request relative to the base URL. Returns `None` for
+	/// requests to other servers, capability requests, paging links and
+	/// unknown requests, which are not checked.
+	fn from_request(request: &reqwest::Request, base_url: &Url) -> Option<Self> {
+		let url = request.url();
+		if url.origin() != base_url.origin() {
+			return None;
+		}
+		let base_path = base_url.path().trim_end_matches('/');
+		let path = url.path().strip_prefix(base_path)?;
+		let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+
+		let resource_type = segments
+			.first()
+			.filter(|segment| segment.starts_with(|c: char| c.is_ascii_uppercase()))
+			.map(|segment| (*segment).to_owned());
+
+		if let Some(name) = segments.iter().find_map(|segment| segment.strip_prefix('$')) {
+			return Some(Self::Operation(resource_type, name.to_owned()));
+		}
+
+		let interaction = match (request.method(), segments.as_slice()) {
+			(&Method::GET, ["_history"]) => Interaction::HistorySystem,
+			// Paging links of servers like HAPI point to the base URL with a
+			// continuation token, e.g. `?_getpages=...`.
+			(&Method::GET, []) if is_paging_link(url) => return None,
+			(&Method::GET, []) | (&Method::POST, ["_search"]) => Interaction::SearchSystem,
+			(&Method::POST, []) if is_transaction(request) => Interaction::Transaction,
+			(&Method::POST, []) => Interaction::Batch,
+			(&Method::GET, [_, _, "_history", _]) => Interaction::Vread,
+			(&Method::GET, [_, _, "_history"]) => Interaction::HistoryInstance,
+			(&Method::GET, [_, "_history"]) => Interaction::HistoryType,
+			(&Method::GET, [_]) | (&Method::POST, [_, "_search"]) => Interaction::SearchType,
+			(&Method::GET, [_, _]) => Interaction::Read,
+			(&Method::POST, [_]) => Interaction::Create,
+			(&Method::PUT, [_] | [_, _]) => Interaction::Update,
+			(&Method::PATCH, [_] | [_, _]) => Interaction::Patch,
+			(&Method::DELETE, [_] | [_, _]) => Interaction::Delete,
+			_ => return None,
+		};
+		if interaction.is_system() {
+			return Some(Self::Interaction(None, interaction));
+		}
+
+		resource_type.map(|resource_type| Self::Interaction(Some(resource_type), interaction))
+	}
+}
+
+/// Query parameters of continuation tokens in paging links.
+const PAGING_PARAMS: &[&str] = &["_getpages", "_page_token"];
+
+/// Whether the URL is a paging link with a continuation token.
+fn is_paging_link(url: &Url) -> bool {
+	url.query_pairs().any(|(key, _)| PAGING_PARAMS.contains(&key.as_ref()))
+}
+
+/// Whether the request body is a transaction bundle instead of a batch bundle.
+fn is_transaction(request: &reqwest::Request) -> bool {
+	request
+		.body()
+		.and_then(reqwest::Body::as_bytes)
+		.and_then(|body| serde_json::from_slice::<Value>(body).ok())
+		.is_some_and(|bundle| bundle.get("type").and_then(Value::as_str) == Some("transaction"))
+}
+
+impl<V: FhirVersion> Client<V> {
+	/// Get the server's capabilities, fetching its CapabilityStatement on first
+	/// use. They are cached for the lifetime of the client, see
+	/// [Self::refresh_capabilities].
+	/// Concurrent first requests wait for a single fetch.
+	pub async fn server_capabilities(&self) -> Result<Arc<ServerCapabilities>, Error> {
+		let mut cached = self.0.capabilities.lock().await;
+		if let Some(capabilities) = &*cached {
+			return Ok(capabilities.clone());
+		}
+		let capabilities = self.fetch_capabilities().await?;
+		*cached = Some(capabilities.clone());
+		Ok(capabilities)
+	}
+
+	/// Fetch the server's CapabilityStatement and replace the cached
+	/// capabilities. Call it right after building the client to load the
+	/// capabilities at startup.
+	pub async fn refresh_capabilities(&self) -> Result<Arc<ServerCapabilities>, Error> {
+		let mut cached = self.0.capabilities.lock().await;
+		let capabilities = self.fetch_capabilities().await?;
+		*cached = Some(capabilities.clone());
+		Ok(capabilities)
+	}
+
+	/// Fetch the server's CapabilityStatement.
+	async fn fetch_capabilities(&self) -> Result<Arc<ServerCapabilities>, Error> {
+		tracing::debug!("Fetching server capabilities");
+		let url = self.url(&["metadata"]);
+		let request = self.0.client.get(url).header(header::ACCEPT, V::MIME_TYPE).build()?;
+
+		// Not via `run_request`, as that checks the capabilities.
+		let response = self.send_request(request).await?;
+		if !response.status().is_success() {
+			return Err(Error::from_response::<V>(response).await);
+		}
+		let statement: V::CapabilityStatement = response.json().await?;
+		Ok(Arc::new(ServerCapabilities::from_statement(&statement)?))
+	}
+
+	/// Fail if the client is capability-aware and the server does not support
+	/// the request, see
+	/// [ClientBuilder::check_capabilities](super::ClientBuilder::check_capabilities).
+	pub(crate) async fn check_capabilities(&self, request: &reqwest::Request) -> Result<(), Error> {
+		if !self.0.check_capabilities {
+			return Ok(());
+		}
+		let Some(required) = Required::from_request(request, self.base_url()) else {
+			return Ok(());
+		};
+		self.server_capabilities().await?.check(&required)
+	}
+}
@@ -0,0 +1,82 @@
+//! Checkpoint persistence for incremental synchronization.
+
+use std::{
+	collections::BTreeSet,
+	error::Error,
+	sync::{Arc, Mutex},
+};
+
+use fhir_model::Instant;
+use serde::{Deserialize, Serialize};
+
+/// Position of an incremental synchronization, see
+/// [`Client::changes_since`](super::Client::changes_since).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncCheckpoint {
+	/// Latest `meta.lastUpdated` of the received resources. The next poll
+	/// requests all changes since this instant.
+	pub since: Option<Instant>,
+	/// Resource versions as `Type/id/_history/versionId` that were already
+	/// received, but will be returned again by the next poll, because they
+	/// were updated exactly at [Self::since] or their time is unknown
+	/// (deletions).
+	pub seen: BTreeSet<String>,
+}
+
+/// Storage for the [SyncCheckpoint] of an incremental synchronization, so it
+/// can resume where it stopped.
+///
+/// The checkpoint is saved once all change events of the fetched pages were
+/// consumed from the stream, i.e. before the next page is fetched or the next
+/// poll starts.
+pub trait CheckpointStore: Send + Sync {
+	/// Load the stored checkpoint, if there is one.
+	fn load(&self) -> Option<SyncCheckpoint>;
+	/// Store the checkpoint, replacing the previous one. A failure is yielded
+	/// from the stream and saving is tried again before any further pages are
+	/// fetched.
+	fn save(&self, checkpoint: &SyncCheckpoint) -> Result<(), Box<dyn Error + Send + Sync>>;
+}
+
+impl<S: CheckpointStore + ?Sized> CheckpointStore for Arc<S> {
+	fn load(&self) -> Option<SyncCheckpoint> {
+		(**self).load()
+	}
+
+	fn save(&self, checkpoint: &SyncCheckpoint) -> Result<(), Box<dyn Error + Send + Sync>> {
+		(**self).save(checkpoint)
+	}
+}
+
+/// In-memory [CheckpointStore], e.g. for tests or to inspect the checkpoint.
+#[derive(Debug, Default)]
+pub struct MemoryCheckpointStore {
+	/// The stored checkpoint.
+	checkpoint: Mutex<Option<SyncCheckpoint>>,
+}
+
+impl MemoryCheckpointStore {
+	/// Create a new empty store.
+	#[must_use]
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Lock the stored checkpoint.
+	fn lock(&self) -> std::sync::MutexGuard<'_, Option<SyncCheckpoint>> {
+		#[allow(clippy::expect_used)] // only happens on panics, so we can panic again.
+		self.checkpoint.lock().expect("mutex poisened")
+	}
+}
+
+impl CheckpointStore for MemoryCheckpointStore {
+	fn load(&self) -> Option<SyncCheckpoint> {
+		self.lock().clone()
+	}
+
+	fn save(&self, checkpoint: &SyncCheckpoint) -> Result<(), Box<dyn Error + Send + Sync>> {
+		*self.lock() = Some(checkpoint.clone());
+		Ok(())
+	}
+}
@@ -18,6 +18,10 @@ pub enum Error {
 	#[error("Builder is missing field `{0}` to construct the client")]
 	BuilderMissingField(&'static str),
 
+	/// Builder field cannot be combined with a custom Reqwest client.
+	#[error("Builder field `{0}` cannot be combined with a custom Reqwest client")]
+	BuilderConflict(&'static str),
+
 	/// URL cannot be a base URL.
 	#[error("Given base URL cannot be a base URL")]
 	UrlCannotBeBase,
@@ -50,6 +54,10 @@ pub enum Error {
 	#[error("URLs with mismatching origins are disabled: {0}")]
 	DifferentOrigin(String),
 
+	/// Found URL outside of the base URL's path.
+	#[error("URLs outside of the base URL's path are disabled: {0}")]
+	DifferentBasePath(String),
+
 	/// Auth callback error.
 	#[error("Authorization callback error: {0}")]
 	AuthCallback(String),
@@ -58,6 +66,10 @@ pub enum Error {
 	#[error("JSON error: {0}")]
 	Json(#[from] serde_json::Error),
 
+	/// Generating or applying a patch failed.
+	#[error("Patch error: {0}")]
+	Patch(#[from] crate::patch::PatchError),
+
 	/// HTTP Request error.
 	#[error("Request error: {0}")]
 	Request(#[from] reqwest::Error),
@@ -104,6 +116,61 @@ pub enum Error {
 	/// Wrong resource was delivered.
 	#[error("Resource type {0} is not the requested type {1}")]
 	WrongResourceType(String, String),
+
+	/// Resources reference each other in a cycle.
+	#[error("Resources reference each other in a cycle: {0:?}")]
+	ReferenceCycle(Vec<String>),
+
+	/// Operation response is missing an expected parameter.
+	#[error("Missing parameter `{0}` in operation response")]
+	MissingParameter(&'static str),
+
+	/// Feature is not supported by the FHIR version, or interaction or
+	/// operation is not supported by the server according to its
+	/// CapabilityStatement.
+	#[error("Not supported: {0}")]
+	Unsupported(String),
+
+	/// Tenant is not known to the tenant client.
+	#[error("Unknown tenant: {0}")]
+	UnknownTenant(String),
+
+	/// Error of a custom HTTP transport.
+	#[error("HTTP transport error: {0}")]
+	Transport(#[source] Box<dyn std::error::Error + Send + Sync>),
+
+	/// Saving the checkpoint of an incremental synchronization failed.
+	#[error("Saving the synchronization checkpoint failed: {0}")]
+	Checkpoint(#[source] Box<dyn std::error::Error + Send + Sync>),
+
+	/// Request did not finish within the timeout.
+	#[error("Request timed out after {0:?}")]
+	Timeout(std::time::Duration),
+
+	#[cfg(feature = "websocket")]
+	/// WebSocket error.
+	#[error("WebSocket error: {0}")]
+	WebSocket(#[from] tokio_tungstenite::tungstenite::Error),
+
+	#[cfg(feature = "blocking")]
+	/// Failed to start the runtime of the blocking client.
+	#[error("Failed to start the blocking client's runtime: {0}")]
+	Runtime(#[source] std::io::Error),
+
+	#[cfg(feature = "smart")]
+	/// SMART App Launch authorization failed.
+	#[error("SMART App Launch error: {0}")]
+	Smart(String),
+
+	#[cfg(feature = "fixtures")]
+	/// Reading, writing or replaying a fixture file failed.
+	#[error("Fixture error: {0}")]
+	Fixture(String),
+
+	#[cfg(feature = "fixtures")]
+	/// No recorded fixture matches the request.
+	#[error("No recorded fixture matches the request: {0}")]
+	UnmatchedFixture(String),
 }
 
 impl Error {
@@ -113,6 +180,7 @@ impl Error {
 		tracing::debug!("Checking if error `{self}` should be retried");
 		match self {
 			Self::Request(err) => err.is_connect() || err.is_request() || err.is_timeout(),
+			Self::Transport(_) | Self::Timeout(_) => true,
 			_ => false,
 		}
 	}
@@ -1,6 +1,7 @@
 //! FHIR CRUD API interactions.
 
 use fhir_model::{ParsedReference, WrongResourceType};
+use futures::{future, Stream, TryStreamExt};
 use reqwest::{
 	header::{self, HeaderValue},
 	StatusCode, Url,
@@ -11,12 +12,15 @@ use super::{
 	misc,
 	paging::Page,
 	patch::{PatchViaFhir, PatchViaJson},
+	references::{check_reference_type, ReferenceResolver},
 	transaction::BatchTransaction,
-	Client, Error, SearchParameters,
+	upload::GraphUpload,
+	CachedResponse, Client, Error, SearchParameters,
 };
 use crate::{
+	changes::{DiffOptions, VersionChanges},
 	client::misc::make_uuid_header_value,
-	extensions::{AnyResource, GenericResource, ReferenceExt},
+	extensions::{AnyResource, ReferenceExt},
 	version::FhirVersion,
 };
 
@@ -39,22 +43,65 @@ where
 		}
 	}
 
-	/// Read any resource from any URL.
+	/// Read any resource from any URL. Uses the response cache if configured,
+	/// revalidating cached responses with the server. Paging links are read
+	/// without checking the capabilities, as the search itself was checked
+	/// already.
 	pub(crate) async fn read_generic<R: DeserializeOwned>(
 		&self,
 		url: Url,
 		correlation_id: Option<HeaderValue>,
+		check_capabilities: bool,
 	) -> Result<Option<R>, Error> {
+		let cache_key = self.cache_key(&url);
+		let cached = self.0.cache.as_ref().and_then(|cache| cache.get(&cache_key));
+
 		let mut request = self.0.client.get(url).header(header::ACCEPT, V::MIME_TYPE);
 		if let Some(correlation_id) = correlation_id {
 			request = request.header("X-Correlation-Id", correlation_id);
 		}
+		if let Some(cached) = &cached {
+			if let Some(etag) = &cached.etag {
+				request = request.header(header::IF_NONE_MATCH, etag.clone());
+			}
+			if let Some(last_modified) = &cached.last_modified {
+				request = request.header(header::IF_MODIFIED_SINCE, last_modified.clone());
+			}
+		}
+
+		let response = if check_capabilities {
+			self.run_request(request).await?
+		} else {
+			self.send_request(request.build()?).await?
+		};
+		if response.status() == StatusCode::NOT_MODIFIED {
+			if let Some(cached) = cached {
+				tracing::debug!("Cached response for {cache_key} is still valid");
+				let resource: R = serde_json::from_str(&cached.body)?;
+				return Ok(Some(resource));
+			}
+		}
 
-		let response = self.run_request(request).await?;
 		if response.status().is_success() {
+			if let Some(cache) = &self.0.cache {
+				let etag = response.headers().get(header::ETAG).cloned();
+				let last_modified = response.headers().get(header::LAST_MODIFIED).cloned();
+				if etag.is_some() || last_modified.is_some() {
+					let body = response.text().await?;
+					let resource: R = serde_json::from_str(&body)?;
+					cache.insert(cache_key, CachedResponse { body, etag, last_modified });
+					return Ok(Some(resource));
+				}
+				// The cached response cannot be revalidated anymore.
+				cache.remove(&cache_key);
+			}
+
 			let resource: R = response.json().await?;
 			Ok(Some(resource))
 		} else if [StatusCode::NOT_FOUND, StatusCode::GONE].contains(&response.status()) {
+			if let Some(cache) = &self.0.cache {
+				cache.remove(&cache_key);
+			}
 			Ok(None)
 		} else {
 			Err(Error::from_response::<V>(response).await)
@@ -67,7 +114,7 @@ where
 		id: &str,
 	) -> Result<Option<R>, Error> {
 		let url = self.url(&[R::TYPE_STR, id]);
-		self.read_generic(url, None).await
+		self.read_generic(url, None, true).await
 	}
 
 	/// Read a specific version of a specific FHIR resource.
@@ -77,12 +124,24 @@ where
 		version_id: &str,
 	) -> Result<Option<R>, Error> {
 		let url = self.url(&[R::TYPE_STR, id, "_history", version_id]);
-		self.read_generic(url, None).await
+		self.read_generic(url, None, true).await
 	}
 
-	/// Read the resource that is targeted in the reference.
+	/// Read the resource that is targeted in the reference. Use
+	/// [Self::reference_resolver] to resolve local references or references
+	/// within a bundle.
 	pub async fn read_referenced(&self, reference: &V::Reference) -> Result<V::Resource, Error> {
 		let parsed_reference = reference.parse().ok_or(Error::MissingReference)?;
+		let resource = self.fetch_referenced(parsed_reference).await?;
+		check_reference_type::<V>(reference, &resource)?;
+		Ok(resource)
+	}
+
+	/// Fetch the resource targeted by the parsed reference from the server.
+	pub(crate) async fn fetch_referenced(
+		&self,
+		parsed_reference: ParsedReference<'_>,
+	) -> Result<V::Resource, Error> {
 		let url = match parsed_reference {
 			ParsedReference::Local { .. } => return Err(Error::LocalReference),
 			ParsedReference::Relative { resource_type, id, version_id } => {
@@ -92,56 +151,77 @@ where
 					self.url(&[resource_type, id])
 				}
 			}
+			ParsedReference::Absolute { url, .. } if url.starts_with("urn:") => {
+				// Only valid within a bundle, not fetchable.
+				return Err(Error::ResourceNotFound(url.to_owned()));
+			}
 			ParsedReference::Absolute { url, .. } => {
 				url.parse().map_err(|_| Error::UrlParse(url.to_owned()))?
 			}
 		};
 
-		let resource: V::Resource = self
-			.read_generic(url.clone(), None)
+		self.read_generic(url.clone(), None, true)
 			.await?
-			.ok_or_else(|| Error::ResourceNotFound(url.to_string()))?;
-		if let Some(resource_type) = reference.r#type() {
-			if resource.resource_type_str() != resource_type {
-				return Err(Error::WrongResourceType(
-					resource.resource_type_str().to_owned(),
-					resource_type.to_owned(),
-				));
-			}
-		}
+			.ok_or_else(|| Error::ResourceNotFound(url.to_string()))
+	}
 
-		Ok(resource)
+	/// Create a resolver for references, that can look up the targets in
+	/// `contained` resources and bundles before fetching them from the server.
+	pub fn reference_resolver<'a>(&self) -> ReferenceResolver<'a, V> {
+		ReferenceResolver::new(self.clone())
 	}
 
 	/// Retrieve the history of the specified resource type or a specific resource.
+	/// Use [Self::type_history] or [Self::instance_history] to set further
+	/// parameters.
 	pub async fn history<R>(&self, id: Option<&str>) -> Result<Page<V, R>, Error>
 	where
 		R: AnyResource<V> + TryFrom<V::Resource, Error = WrongResourceType> + 'static,
 		for<'a> &'a R: TryFrom<&'a V::Resource>,
 	{
-		let correlation_id = make_uuid_header_value();
+		match id {
+			Some(id) => self.instance_history(id).send().await,
+			None => self.type_history().send().await,
+		}
+	}
 
-		let url = {
-			if let Some(id) = id {
-				self.url(&[R::TYPE_STR, id, "_history"])
+	/// Retrieve the history of a specific resource as stream of the changes
+	/// each version made to its previous version, newest version first. The
+	/// first version of the resource has no previous version and yields no
+	/// changes. Deletions are reported as removal of the whole resource and
+	/// re-creations as its addition, see [DiffOptions::diff_versions].
+	pub async fn history_changes<R>(
+		&self,
+		id: &str,
+		options: DiffOptions,
+	) -> Result<impl Stream<Item = Result<VersionChanges, Error>> + Send + 'static, Error>
+	where
+		R: AnyResource<V> + TryFrom<V::Resource, Error = WrongResourceType> + Serialize + 'static,
+		for<'a> &'a R: TryFrom<&'a V::Resource>,
+	{
+		let versions = self.history::<R>(Some(id)).await?.all_history_entries();
+		// The newer version and its version ID, `None` if it is a deletion.
+		let mut previous: Option<(Option<R>, Option<String>)> = None;
+		Ok(versions.try_filter_map(move |entry| {
+			let version_id = entry.version_id().map(ToOwned::to_owned);
+			let older = if entry.is_delete() {
+				None
 			} else {
-				self.url(&[R::TYPE_STR, "_history"])
-			}
-		};
-		let request = self
-			.0
-			.client
-			.get(url)
-			.header(header::ACCEPT, V::MIME_TYPE)
-			.header("X-Correlation-Id", correlation_id.clone());
-
-		let response = self.run_request(request).await?;
-		if response.status().is_success() {
-			let bundle: V::Bundle = response.json().await?;
-			Ok(Page::new(self.clone(), bundle, correlation_id))
-		} else {
-			Err(Error::from_response::<V>(response).await)
-		}
+				let Some(Ok(older)) = entry.resource.map(R::try_from) else {
+					return future::ready(Ok(None));
+				};
+				Some(older)
+			};
+			let result = match previous.take() {
+				Some((newer, version_id)) => options
+					.diff_versions::<V, R>(older.as_ref(), newer.as_ref())
+					.map_err(Error::from)
+					.map(|changes| Some(VersionChanges { version_id, changes })),
+				None => Ok(None),
+			};
+			previous = Some((older, version_id));
+			future::ready(result)
+		}))
 	}
 
 	/// Inner function to create any resource for any resource type.
@@ -190,7 +270,7 @@ where
 		let mut request = self
 			.0
 			.client
-			.put(url)
+			.put(url.clone())
 			.header(header::ACCEPT, V::MIME_TYPE)
 			.header(header::CONTENT_TYPE, V::MIME_TYPE)
 			.json(resource);
@@ -202,6 +282,7 @@ where
 
 		let response = self.run_request(request).await?;
 		if response.status().is_success() {
+			self.invalidate_cached(&url);
 			let created = response.status() == StatusCode::CREATED;
 			let version_id = misc::parse_etag(response.headers())?;
 			Ok((created, version_id))
@@ -228,10 +309,11 @@ where
 	/// Delete a FHIR resource on the server.
 	pub async fn delete(&self, resource_type: V::ResourceType, id: &str) -> Result<(), Error> {
 		let url = self.url(&[resource_type.as_ref(), id]);
-		let request = self.0.client.delete(url).header(header::ACCEPT, V::MIME_TYPE);
+		let request = self.0.client.delete(url.clone()).header(header::ACCEPT, V::MIME_TYPE);
 
 		let response = self.run_request(request).await?;
 		if response.status().is_success() {
+			self.invalidate_cached(&url);
 			Ok(())
 		} else {
 			Err(Error::from_response::<V>(response).await)
@@ -361,4 +443,10 @@ where
 	pub fn transaction(&self) -> BatchTransaction<V> {
 		BatchTransaction::new(self.clone(), true)
 	}
+
+	/// Start building an upload of resources that reference each other via
+	/// their local IDs.
+	pub fn upload_graph(&self, resources: Vec<V::Resource>) -> GraphUpload<V> {
+		GraphUpload::new(self.clone(), resources)
+	}
 }
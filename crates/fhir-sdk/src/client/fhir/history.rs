@@ -0,0 +1,279 @@
+//! History request building and history entries.
+
+use std::{borrow::Cow, marker::PhantomData};
+
+use fhir_model::{DateTime, Instant, ParsedReference, WrongResourceType};
+use reqwest::{header, Method, StatusCode};
+use serde::Serialize;
+
+use super::{misc, paging::Page, transaction::BundleEntry, Client, Error};
+use crate::{
+	client::misc::make_uuid_header_value,
+	extensions::{
+		AnyResource, BundleEntryExt, BundleEntryRequestExt, BundleEntryResponseExt, GenericResource,
+	},
+	version::FhirVersion,
+};
+
+/// Builder for a history request on the whole system, a resource type or a
+/// specific resource.
+#[derive(Debug, Clone)]
+#[must_use = "You probably want to send the history request"]
+pub struct HistoryRequest<V: FhirVersion, R> {
+	/// The FHIR client.
+	client: Client<V>,
+	/// Resource type and optional resource ID, `None` for the whole system.
+	target: Option<(Cow<'static, str>, Option<String>)>,
+	/// Only include versions created at or after this instant.
+	since: Option<Instant>,
+	/// Only include the versions that were current at this point in time.
+	at: Option<DateTime>,
+	/// Maximum number of entries per page.
+	count: Option<u32>,
+	/// Sort order of the entries.
+	sort: Option<String>,
+	/// The resource type to return in matches.
+	_resource_type: PhantomData<R>,
+}
+
+impl<V: FhirVersion, R> HistoryRequest<V, R> {
+	/// Create a new history request builder.
+	pub(super) const fn new(
+		client: Client<V>,
+		target: Option<(Cow<'static, str>, Option<String>)>,
+	) -> Self {
+		Self {
+			client,
+			target,
+			since: None,
+			at: None,
+			count: None,
+			sort: None,
+			_resource_type: PhantomData,
+		}
+	}
+}
+
+impl<V: FhirVersion> HistoryRequest<V, V::Resource> {
+	/// Start building a history request on the whole system.
+	pub const fn system(client: Client<V>) -> Self {
+		Self::new(client, None)
+	}
+}
+
+impl<V: FhirVersion, R: AnyResource<V>> HistoryRequest<V, R> {
+	/// Start building a history request on all resources of the resource type.
+	pub const fn resource_type(client: Client<V>) -> Self {
+		Self::new(client, Some((Cow::Borrowed(R::TYPE_STR), None)))
+	}
+
+	/// Start building a history request on the specific resource.
+	pub fn instance(client: Client<V>, id: impl Into<String>) -> Self {
+		Self::new(client, Some((Cow::Borrowed(R::TYPE_STR), Some(id.into()))))
+	}
+}
+
+impl<V: FhirVersion, R> HistoryRequest<V, R>
+where
+	(StatusCode, V::OperationOutcome): Into<Error>,
+	R: TryFrom<V::Resource> + Send + Sync + 'static,
+	for<'a> &'a R: TryFrom<&'a V::Resource>,
+{
+	/// Only include versions created at or after the given instant (`_since`).
+	pub fn since(mut self, since: Instant) -> Self {
+		self.since = Some(since);
+		self
+	}
+
+	/// Only include the versions that were current at the given point in time
+	/// (`_at`).
+	pub fn at(mut self, at: DateTime) -> Self {
+		self.at = Some(at);
+		self
+	}
+
+	/// Set the maximum number of entries per page (`_count`).
+	pub const fn count(mut self, count: u32) -> Self {
+		self.count = Some(count);
+		self
+	}
+
+	/// Set the sort order (`_sort`), e.g. `_lastUpdated` for oldest first or
+	/// `-_lastUpdated` for newest first. Not all servers support this.
+	pub fn sort(mut self, sort: impl Into<String>) -> Self {
+		self.sort = Some(sort.into());
+		self
+	}
+
+	/// Send the history request and return the first page of results.
+	pub async fn send(self) -> Result<Page<V, R>, Error> {
+		let correlation_id = make_uuid_header_value();
+
+		let mut url = match &self.target {
+			None => self.client.url(&["_history"]),
+			Some((resource_type, None)) => self.client.url(&[resource_type.as_ref(), "_history"]),
+			Some((resource_type, Some(id))) => {
+				self.client.url(&[resource_type.as_ref(), id.as_str(), "_history"])
+			}
+		};
+		{
+			let mut query = url.query_pairs_mut();
+			if let Some(since) = &self.since {
+				query.append_pair("_since", &query_value(since)?);
+			}
+			if let Some(at) = &self.at {
+				query.append_pair("_at", &query_value(at)?);
+			}
+			if let Some(count) = self.count {
+				query.append_pair("_count", &count.to_string());
+			}
+			if let Some(sort) = &self.sort {
+				query.append_pair("_sort", sort);
+			}
+		}
+		if url.query() == Some("") {
+			url.set_query(None);
+		}
+
+		let request = self
+			.client
+			.0
+			.client
+			.get(url)
+			.header(header::ACCEPT, V::MIME_TYPE)
+			.header("X-Correlation-Id", correlation_id.clone());
+
+		let response = self.client.run_request(request).await?;
+		if response.status().is_success() {
+			let bundle: V::Bundle = response.json().await?;
+			Ok(Page::new(self.client, bundle, correlation_id))
+		} else {
+			Err(Error::from_response::<V>(response).await)
+		}
+	}
+}
+
+/// Format the FHIR date/time value for use in a query.
+pub(super) fn query_value<T: Serialize>(value: &T) -> Result<String, Error> {
+	match serde_json::to_value(value)? {
+		serde_json::Value::String(value) => Ok(value),
+		value => Ok(value.to_string()),
+	}
+}
+
+/// An entry of a history `Bundle`, i.e. a version of a resource or its
+/// deletion.
+#[derive(Debug, Clone)]
+pub struct HistoryEntry<V: FhirVersion> {
+	/// Method of the interaction that led to this version, e.g. `PUT` or
+	/// `DELETE`.
+	pub method: Option<Method>,
+	/// URL of the interaction, e.g. `Patient/1`.
+	pub url: Option<String>,
+	/// Full URL of the resource.
+	pub full_url: Option<String>,
+	/// Response status of the interaction, e.g. `201 Created`.
+	pub status: Option<String>,
+	/// ETag of the version.
+	pub etag: Option<String>,
+	/// The resource in this version. Missing for deletions.
+	pub resource: Option<V::Resource>,
+}
+
+impl<V: FhirVersion> HistoryEntry<V> {
+	/// Convert the `Bundle` entry into a history entry.
+	pub(crate) fn from_entry(entry: BundleEntry<V>) -> Self {
+		let request = entry.request();
+		let method = request.and_then(|request| request.method().parse().ok());
+		let url = request.map(|request| request.url().to_owned());
+		let full_url = entry.full_url().cloned();
+		let response = entry.response();
+		let status = response.map(|response| response.status().to_owned());
+		let etag = response.and_then(|response| response.etag()).map(ToOwned::to_owned);
+		Self { method, url, full_url, status, etag, resource: entry.into_resource() }
+	}
+
+	/// Whether this entry represents the deletion of the resource.
+	#[must_use]
+	pub fn is_delete(&self) -> bool {
+		self.method == Some(Method::DELETE)
+	}
+
+	/// Whether this entry represents the creation of the resource, as far as
+	/// the server tells.
+	#[must_use]
+	pub fn is_create(&self) -> bool {
+		self.method == Some(Method::POST)
+			|| self.status.as_deref().is_some_and(|status| status.starts_with("201"))
+	}
+
+	/// The request URL parsed as reference, e.g. `Patient/1`.
+	fn parsed_url(&self) -> Option<ParsedReference<'_>> {
+		let url = self.url.as_deref().or(self.full_url.as_deref())?;
+		Some(ParsedReference::new(url.split('?').next().unwrap_or(url)))
+	}
+
+	/// The resource type, from the resource or the request URL.
+	#[must_use]
+	pub fn resource_type(&self) -> Option<&str> {
+		if let Some(resource) = &self.resource {
+			return Some(resource.resource_type_str());
+		}
+		match self.parsed_url()? {
+			ParsedReference::Relative { resource_type, .. } => Some(resource_type),
+			ParsedReference::Absolute { resource_type, .. } => resource_type,
+			ParsedReference::Local { .. } => None,
+		}
+	}
+
+	/// The resource ID, from the resource or the request URL.
+	#[must_use]
+	pub fn id(&self) -> Option<&str> {
+		if let Some(id) = self.resource.as_ref().and_then(GenericResource::id) {
+			return Some(id);
+		}
+		match self.parsed_url()? {
+			ParsedReference::Relative { id, .. } => Some(id),
+			ParsedReference::Absolute { id, .. } => id,
+			ParsedReference::Local { .. } => None,
+		}
+	}
+
+	/// The version ID, from the resource or the ETag.
+	#[must_use]
+	pub fn version_id(&self) -> Option<&str> {
+		self.resource
+			.as_ref()
+			.and_then(GenericResource::version_id)
+			.or_else(|| self.etag.as_deref().and_then(misc::parse_etag_str))
+	}
+}
+
+impl<V: FhirVersion> Client<V>
+where
+	(StatusCode, V::OperationOutcome): Into<Error>,
+{
+	/// Start building a history request on the whole system, returning all
+	/// resource types.
+	pub fn system_history(&self) -> HistoryRequest<V, V::Resource> {
+		HistoryRequest::system(self.clone())
+	}
+
+	/// Start building a history request on all resources of the resource type.
+	pub fn type_history<R>(&self) -> HistoryRequest<V, R>
+	where
+		R: AnyResource<V> + TryFrom<V::Resource, Error = WrongResourceType> + 'static,
+		for<'a> &'a R: TryFrom<&'a V::Resource>,
+	{
+		HistoryRequest::resource_type(self.clone())
+	}
+
+	/// Start building a history request on the specific resource.
+	pub fn instance_history<R>(&self, id: impl Into<String>) -> HistoryRequest<V, R>
+	where
+		R: AnyResource<V> + TryFrom<V::Resource, Error = WrongResourceType> + 'static,
+		for<'a> &'a R: TryFrom<&'a V::Resource>,
+	{
+		HistoryRequest::instance(self.clone(), id)
+	}
+}
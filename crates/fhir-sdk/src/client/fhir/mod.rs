@@ -1,19 +1,29 @@
 //! FHIR API access methods (version specific).
 
 mod crud;
+mod history;
 mod operations;
 mod paging;
 mod patch;
+mod references;
 mod search_params;
+mod sync;
 mod transaction;
+mod upload;
 mod write;
 
 pub use self::{
+	history::{HistoryEntry, HistoryRequest},
 	paging::Page,
+	patch::{PatchViaFhir, PatchViaJson},
+	references::{ReferenceResolver, ResolutionSource, ResolvedReference},
 	search_params::{
 		DateSearch, MissingSearch, NumberSearch, QuantitySearch, ReferenceSearch, StringSearch,
 		TokenSearch, UriSearch,
 	},
+	sync::{ChangeSync, SyncEvent, SyncMode},
+	transaction::{BatchResponse, BatchResponseEntry, BatchTransaction},
+	upload::GraphUpload,
 	write::{AnyResourceWrite, ResourceWrite},
 };
 use super::{misc, Client, Error, SearchParameters};
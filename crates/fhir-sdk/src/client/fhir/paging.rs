@@ -5,7 +5,7 @@ use std::{any::type_name, fmt::Debug, marker::PhantomData};
 use futures::{stream, Stream, StreamExt, TryStreamExt};
 use reqwest::{header::HeaderValue, StatusCode, Url};
 
-use super::{Client, Error};
+use super::{history::HistoryEntry, Client, Error};
 use crate::{
 	extensions::{BundleEntryExt, BundleEntryRequestExt, BundleExt, SearchEntryModeExt},
 	version::FhirVersion,
@@ -59,7 +59,7 @@ where
 		tracing::debug!("Fetching next page from URL: {next_page_url}");
 		let next_bundle = match self
 			.client
-			.read_generic::<V::Bundle>(url, Some(self.correlation_id.clone()))
+			.read_generic::<V::Bundle>(url, Some(self.correlation_id.clone()), false)
 			.await
 		{
 			Ok(Some(bundle)) => bundle,
@@ -141,6 +141,13 @@ where
 		.try_filter_map(|resource| std::future::ready(Ok(resource.try_into().ok())))
 	}
 
+	/// Get the entries of this page as history entries, including deletions. Use this for
+	/// history requests.
+	/// Consumes the entries, leaving the page empty.
+	pub fn history_entries_owned(&mut self) -> Vec<HistoryEntry<V>> {
+		self.take_entries().into_iter().flatten().map(HistoryEntry::from_entry).collect()
+	}
+
 	/// Start automatic paging through all entries across pages.
 	///
 	/// Hint: you can activate pre-fetching by [StreamExt::buffered].
@@ -170,6 +177,22 @@ where
 			)
 			.boxed() // Somehow gives error when using if not boxed?
 	}
+
+	/// Start automatic paging through all history entries across pages, including deletions.
+	///
+	/// Hint: you can activate pre-fetching by [StreamExt::buffered].
+	pub fn all_history_entries(
+		mut self,
+	) -> impl Stream<Item = Result<HistoryEntry<V>, Error>> + Send + 'static {
+		stream::iter(self.history_entries_owned().into_iter().map(Ok))
+			.chain(
+				stream::once(async move { self.next_page().await })
+					.filter_map(std::future::ready)
+					.map_ok(Self::all_history_entries)
+					.try_flatten(),
+			)
+			.boxed() // Somehow gives error when using if not boxed?
+	}
 }
 
 /// Convert the bundle entry into a resource, resolving the `fullUrl` if there is no resource
@@ -200,7 +223,7 @@ where
 	};
 
 	let result = client
-		.read_generic::<V::Resource>(url, Some(correlation_id))
+		.read_generic::<V::Resource>(url, Some(correlation_id), true)
 		.await
 		.and_then(|opt| opt.ok_or_else(|| Error::ResourceNotFound(full_url.clone())));
 	Some(result)
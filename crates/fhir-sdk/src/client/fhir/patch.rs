@@ -5,10 +5,16 @@ use reqwest::{
 	StatusCode,
 };
 use serde::Serialize;
+use serde_json::{json, Value};
 
 use super::{Client, Error};
 use crate::{
-	extensions::{ParameterExt, ParameterValueExt, ParametersExt},
+	extensions::{AnyResource, ParameterExt, ParameterValueExt, ParametersExt},
+	patch::{
+		self,
+		element::{value_type_suffix, Element},
+		FhirPathPatchOperation, JsonPatchOperation, PatchError,
+	},
 	version::FhirVersion,
 };
 
@@ -30,6 +36,8 @@ pub struct PatchViaFhir<'a, V: FhirVersion> {
 	id: &'a str,
 	/// Operations to apply.
 	operations: Vec<Option<ParametersParameter<V>>>,
+	/// Version ID the resource must have for the patch to be applied.
+	version_id: Option<String>,
 }
 
 impl<'a, V: FhirVersion> PatchViaFhir<'a, V>
@@ -38,7 +46,34 @@ where
 {
 	/// Start building a new Patch request.
 	pub fn new(client: Client<V>, resource_type: V::ResourceType, id: &'a str) -> Self {
-		Self { client, resource_type, id, operations: Vec::new() }
+		Self { client, resource_type, id, operations: Vec::new(), version_id: None }
+	}
+
+	/// Build a Patch request with the operations that turn `old` into `new`,
+	/// see [patch::diff_fhirpath]. Values are typed via the element
+	/// definitions, e.g. `valueDate` for `birthDate`. `FHIRPath Patch` has no
+	/// `test` operation, so the version ID of `old` is sent in the `If-Match`
+	/// header instead, making the server reject the patch if the resource was
+	/// changed in the meantime.
+	pub fn from_diff<R>(client: Client<V>, old: &'a R, new: &R) -> Result<Self, Error>
+	where
+		R: AnyResource<V> + Serialize,
+	{
+		let id = old.id().ok_or(Error::MissingId)?;
+		let mut patch = Self::new(client, R::TYPE, id);
+		patch.version_id = old.version_id().map(ToOwned::to_owned);
+		for operation in patch::diff_fhirpath::<V, _>(old, new)? {
+			let parameter = serde_json::from_value(operation_parameter::<V>(&operation)?)?;
+			patch.operations.push(Some(parameter));
+		}
+		Ok(patch)
+	}
+
+	/// Get the operations as `Parameters` resource, e.g. to apply them locally
+	/// via [patch::fhirpath_operations] and [patch::apply_fhirpath].
+	#[must_use]
+	pub fn parameters(&self) -> V::Parameters {
+		V::Parameters::make(self.operations.clone())
 	}
 
 	/// Add an `add` operation to the list of operations. Note that the `path`
@@ -213,17 +248,21 @@ where
 		let parameters = V::Parameters::make(self.operations);
 
 		let url = self.client.url(&[self.resource_type.as_ref(), self.id]);
-		let request = self
+		let mut request = self
 			.client
 			.0
 			.client
-			.patch(url)
+			.patch(url.clone())
 			.header(header::ACCEPT, V::MIME_TYPE)
 			.header(header::CONTENT_TYPE, HeaderValue::from_static(V::MIME_TYPE))
 			.json(&parameters);
+		if let Some(version_id) = self.version_id {
+			request = request.header(header::IF_MATCH, format!("W/\"{version_id}\""));
+		}
 
 		let response = self.client.run_request(request).await?;
 		if response.status().is_success() {
+			self.client.invalidate_cached(&url);
 			Ok(())
 		} else {
 			Err(Error::from_response::<V>(response).await)
@@ -242,7 +281,7 @@ pub struct PatchViaJson<'a, V: FhirVersion> {
 	/// Resource ID to apply the path to.
 	id: &'a str,
 	/// Operations to apply.
-	operations: Vec<serde_json::Map<String, serde_json::Value>>,
+	operations: Vec<JsonPatchOperation>,
 }
 
 impl<'a, V: FhirVersion> PatchViaJson<'a, V>
@@ -254,30 +293,45 @@ where
 		Self { client, resource_type, id, operations: Vec::new() }
 	}
 
+	/// Build a Patch request with the operations that turn `old` into `new`,
+	/// see [patch::diff]. If `old` has a version ID, a `test` operation on
+	/// `/meta/versionId` is prepended, making the server reject the patch if
+	/// the resource was changed in the meantime.
+	pub fn from_diff<R>(client: Client<V>, old: &'a R, new: &R) -> Result<Self, Error>
+	where
+		R: AnyResource<V> + Serialize,
+	{
+		let id = old.id().ok_or(Error::MissingId)?;
+		let mut patch = Self::new(client, R::TYPE, id);
+		if let Some(version_id) = old.version_id() {
+			patch = patch.test("/meta/versionId", version_id)?;
+		}
+		patch.operations.extend(patch::diff(old, new)?);
+		Ok(patch)
+	}
+
+	/// Get the operations, e.g. to apply them locally via [patch::apply].
+	#[must_use]
+	pub fn operations(&self) -> &[JsonPatchOperation] {
+		&self.operations
+	}
+
 	/// Add an `add` operation to the list of operations. The `path` needs to be
 	/// in the correct format, e.g. `/birthDate`. The value needs to serialize
 	/// into the correct format for the respective FHIR datatype, this cannot be
 	/// checked in the client.
 	pub fn add(mut self, path: impl Into<String>, value: impl Serialize) -> Result<Self, Error> {
-		let mut operation = serde_json::Map::new();
-
-		operation.insert("op".to_owned(), "add".into());
-		operation.insert("path".to_owned(), path.into().into());
-		operation.insert("value".to_owned(), serde_json::to_value(value)?);
-
-		self.operations.push(operation);
+		self.operations.push(JsonPatchOperation::Add {
+			path: path.into(),
+			value: serde_json::to_value(value)?,
+		});
 		Ok(self)
 	}
 
 	/// Add a `remove` operation to the list of operations. The `path` needs to
 	/// be in the correct format, e.g. `/birthDate`.
 	pub fn remove(mut self, path: impl Into<String>) -> Self {
-		let mut operation = serde_json::Map::new();
-
-		operation.insert("op".to_owned(), "remove".into());
-		operation.insert("path".to_owned(), path.into().into());
-
-		self.operations.push(operation);
+		self.operations.push(JsonPatchOperation::Remove { path: path.into() });
 		self
 	}
 
@@ -286,13 +340,10 @@ where
 	/// into the correct format for the respective FHIR datatype, this cannot be
 	/// checked in the client.
 	pub fn test(mut self, path: impl Into<String>, value: impl Serialize) -> Result<Self, Error> {
-		let mut operation = serde_json::Map::new();
-
-		operation.insert("op".to_owned(), "test".into());
-		operation.insert("path".to_owned(), path.into().into());
-		operation.insert("value".to_owned(), serde_json::to_value(value)?);
-
-		self.operations.push(operation);
+		self.operations.push(JsonPatchOperation::Test {
+			path: path.into(),
+			value: serde_json::to_value(value)?,
+		});
 		Ok(self)
 	}
 
@@ -305,39 +356,24 @@ where
 		path: impl Into<String>,
 		value: impl Serialize,
 	) -> Result<Self, Error> {
-		let mut operation = serde_json::Map::new();
-
-		operation.insert("op".to_owned(), "replace".into());
-		operation.insert("path".to_owned(), path.into().into());
-		operation.insert("value".to_owned(), serde_json::to_value(value)?);
-
-		self.operations.push(operation);
+		self.operations.push(JsonPatchOperation::Replace {
+			path: path.into(),
+			value: serde_json::to_value(value)?,
+		});
 		Ok(self)
 	}
 
 	/// Add a `move` operation to the list of operations. The `path`s needs to
 	/// be in the correct format, e.g. `/birthDate`.
 	pub fn r#move(mut self, from: impl Into<String>, path: impl Into<String>) -> Self {
-		let mut operation = serde_json::Map::new();
-
-		operation.insert("op".to_owned(), "move".into());
-		operation.insert("from".to_owned(), from.into().into());
-		operation.insert("path".to_owned(), path.into().into());
-
-		self.operations.push(operation);
+		self.operations.push(JsonPatchOperation::Move { from: from.into(), path: path.into() });
 		self
 	}
 
 	/// Add a `copy` operation to the list of operations. The `path`s needs to
 	/// be in the correct format, e.g. `/birthDate`.
 	pub fn copy(mut self, from: impl Into<String>, path: impl Into<String>) -> Self {
-		let mut operation = serde_json::Map::new();
-
-		operation.insert("op".to_owned(), "copy".into());
-		operation.insert("from".to_owned(), from.into().into());
-		operation.insert("path".to_owned(), path.into().into());
-
-		self.operations.push(operation);
+		self.operations.push(JsonPatchOperation::Copy { from: from.into(), path: path.into() });
 		self
 	}
 
@@ -348,16 +384,109 @@ where
 			.client
 			.0
 			.client
-			.patch(url)
+			.patch(url.clone())
 			.header(header::ACCEPT, V::MIME_TYPE)
 			.header(header::CONTENT_TYPE, HeaderValue::from_static("application/json-patch+json"))
 			.json(&self.operations);
 
 		let response = self.client.run_request(request).await?;
 		if response.status().is_success() {
+			self.client.invalidate_cached(&url);
 			Ok(())
 		} else {
 			Err(Error::from_response::<V>(response).await)
 		}
 	}
 }
+
+/// Make the `operation` parameter of a `FHIRPath Patch` for the operation.
+/// Values are typed via the element definitions of the FHIR version.
+fn operation_parameter<V: FhirVersion>(operation: &FhirPathPatchOperation) -> Result<Value, Error> {
+	let part = match operation {
+		FhirPathPatchOperation::Add { path, name, value, value_type } => {
+			let element =
+				Element::resolve::<V>(path)?.child::<V>(name)?.with_type(value_type.as_deref())?;
+			vec![
+				json!({ "name": "type", "valueCode": "add" }),
+				json!({ "name": "path", "valueString": path }),
+				json!({ "name": "name", "valueString": name }),
+				value_parameter::<V>("value", &element, value)?,
+			]
+		}
+		FhirPathPatchOperation::Insert { path, value, value_type, index } => {
+			let element = Element::resolve::<V>(path)?.with_type(value_type.as_deref())?;
+			vec![
+				json!({ "name": "type", "valueCode": "insert" }),
+				json!({ "name": "path", "valueString": path }),
+				json!({ "name": "index", "valueInteger": index }),
+				value_parameter::<V>("value", &element, value)?,
+			]
+		}
+		FhirPathPatchOperation::Delete { path } => vec![
+			json!({ "name": "type", "valueCode": "delete" }),
+			json!({ "name": "path", "valueString": path }),
+		],
+		FhirPathPatchOperation::Replace { path, value, value_type } => {
+			let element = Element::resolve::<V>(path)?.with_type(value_type.as_deref())?;
+			vec![
+				json!({ "name": "type", "valueCode": "replace" }),
+				json!({ "name": "path", "valueString": path }),
+				value_parameter::<V>("value", &element, value)?,
+			]
+		}
+		FhirPathPatchOperation::Move { path, source, destination } => vec![
+			json!({ "name": "type", "valueCode": "move" }),
+			json!({ "name": "path", "valueString": path }),
+			json!({ "name": "source", "valueInteger": source }),
+			json!({ "name": "destination", "valueInteger": destination }),
+		],
+	};
+	Ok(json!({ "name": "operation", "part": part }))
+}
+
+/// Make a parameter with the given name for the value of the element. Values
+/// become a `value[x]` of the element's type, resources a `resource` and
+/// backbone elements `part`s named after their elements.
+fn value_parameter<V: FhirVersion>(
+	name: &str,
+	element: &Element,
+	value: &Value,
+) -> Result<Value, Error> {
+	let Some(r#type) = element.r#type() else {
+		return Err(PatchError::Unsupported(format!(
+			"values of choice elements without type (`{}`)",
+			element.path()
+		))
+		.into());
+	};
+	match value {
+		Value::Null => {
+			Err(PatchError::Unsupported(format!("null values (`{}`)", element.path())).into())
+		}
+		Value::Array(_) => {
+			Err(PatchError::Unsupported(format!("lists as single value (`{}`)", element.path()))
+				.into())
+		}
+		Value::Object(object) if element.uses_parts() => {
+			let mut part = Vec::new();
+			for (key, value) in object {
+				let (name, child) = element.json_child::<V>(key)?;
+				let values = match value {
+					Value::Array(values) => values.as_slice(),
+					value => std::slice::from_ref(value),
+				};
+				for value in values {
+					part.push(value_parameter::<V>(&name, &child, value)?);
+				}
+			}
+			Ok(json!({ "name": name, "part": part }))
+		}
+		_ if element.is_resource() => Ok(json!({ "name": name, "resource": value })),
+		_ => {
+			let mut parameter = serde_json::Map::new();
+			parameter.insert("name".to_owned(), json!(name));
+			parameter.insert(format!("value{}", value_type_suffix(r#type)), value.clone());
+			Ok(Value::Object(parameter))
+		}
+	}
+}
@@ -0,0 +1,387 @@
+//! Resolution of references against contained resources, bundles and the
+//! server.
+
+use std::collections::{BTreeSet, HashMap};
+
+use fhir_model::{ParsedReference, WrongResourceType};
+use futures::{stream, StreamExt, TryStreamExt};
+use reqwest::StatusCode;
+use serde::Serialize;
+use serde_json::Value;
+
+use super::{Client, Error};
+use crate::{
+	extensions::{AnyResource, BundleEntryExt, BundleExt, GenericResource, ReferenceExt},
+	version::FhirVersion,
+};
+
+/// Maximum number of reads in one batch request when resolving references in
+/// bulk.
+const RESOLVE_BATCH_SIZE: usize = 100;
+
+/// Number of references to other servers to fetch concurrently when resolving
+/// references in bulk.
+const RESOLVE_CONCURRENCY: usize = 4;
+
+/// Where a reference was resolved from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResolutionSource {
+	/// The `contained` resources of the referencing resource.
+	Contained,
+	/// An entry of the bundle the referencing resource is part of.
+	Bundle,
+	/// The FHIR server.
+	Server,
+}
+
+/// The target resource of a resolved reference.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedReference<R> {
+	/// The referenced resource.
+	pub resource: R,
+	/// Where the resource was found.
+	pub source: ResolutionSource,
+}
+
+/// Resolver for references, looking for the target in the `contained`
+/// resources of the referencing resource first, then in the bundle it is part
+/// of and only then fetching it from the server.
+#[derive(Debug, Clone)]
+pub struct ReferenceResolver<'a, V: FhirVersion> {
+	/// FHIR client.
+	client: Client<V>,
+	/// The referencing resource, to resolve local references in.
+	container: Option<&'a V::Resource>,
+	/// The bundle the referencing resource is part of.
+	bundle: Option<&'a V::Bundle>,
+}
+
+impl<'a, V: FhirVersion> ReferenceResolver<'a, V>
+where
+	(StatusCode, V::OperationOutcome): Into<Error>,
+{
+	/// Create a new resolver that only fetches from the server until configured
+	/// otherwise.
+	pub const fn new(client: Client<V>) -> Self {
+		Self { client, container: None, bundle: None }
+	}
+
+	/// Resolve local references (`#id`) in the `contained` resources of the given
+	/// referencing resource. `#` alone references the resource itself.
+	#[must_use]
+	pub const fn contained_in(mut self, resource: &'a V::Resource) -> Self {
+		self.container = Some(resource);
+		self
+	}
+
+	/// Resolve references in the entries of the given bundle, matching absolute
+	/// references and `urn:uuid:`s against the entries' `fullUrl` and relative
+	/// references against the entries' resource type, ID and version ID.
+	#[must_use]
+	pub const fn bundle(mut self, bundle: &'a V::Bundle) -> Self {
+		self.bundle = Some(bundle);
+		self
+	}
+
+	/// Resolve the reference to the target resource. Fails if the target
+	/// cannot be found or does not match the reference's `type`.
+	pub async fn resolve(
+		&self,
+		reference: &V::Reference,
+	) -> Result<ResolvedReference<V::Resource>, Error> {
+		let parsed_reference = reference.parse().ok_or(Error::MissingReference)?;
+		let resolved = match self.resolve_locally(parsed_reference)? {
+			Some(resolved) => resolved,
+			None => ResolvedReference {
+				resource: self.client.fetch_referenced(parsed_reference).await?,
+				source: ResolutionSource::Server,
+			},
+		};
+		check_reference_type::<V>(reference, &resolved.resource)?;
+		Ok(resolved)
+	}
+
+	/// Resolve the reference to the target resource of the given type. Fails if
+	/// the target cannot be found or is of a different type.
+	pub async fn resolve_as<R>(
+		&self,
+		reference: &V::Reference,
+	) -> Result<ResolvedReference<R>, Error>
+	where
+		R: AnyResource<V> + TryFrom<V::Resource, Error = WrongResourceType>,
+	{
+		let ResolvedReference { resource, source } = self.resolve(reference).await?;
+		let resource = R::try_from(resource).map_err(|WrongResourceType(actual, expected)| {
+			Error::WrongResourceType(actual, expected)
+		})?;
+		Ok(ResolvedReference { resource, source })
+	}
+
+	/// Try to resolve the reference without the server. Returns `None` if the
+	/// server needs to be asked.
+	fn resolve_locally(
+		&self,
+		parsed_reference: ParsedReference<'_>,
+	) -> Result<Option<ResolvedReference<V::Resource>>, Error> {
+		if let ParsedReference::Local { id } = parsed_reference {
+			let container = self.container.ok_or(Error::LocalReference)?;
+			let resource = if id.is_empty() {
+				Some(container)
+			} else {
+				container.contained().iter().find(|resource| resource.id() == Some(id))
+			};
+			let resource = resource.ok_or_else(|| Error::ResourceNotFound(format!("#{id}")))?;
+			return Ok(Some(ResolvedReference {
+				resource: resource.clone(),
+				source: ResolutionSource::Contained,
+			}));
+		}
+
+		let Some(bundle) = self.bundle else {
+			return Ok(None);
+		};
+		let resource = bundle
+			.entries()
+			.filter_map(|entry| entry.resource().map(|resource| (entry.full_url(), resource)))
+			.find(|(full_url, resource)| match parsed_reference {
+				ParsedReference::Local { .. } => false,
+				ParsedReference::Relative { resource_type, id, version_id } => {
+					resource.resource_type_str() == resource_type
+						&& resource.id() == Some(id)
+						&& version_id
+							.map_or(true, |version_id| resource.version_id() == Some(version_id))
+				}
+				ParsedReference::Absolute { url, .. } => full_url.map(String::as_str) == Some(url),
+			})
+			.map(|(_, resource)| resource.clone());
+
+		Ok(resource
+			.map(|resource| ResolvedReference { resource, source: ResolutionSource::Bundle }))
+	}
+}
+
+impl<V: FhirVersion> Client<V>
+where
+	(StatusCode, V::OperationOutcome): Into<Error>,
+{
+	/// Resolve the references selected by the given function from all resources
+	/// in bulk. The references are deduplicated and fetched via batch requests
+	/// of `GET`s instead of one request per reference. References to other
+	/// servers are fetched individually and concurrently.
+	///
+	/// Returns a map from the reference string to the target resource. Local
+	/// references and `urn:uuid:`s cannot be resolved without context and are
+	/// skipped, just like targets that were not found.
+	pub async fn resolve_references<'r, R, F, I>(
+		&self,
+		resources: &'r [R],
+		references: F,
+	) -> Result<HashMap<String, V::Resource>, Error>
+	where
+		F: Fn(&'r R) -> I,
+		I: IntoIterator<Item = &'r V::Reference>,
+	{
+		let references = resources
+			.iter()
+			.flat_map(references)
+			.filter_map(ReferenceExt::reference)
+			.map(ToOwned::to_owned)
+			.collect();
+		self.fetch_references(references).await
+	}
+
+	/// Resolve the references found at the given path in all resources in bulk,
+	/// see [Self::resolve_references]. The path is a simplified FHIRPath of
+	/// element names separated by dots, e.g. `subject` or
+	/// `Observation.performer`. Arrays on the way are flattened.
+	pub async fn resolve_reference_path<R: Serialize>(
+		&self,
+		resources: &[R],
+		path: &str,
+	) -> Result<HashMap<String, V::Resource>, Error> {
+		let segments: Vec<&str> = path.split('.').collect();
+		let mut references = BTreeSet::new();
+		for resource in resources {
+			let mut value = serde_json::to_value(resource)?;
+			let segments = match segments.split_first() {
+				Some((first, rest))
+					if value.get("resourceType").and_then(Value::as_str) == Some(*first) =>
+				{
+					rest
+				}
+				_ => &segments,
+			};
+			visit_references(&mut value, Some(segments), &mut |reference| {
+				references.insert(reference.clone());
+			});
+		}
+		self.fetch_references(references).await
+	}
+
+	/// Fetch the targets of the given references in bulk.
+	async fn fetch_references(
+		&self,
+		references: BTreeSet<String>,
+	) -> Result<HashMap<String, V::Resource>, Error> {
+		let mut batch = self.batch().max_entries(RESOLVE_BATCH_SIZE);
+		// Expected resource type and reference of the reads in the batch.
+		let mut reads = Vec::new();
+		// Reference and URL of the references to other servers.
+		let mut absolute = Vec::new();
+		for reference in references {
+			let parsed_reference = match ParsedReference::new(&reference) {
+				ParsedReference::Absolute { url, .. } => url
+					.strip_prefix(self.base_url().as_str())
+					.map(|relative| ParsedReference::new(relative.trim_start_matches('/')))
+					.filter(|parsed| matches!(parsed, ParsedReference::Relative { .. }))
+					.unwrap_or_else(|| ParsedReference::new(url)),
+				parsed_reference => parsed_reference,
+			};
+
+			match parsed_reference {
+				ParsedReference::Local { .. } => {
+					tracing::debug!("Skipping local reference `{reference}`");
+				}
+				ParsedReference::Relative { resource_type, id, version_id } => {
+					let Ok(parsed_type) = resource_type.parse::<V::ResourceType>() else {
+						tracing::debug!("Skipping reference `{reference}` of unknown type");
+						continue;
+					};
+					match version_id {
+						Some(version_id) => batch.read_version(parsed_type, id, version_id),
+						None => batch.read(parsed_type, id),
+					}
+					reads.push((resource_type.to_owned(), reference.clone()));
+				}
+				ParsedReference::Absolute { url, .. } if url.starts_with("urn:") => {
+					tracing::debug!("Skipping reference `{reference}` without context");
+				}
+				ParsedReference::Absolute { url, .. } => {
+					absolute.push((reference.clone(), url.to_owned()));
+				}
+			}
+		}
+
+		let fetched: Vec<_> = stream::iter(absolute)
+			.map(|(reference, url)| async move {
+				match self.fetch_referenced(ParsedReference::new(&url)).await {
+					Ok(resource) => Ok(Some((reference, resource))),
+					Err(Error::ResourceNotFound(_)) => Ok(None),
+					Err(err) => Err(err),
+				}
+			})
+			.buffer_unordered(RESOLVE_CONCURRENCY)
+			.try_collect()
+			.await?;
+		let mut resolved: HashMap<_, _> = fetched.into_iter().flatten().collect();
+
+		if reads.is_empty() {
+			return Ok(resolved);
+		}
+
+		tracing::debug!("Resolving {} references via batch requests", reads.len());
+		let response = batch.send().await?;
+
+		for ((resource_type, reference), entry) in reads.into_iter().zip(response.into_entries()) {
+			if !entry.is_success() {
+				tracing::debug!("Could not resolve reference `{reference}`");
+				continue;
+			}
+			if let Some(resource) = entry
+				.response
+				.and_then(BundleEntryExt::into_resource)
+				.filter(|resource| resource.resource_type_str() == resource_type)
+			{
+				resolved.insert(reference, resource);
+			}
+		}
+
+		Ok(resolved)
+	}
+}
+
+/// Element names of the `Reference` data type, including the extensions of its
+/// primitive elements.
+const REFERENCE_ELEMENTS: &[&str] = &[
+	"id",
+	"extension",
+	"reference",
+	"_reference",
+	"type",
+	"_type",
+	"identifier",
+	"display",
+	"_display",
+];
+
+/// Whether the JSON object is a `Reference`. Other elements named `reference`,
+/// e.g. in `Expression`, are URIs instead.
+fn is_reference(object: &serde_json::Map<String, Value>) -> bool {
+	object.keys().all(|key| REFERENCE_ELEMENTS.contains(&key.as_str()))
+}
+
+/// Visit the reference strings of the `Reference` elements in the JSON value.
+/// Given a path of element names, only the elements at the path are visited,
+/// otherwise all `Reference` elements anywhere in the value.
+pub(super) fn visit_references(
+	value: &mut Value,
+	path: Option<&[&str]>,
+	visit: &mut impl FnMut(&mut String),
+) {
+	match value {
+		Value::Array(items) => {
+			for item in items {
+				visit_references(item, path, visit);
+			}
+		}
+		Value::Object(object) => match path.and_then(<[&str]>::split_first) {
+			Some((name, rest)) => {
+				if let Some(value) = object.get_mut(*name) {
+					visit_references(value, Some(rest), visit);
+				}
+			}
+			None => {
+				if is_reference(object) {
+					if let Some(Value::String(reference)) = object.get_mut("reference") {
+						visit(reference);
+					}
+				}
+				if path.is_none() {
+					for item in object.values_mut() {
+						visit_references(item, None, visit);
+					}
+				}
+			}
+		},
+		_ => {}
+	}
+}
+
+/// Replace all reference strings of `Reference` elements in the JSON value
+/// that are in the map. Returns the number of replaced references.
+pub(super) fn rewrite_references(value: &mut Value, rewrites: &HashMap<String, String>) -> usize {
+	let mut count = 0;
+	visit_references(value, None, &mut |reference| {
+		if let Some(rewrite) = rewrites.get(reference.as_str()) {
+			reference.clone_from(rewrite);
+			count += 1;
+		}
+	});
+	count
+}
+
+/// Make sure the resource matches the reference's `type`, if it is set.
+pub(super) fn check_reference_type<V: FhirVersion>(
+	reference: &V::Reference,
+	resource: &V::Resource,
+) -> Result<(), Error> {
+	if let Some(resource_type) = reference.r#type() {
+		if resource.resource_type_str() != resource_type {
+			return Err(Error::WrongResourceType(
+				resource.resource_type_str().to_owned(),
+				resource_type.to_owned(),
+			));
+		}
+	}
+	Ok(())
+}
@@ -0,0 +1,488 @@
+//! Incremental synchronization of resource changes by polling.
+
+use std::{
+	borrow::Cow,
+	collections::{BTreeSet, HashSet, VecDeque},
+	fmt::Debug,
+	sync::Arc,
+	time::Duration,
+};
+
+use fhir_model::Instant;
+use futures::{stream, Stream, TryStreamExt};
+use reqwest::StatusCode;
+
+use super::{
+	history::{query_value, HistoryEntry, HistoryRequest},
+	paging::Page,
+	Client, Error, SearchParameters,
+};
+use crate::{
+	client::{CheckpointStore, SyncCheckpoint},
+	extensions::GenericResource,
+	version::FhirVersion,
+};
+
+/// How to poll for changes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncMode {
+	/// Poll the system history or the history of the resource types with
+	/// `_since`, sorted by `_lastUpdated`. Reports deletions. The server needs
+	/// to support sorting the history, as introduced in FHIR R5.
+	#[default]
+	History,
+	/// Search with `_lastUpdated=ge...`, sorted by `_lastUpdated`. Works with
+	/// servers without history support, but does not report deletions and only
+	/// the current version of each resource.
+	LastUpdatedSearch,
+}
+
+/// A change of a resource on the server.
+#[derive(Debug, Clone)]
+pub enum SyncEvent<V: FhirVersion> {
+	/// The resource was created.
+	Created(V::Resource),
+	/// The resource was updated.
+	Updated(V::Resource),
+	/// The resource was deleted.
+	Deleted {
+		/// Resource type of the deleted resource.
+		resource_type: String,
+		/// ID of the deleted resource.
+		id: String,
+		/// Version ID of the deletion, if the server reported it.
+		version_id: Option<String>,
+	},
+}
+
+impl<V: FhirVersion> SyncEvent<V> {
+	/// Convert the history entry into a change event. Returns `None` if the
+	/// entry is neither a deletion nor contains a resource.
+	fn from_entry(entry: HistoryEntry<V>) -> Option<Self> {
+		if entry.is_delete() {
+			return Some(Self::Deleted {
+				resource_type: entry.resource_type()?.to_owned(),
+				id: entry.id()?.to_owned(),
+				version_id: entry.version_id().map(ToOwned::to_owned),
+			});
+		}
+
+		let created = entry.is_create() || entry.version_id() == Some("1");
+		let resource = entry.resource?;
+		Some(if created { Self::Created(resource) } else { Self::Updated(resource) })
+	}
+
+	/// The created or updated resource. `None` for deletions.
+	#[must_use]
+	pub const fn resource(&self) -> Option<&V::Resource> {
+		match self {
+			Self::Created(resource) | Self::Updated(resource) => Some(resource),
+			Self::Deleted { .. } => None,
+		}
+	}
+
+	/// The resource type of the changed resource.
+	#[must_use]
+	pub fn resource_type(&self) -> &str {
+		match self {
+			Self::Created(resource) | Self::Updated(resource) => resource.resource_type_str(),
+			Self::Deleted { resource_type, .. } => resource_type,
+		}
+	}
+
+	/// The ID of the changed resource.
+	#[must_use]
+	pub fn id(&self) -> Option<&str> {
+		match self {
+			Self::Created(resource) | Self::Updated(resource) => resource.id(),
+			Self::Deleted { id, .. } => Some(id),
+		}
+	}
+
+	/// The version ID of the change.
+	#[must_use]
+	pub fn version_id(&self) -> Option<&str> {
+		match self {
+			Self::Created(resource) | Self::Updated(resource) => resource.version_id(),
+			Self::Deleted { version_id, .. } => version_id.as_deref(),
+		}
+	}
+}
+
+/// Builder for an incremental synchronization, polling the server for changes
+/// on an interval.
+#[must_use = "You probably want to start the synchronization stream"]
+pub struct ChangeSync<V: FhirVersion> {
+	/// The FHIR client.
+	client: Client<V>,
+	/// Resource types to synchronize, all types if empty.
+	types: Vec<String>,
+	/// Initial instant to synchronize changes from, all changes if `None`.
+	since: Option<Instant>,
+	/// How to poll for changes.
+	mode: SyncMode,
+	/// Duration to wait between polls.
+	interval: Duration,
+	/// Page size to request.
+	count: Option<u32>,
+	/// Storage for the checkpoint.
+	store: Option<Arc<dyn CheckpointStore>>,
+}
+
+impl<V: FhirVersion> ChangeSync<V>
+where
+	(StatusCode, V::OperationOutcome): Into<Error>,
+{
+	/// Create a new synchronization builder.
+	pub fn new(
+		client: Client<V>,
+		types: impl IntoIterator<Item = V::ResourceType>,
+		since: Option<Instant>,
+	) -> Self {
+		Self {
+			client,
+			types: types
+				.into_iter()
+				.map(|resource_type| resource_type.as_ref().to_owned())
+				.collect(),
+			since,
+			mode: SyncMode::default(),
+			interval: Duration::from_secs(60),
+			count: None,
+			store: None,
+		}
+	}
+
+	/// Set how to poll for changes. Defaults to [SyncMode::History].
+	pub const fn mode(mut self, mode: SyncMode) -> Self {
+		self.mode = mode;
+		self
+	}
+
+	/// Set the duration to wait between polls. Defaults to 60 seconds.
+	pub const fn interval(mut self, interval: Duration) -> Self {
+		self.interval = interval;
+		self
+	}
+
+	/// Set the page size to request (`_count`).
+	pub const fn count(mut self, count: u32) -> Self {
+		self.count = Some(count);
+		self
+	}
+
+	/// Persist the checkpoint in the store. A checkpoint loaded from the store
+	/// takes precedence over the initial `since` instant.
+	pub fn checkpoint_store(mut self, store: impl CheckpointStore + 'static) -> Self {
+		self.store = Some(Arc::new(store));
+		self
+	}
+
+	/// Start polling and return the never-ending stream of change events, in
+	/// the order the changes happened. The histories of multiple resource types
+	/// are merged by `meta.lastUpdated`. Pages are fetched as the events are
+	/// consumed and the checkpoint is saved before fetching the next page.
+	/// Versions are only reported once, even if they show up on multiple pages
+	/// or polls. Errors are yielded without ending the stream, the next poll
+	/// happens after the interval. A failure to save the checkpoint is yielded
+	/// as well, saving is tried again on the next item.
+	pub fn stream(self) -> impl Stream<Item = Result<SyncEvent<V>, Error>> + Send + 'static {
+		let checkpoint = self
+			.store
+			.as_ref()
+			.and_then(|store| store.load())
+			.unwrap_or_else(|| SyncCheckpoint { since: self.since.clone(), seen: BTreeSet::new() });
+		let state = SyncState {
+			sync: self,
+			checkpoint,
+			changed: false,
+			sources: Vec::new(),
+			skipped: BTreeSet::new(),
+			reported: HashSet::new(),
+			polled: false,
+		};
+		stream::unfold(state, |mut state| async move {
+			let result = state.next_event().await;
+			Some((result, state))
+		})
+	}
+}
+
+impl<V: FhirVersion> Debug for ChangeSync<V> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("ChangeSync")
+			.field("client", &self.client)
+			.field("types", &self.types)
+			.field("since", &self.since)
+			.field("mode", &self.mode)
+			.field("interval", &self.interval)
+			.field("count", &self.count)
+			.field("store", &self.store.as_ref().map(|_| "CheckpointStore"))
+			.finish()
+	}
+}
+
+/// Paged source of changes within a poll, oldest first.
+struct ChangeSource<V: FhirVersion> {
+	/// Fetched entries that were not processed yet.
+	entries: VecDeque<HistoryEntry<V>>,
+	/// Current page, `None` after the last page.
+	page: Option<Page<V, V::Resource>>,
+	/// Whether the entries of the current page were taken already, so the
+	/// next page needs to be fetched.
+	taken: bool,
+	/// Latest `meta.lastUpdated` of the processed entries, to order entries of
+	/// unknown time, i.e. deletions.
+	last_updated: Option<Instant>,
+}
+
+impl<V: FhirVersion> ChangeSource<V>
+where
+	(StatusCode, V::OperationOutcome): Into<Error>,
+{
+	/// Fetch pages until there are entries to process or there are no more
+	/// pages.
+	async fn fill(&mut self, mode: SyncMode) -> Result<(), Error> {
+		while self.entries.is_empty() {
+			let Some(mut page) = self.page.take() else { return Ok(()) };
+			if self.taken {
+				let Some(next_page) = page.next_page().await else { return Ok(()) };
+				page = next_page?;
+			}
+			match mode {
+				SyncMode::History => self.entries.extend(page.history_entries_owned()),
+				SyncMode::LastUpdatedSearch => {
+					let matches: Vec<V::Resource> = page.matches_owned().try_collect().await?;
+					self.entries.extend(matches.into_iter().map(|resource| HistoryEntry {
+						method: None,
+						url: None,
+						full_url: None,
+						status: None,
+						etag: None,
+						resource: Some(resource),
+					}));
+				}
+			}
+			self.page = Some(page);
+			self.taken = true;
+		}
+		Ok(())
+	}
+
+	/// Time to order the next entry by, `None` if there are no entries.
+	fn next_time(&self) -> Option<Option<&Instant>> {
+		let entry = self.entries.front()?;
+		Some(
+			entry
+				.resource
+				.as_ref()
+				.and_then(GenericResource::last_updated)
+				.or(self.last_updated.as_ref()),
+		)
+	}
+}
+
+/// State of a running synchronization.
+struct SyncState<V: FhirVersion> {
+	/// Synchronization settings.
+	sync: ChangeSync<V>,
+	/// Checkpoint after the reported events.
+	checkpoint: SyncCheckpoint,
+	/// Whether the checkpoint changed since it was last saved.
+	changed: bool,
+	/// Sources of the current poll, empty if there is none.
+	sources: Vec<ChangeSource<V>>,
+	/// Versions that were already reported before the current poll.
+	skipped: BTreeSet<String>,
+	/// Versions reported in the current poll.
+	reported: HashSet<String>,
+	/// Whether there was a poll already, so the next one needs to wait.
+	polled: bool,
+}
+
+impl<V: FhirVersion> SyncState<V>
+where
+	(StatusCode, V::OperationOutcome): Into<Error>,
+{
+	/// Get the next change event, polling as long as there are none.
+	async fn next_event(&mut self) -> Result<SyncEvent<V>, Error> {
+		loop {
+			if self.sources.is_empty() {
+				self.save_checkpoint()?;
+				if self.polled {
+					tokio::time::sleep(self.sync.interval).await;
+				}
+				self.polled = true;
+				tracing::debug!("Polling changes since {:?}", self.checkpoint.since);
+				self.sources = self.start_poll().await?;
+				self.skipped = self.checkpoint.seen.clone();
+				self.reported.clear();
+			}
+
+			match self.fill_sources().await {
+				Ok(()) => {}
+				// Saving is tried again before fetching the next pages.
+				Err(error @ Error::Checkpoint(_)) => return Err(error),
+				Err(error) => {
+					// Continue with the next poll from the checkpoint.
+					self.sources.clear();
+					return Err(error);
+				}
+			}
+
+			// Take the oldest entry of all sources, so the events stay in order.
+			let Some(source) =
+				self.sources.iter_mut().min_by(|a, b| a.next_time().cmp(&b.next_time()))
+			else {
+				// The poll is done.
+				continue;
+			};
+			let Some(entry) = source.entries.pop_front() else { continue };
+			if let Some(last_updated) =
+				entry.resource.as_ref().and_then(GenericResource::last_updated)
+			{
+				source.last_updated = Some(last_updated.clone());
+			}
+			if let Some(event) = self.process(entry) {
+				return Ok(event);
+			}
+		}
+	}
+
+	/// Save the checkpoint to the store if it changed. All events reported so
+	/// far were consumed when this is called.
+	fn save_checkpoint(&mut self) -> Result<(), Error> {
+		if !self.changed {
+			return Ok(());
+		}
+		if let Some(store) = &self.sync.store {
+			store.save(&self.checkpoint).map_err(Error::Checkpoint)?;
+		}
+		self.changed = false;
+		Ok(())
+	}
+
+	/// Fetch the next pages of the sources that have no entries left, saving
+	/// the checkpoint before. Removes the exhausted sources.
+	async fn fill_sources(&mut self) -> Result<(), Error> {
+		if self.sources.iter().all(|source| !source.entries.is_empty()) {
+			return Ok(());
+		}
+		self.save_checkpoint()?;
+		for source in &mut self.sources {
+			source.fill(self.sync.mode).await?;
+		}
+		self.sources.retain(|source| !source.entries.is_empty());
+		Ok(())
+	}
+
+	/// Send the requests of a poll and return the sources of their pages.
+	async fn start_poll(&self) -> Result<Vec<ChangeSource<V>>, Error> {
+		let pages = match self.sync.mode {
+			SyncMode::History => self.history_pages().await?,
+			SyncMode::LastUpdatedSearch => vec![self.search_page().await?],
+		};
+		Ok(pages
+			.into_iter()
+			.map(|page| ChangeSource {
+				entries: VecDeque::new(),
+				page: Some(page),
+				taken: false,
+				last_updated: self.checkpoint.since.clone(),
+			})
+			.collect())
+	}
+
+	/// Request the history since the checkpoint, oldest entries first. Returns
+	/// the first page of the system history or of each resource type.
+	async fn history_pages(&self) -> Result<Vec<Page<V, V::Resource>>, Error> {
+		let targets: Vec<_> = if self.sync.types.is_empty() {
+			vec![None]
+		} else {
+			self.sync
+				.types
+				.iter()
+				.map(|resource_type| Some((Cow::Owned(resource_type.clone()), None)))
+				.collect()
+		};
+
+		let mut pages = Vec::new();
+		for target in targets {
+			let mut request =
+				HistoryRequest::<V, V::Resource>::new(self.sync.client.clone(), target)
+					.sort("_lastUpdated");
+			if let Some(since) = &self.checkpoint.since {
+				request = request.since(since.clone());
+			}
+			if let Some(count) = self.sync.count {
+				request = request.count(count);
+			}
+			pages.push(request.send().await?);
+		}
+		Ok(pages)
+	}
+
+	/// Search for resources updated since the checkpoint, oldest first.
+	/// Returns the first page.
+	async fn search_page(&self) -> Result<Page<V, V::Resource>, Error> {
+		let mut search = SearchParameters::empty().and_raw("_sort", "_lastUpdated");
+		if !self.sync.types.is_empty() {
+			search = search.and_raw("_type", self.sync.types.join(","));
+		}
+		if let Some(since) = &self.checkpoint.since {
+			search = search.and_raw("_lastUpdated", format!("ge{}", query_value(since)?));
+		}
+		if let Some(count) = self.sync.count {
+			search = search.and_raw("_count", count);
+		}
+		self.sync.client.search_all(search).await
+	}
+
+	/// Advance the checkpoint past the entry and convert it into an event if it
+	/// was not reported yet.
+	fn process(&mut self, entry: HistoryEntry<V>) -> Option<SyncEvent<V>> {
+		let (Some(resource_type), Some(id)) = (entry.resource_type(), entry.id()) else {
+			tracing::warn!("Skipping history entry without resource type or ID");
+			return None;
+		};
+		let key =
+			format!("{resource_type}/{id}/_history/{}", entry.version_id().unwrap_or_default());
+
+		// Versions at the checkpoint instant or of unknown time will be returned again by
+		// the next poll.
+		let last_updated = entry.resource.as_ref().and_then(GenericResource::last_updated);
+		if let Some(last_updated) = last_updated {
+			if self.checkpoint.since.as_ref().map_or(true, |since| last_updated > since) {
+				self.checkpoint.since = Some(last_updated.clone());
+				self.checkpoint.seen.clear();
+			}
+		}
+		if last_updated.is_none() || last_updated == self.checkpoint.since.as_ref() {
+			self.checkpoint.seen.insert(key.clone());
+		}
+		self.changed = true;
+
+		if self.skipped.contains(&key) || !self.reported.insert(key) {
+			return None;
+		}
+		SyncEvent::from_entry(entry)
+	}
+}
+
+impl<V: FhirVersion> Client<V>
+where
+	(StatusCode, V::OperationOutcome): Into<Error>,
+{
+	/// Start building an incremental synchronization of the resources of the
+	/// given types, or all types if empty, that changed since the instant, or
+	/// all changes if `None`. The server is polled on an interval and changes
+	/// are yielded as [SyncEvent]s. Set a [CheckpointStore] to resume where a
+	/// previous synchronization stopped.
+	pub fn changes_since(
+		&self,
+		types: impl IntoIterator<Item = V::ResourceType>,
+		since: Option<Instant>,
+	) -> ChangeSync<V> {
+		ChangeSync::new(self.clone(), types, since)
+	}
+}
@@ -1,22 +1,28 @@
 //! Implementation of building batch/transaction requests and processing the
 //! response.
 
+use std::collections::HashMap;
+
+use fhir_model::{Instant, ParsedReference};
+use futures::{stream, StreamExt, TryStreamExt};
 use reqwest::{
 	header::{self, HeaderValue},
 	StatusCode,
 };
 use uuid::Uuid;
 
-use super::{Client, Error};
+use super::{misc, references::rewrite_references, Client, Error, SearchParameters};
 use crate::{
-	extensions::{BundleEntryExt, BundleEntryRequestExt, BundleExt, GenericResource},
+	extensions::{
+		BundleEntryExt, BundleEntryRequestExt, BundleEntryResponseExt, BundleExt, GenericResource,
+	},
 	version::FhirVersion,
 };
 
 /// Type alias for the `BundleEntry` type for any version.
-type BundleEntry<V> = <<V as FhirVersion>::Bundle as BundleExt>::Entry;
+pub(super) type BundleEntry<V> = <<V as FhirVersion>::Bundle as BundleExt>::Entry;
 /// Type alias for the `BundleEntryRequest` type for any version.
-type BundleEntryRequest<V> =
+pub(super) type BundleEntryRequest<V> =
 	<<<V as FhirVersion>::Bundle as BundleExt>::Entry as BundleEntryExt>::Request;
 
 /// A batch/transaction request builder.
@@ -29,6 +35,10 @@ pub struct BatchTransaction<V: FhirVersion> {
 	is_transaction: bool,
 	/// Current entries in the batch or transaction.
 	entries: Vec<Option<BundleEntry<V>>>,
+	/// Maximum number of entries per batch request.
+	max_entries: Option<usize>,
+	/// Number of batch requests to send concurrently.
+	concurrency: usize,
 }
 
 impl<V: FhirVersion> BatchTransaction<V>
@@ -38,34 +48,52 @@ where
 	/// Create new batch or transaction builder, given whether it is a
 	/// transaction.
 	pub fn new(client: Client<V>, is_transaction: bool) -> Self {
-		Self { client, is_transaction, entries: Vec::new() }
+		Self { client, is_transaction, entries: Vec::new(), max_entries: None, concurrency: 1 }
+	}
+
+	/// Set the maximum number of entries per request, as servers usually limit
+	/// the size of batches. Larger batches are split into multiple requests and
+	/// the responses merged back in the original order. Only applies to
+	/// batches, transactions are always sent as a whole to remain atomic.
+	#[must_use]
+	pub fn max_entries(mut self, max_entries: usize) -> Self {
+		self.max_entries = Some(max_entries.max(1));
+		self
+	}
+
+	/// Set the number of split batch requests to send concurrently. Defaults to
+	/// 1, i.e. sequentially.
+	#[must_use]
+	pub fn concurrency(mut self, concurrency: usize) -> Self {
+		self.concurrency = concurrency.max(1);
+		self
 	}
 
 	/// Add creation of a resource to the batch/transaction.
-	/// For advanced features like conditional creation via `ifNoneExists`,
-	/// please use `with_raw` for now.
 	///
 	/// Returns the temporary UUID to be used a reference that will be resolved
 	/// by the server for cross-referencing resources inside batch/transaction
 	/// requests.
 	pub fn create(&mut self, resource: impl Into<V::Resource>) -> String {
-		let resource = resource.into();
-		let uuid = format!("urn:uuid:{}", Uuid::new_v4());
+		let uuid = new_temp_id();
+		self.create_with_temp_id(resource.into(), uuid.clone());
+		uuid
+	}
 
+	/// Add creation of a resource to the batch/transaction, using the given
+	/// temporary UUID as full URL.
+	pub(super) fn create_with_temp_id(&mut self, resource: V::Resource, uuid: String) {
 		let entry = BundleEntry::<V>::empty()
-			.with_full_url(uuid.clone())
+			.with_full_url(uuid)
 			.with_request(BundleEntryRequest::<V>::make_post(
 				resource.resource_type_str().to_owned(),
 			))
 			.with_resource(resource);
 
 		self.entries.push(Some(entry));
-		uuid
 	}
 
 	/// Add update of a resource to the batch/transaction.
-	/// For advanced features like conditional creation, please use `with_raw`
-	/// for now.
 	pub fn update(
 		&mut self,
 		resource: impl Into<V::Resource>,
@@ -111,17 +139,231 @@ where
 		self.entries.push(Some(entry));
 	}
 
+	/// Add retrieval of a specific version of a resource to the
+	/// batch/transaction.
+	pub fn read_version(&mut self, resource_type: V::ResourceType, id: &str, version_id: &str) {
+		let url = format!("{resource_type}/{id}/_history/{version_id}");
+
+		let entry = BundleEntry::<V>::empty().with_request(BundleEntryRequest::<V>::make_get(url));
+
+		self.entries.push(Some(entry));
+	}
+
+	/// Add conditional creation of a resource to the batch/transaction. The
+	/// resource is only created if no resource matches the search parameters
+	/// (`ifNoneExist`).
+	///
+	/// Returns the temporary UUID to be used as reference, just like
+	/// [Self::create].
+	pub fn create_if_none_exist(
+		&mut self,
+		resource: impl Into<V::Resource>,
+		search: SearchParameters,
+	) -> String {
+		let resource = resource.into();
+		let uuid = new_temp_id();
+		let if_none_exist = self.query_string(resource.resource_type_str(), search);
+
+		let entry = BundleEntry::<V>::empty()
+			.with_full_url(uuid.clone())
+			.with_request(
+				BundleEntryRequest::<V>::make_post(resource.resource_type_str().to_owned())
+					.with_if_none_exist(if_none_exist),
+			)
+			.with_resource(resource);
+
+		self.entries.push(Some(entry));
+		uuid
+	}
+
+	/// Add conditional update of a resource to the batch/transaction, updating
+	/// the resource matching the search parameters instead of using the
+	/// resource's ID.
+	pub fn update_conditional(
+		&mut self,
+		resource: impl Into<V::Resource>,
+		search: SearchParameters,
+	) {
+		let resource = resource.into();
+		let url = self.search_url(resource.resource_type_str(), search);
+
+		let entry = BundleEntry::<V>::empty()
+			.with_request(BundleEntryRequest::<V>::make_put(url))
+			.with_resource(resource);
+
+		self.entries.push(Some(entry));
+	}
+
+	/// Add patch of a resource to the batch/transaction. The patch is the
+	/// `Parameters` resource of a FHIRPath Patch or the `Binary` resource of
+	/// a JSON Patch. Not supported in STU3.
+	pub fn patch(
+		&mut self,
+		resource_type: V::ResourceType,
+		id: &str,
+		patch: impl Into<V::Resource>,
+	) -> Result<(), Error> {
+		let url = format!("{resource_type}/{id}");
+		let request = BundleEntryRequest::<V>::make_patch(url).ok_or_else(|| {
+			Error::Unsupported(format!("PATCH in batch/transaction in FHIR version {}", V::VERSION))
+		})?;
+
+		let entry = BundleEntry::<V>::empty().with_request(request).with_resource(patch.into());
+
+		self.entries.push(Some(entry));
+		Ok(())
+	}
+
+	/// Add conditional deletion of the resources matching the search parameters
+	/// to the batch/transaction.
+	pub fn delete_conditional(&mut self, resource_type: V::ResourceType, search: SearchParameters) {
+		let url = self.search_url(resource_type.as_ref(), search);
+
+		let entry =
+			BundleEntry::<V>::empty().with_request(BundleEntryRequest::<V>::make_delete(url));
+
+		self.entries.push(Some(entry));
+	}
+
+	/// Add conditional retrieval of a resource to the batch/transaction. The
+	/// resource is only returned if its version differs from `if_none_match`
+	/// (`ifNoneMatch`) or it changed since `if_modified_since`
+	/// (`ifModifiedSince`).
+	pub fn read_conditional(
+		&mut self,
+		resource_type: V::ResourceType,
+		id: &str,
+		if_none_match: Option<&str>,
+		if_modified_since: Option<Instant>,
+	) {
+		let url = format!("{resource_type}/{id}");
+
+		let mut request = BundleEntryRequest::<V>::make_get(url);
+		if let Some(version_id) = if_none_match {
+			request = request.with_if_none_match(format!("W/\"{version_id}\""));
+		}
+		if let Some(if_modified_since) = if_modified_since {
+			request = request.with_if_modified_since(if_modified_since);
+		}
+
+		let entry = BundleEntry::<V>::empty().with_request(request);
+
+		self.entries.push(Some(entry));
+	}
+
+	/// Add a search to the batch/transaction. The response entry contains the
+	/// search result `Bundle`.
+	pub fn search(&mut self, resource_type: V::ResourceType, search: SearchParameters) {
+		let url = self.search_url(resource_type.as_ref(), search);
+
+		let entry = BundleEntry::<V>::empty().with_request(BundleEntryRequest::<V>::make_get(url));
+
+		self.entries.push(Some(entry));
+	}
+
+	/// Rewrite references in all queued resources that point to other queued
+	/// resources of [Self::create] or [Self::create_if_none_exist], so that
+	/// they use the temporary UUID instead. Queued resources are matched by
+	/// their (local) ID, so resources can reference each other via
+	/// `ResourceType/id` before the server assigned any IDs.
+	///
+	/// Returns the number of rewritten references.
+	pub fn rewrite_references(&mut self) -> Result<usize, Error> {
+		let base_url = self.client.url(&[]);
+		let base_url = base_url.as_str().trim_end_matches('/');
+		let mut temp_ids = HashMap::new();
+		for entry in self.entries.iter().flatten() {
+			let Some(full_url) = entry.full_url().filter(|url| url.starts_with("urn:uuid:")) else {
+				continue;
+			};
+			let Some(resource) = entry.resource() else { continue };
+			let Some(id) = resource.id() else { continue };
+			let reference = format!("{}/{id}", resource.resource_type_str());
+			temp_ids.insert(format!("{base_url}/{reference}"), full_url.clone());
+			temp_ids.insert(reference, full_url.clone());
+		}
+		if temp_ids.is_empty() {
+			return Ok(0);
+		}
+
+		let mut rewritten = 0;
+		for slot in &mut self.entries {
+			let Some(resource) = slot.as_ref().and_then(BundleEntryExt::resource) else {
+				continue;
+			};
+			let mut value = serde_json::to_value(resource)?;
+			let count = rewrite_references(&mut value, &temp_ids);
+			if count > 0 {
+				let resource: V::Resource = serde_json::from_value(value)?;
+				*slot = slot.take().map(|entry| entry.with_resource(resource));
+				rewritten += count;
+			}
+		}
+		tracing::debug!("Rewrote {rewritten} references to temporary UUIDs");
+		Ok(rewritten)
+	}
+
+	/// Make the relative search URL for the resource type and search
+	/// parameters, e.g. for conditional requests.
+	fn search_url(&self, resource_type: &str, search: SearchParameters) -> String {
+		format!("{resource_type}?{}", self.query_string(resource_type, search))
+	}
+
+	/// Make the URL-encoded query string of the search parameters.
+	fn query_string(&self, resource_type: &str, search: SearchParameters) -> String {
+		let mut url = self.client.url(&[resource_type]);
+		url.query_pairs_mut().extend_pairs(search.into_queries()).finish();
+		url.query().unwrap_or_default().to_owned()
+	}
+
 	/// Add a raw Bundle entry for more advanced queries.
 	pub fn with_raw(&mut self, entry: BundleEntry<V>) {
 		self.entries.push(Some(entry));
 	}
 
 	/// Send the batch or transaction to the server and receive the response.
-	pub async fn send(self) -> Result<V::Bundle, Error> {
-		let bundle = if self.is_transaction {
-			V::Bundle::make_transaction(self.entries)
+	/// The response entries are paired with the request entries, use
+	/// [BatchResponse::into_result] to make sure all of them succeeded.
+	pub async fn send(mut self) -> Result<BatchResponse<V>, Error> {
+		let entries = std::mem::take(&mut self.entries);
+		let max_entries = match self.max_entries {
+			Some(max_entries) if !self.is_transaction && entries.len() > max_entries => max_entries,
+			_ => return self.send_entries(entries).await,
+		};
+
+		let mut chunks = Vec::with_capacity(entries.len().div_ceil(max_entries));
+		let mut entries = entries.into_iter();
+		loop {
+			let chunk: Vec<_> = entries.by_ref().take(max_entries).collect();
+			if chunk.is_empty() {
+				break;
+			}
+			chunks.push(chunk);
+		}
+		tracing::debug!("Splitting batch into {} requests", chunks.len());
+
+		let responses: Vec<BatchResponse<V>> = stream::iter(chunks)
+			.map(|chunk| self.send_entries(chunk))
+			.buffered(self.concurrency)
+			.try_collect()
+			.await?;
+		let mut responses = responses.into_iter();
+		let mut merged = responses.next().unwrap_or_default();
+		for response in responses {
+			merged.extend(response);
+		}
+		Ok(merged)
+	}
+
+	/// Send the entries as one batch or transaction.
+	async fn send_entries(
+		&self,
+		entries: Vec<Option<BundleEntry<V>>>,
+	) -> Result<BatchResponse<V>, Error> {
+		let mut bundle = if self.is_transaction {
+			V::Bundle::make_transaction(entries)
 		} else {
-			V::Bundle::make_batch(self.entries)
+			V::Bundle::make_batch(entries)
 		};
 
 		let url = self.client.url(&[]);
@@ -136,10 +378,230 @@ where
 
 		let response = self.client.run_request(request).await?;
 		if response.status().is_success() {
-			let bundle: V::Bundle = response.json().await?;
-			Ok(bundle)
+			let response_bundle: V::Bundle = response.json().await?;
+			let response = BatchResponse::new(bundle.take_entries(), response_bundle);
+			self.invalidate_written(&response);
+			Ok(response)
 		} else {
 			Err(Error::from_response::<V>(response).await)
 		}
 	}
+
+	/// Remove the cached responses of the resources the entries wrote to. The
+	/// targets of conditional writes are taken from the response location.
+	fn invalidate_written(&self, response: &BatchResponse<V>) {
+		for entry in response.entries() {
+			let Some(request) = entry.request.as_ref().and_then(BundleEntryExt::request) else {
+				continue;
+			};
+			if !["PUT", "PATCH", "DELETE"].contains(&request.method().to_uppercase().as_str()) {
+				continue;
+			}
+			let target =
+				if request.url().contains('?') { entry.location() } else { Some(request.url()) };
+			let Some(target) = target.map(ParsedReference::new) else { continue };
+			if let (Some(resource_type), Some(id)) = (target.resource_type(), target.id()) {
+				self.client.invalidate_cached(&self.client.url(&[resource_type, id]));
+			}
+		}
+	}
+}
+
+/// Generate a new temporary `urn:uuid` to reference resources created in the
+/// batch/transaction.
+pub(super) fn new_temp_id() -> String {
+	format!("urn:uuid:{}", Uuid::new_v4())
+}
+
+/// Response of a batch/transaction request, pairing every request entry with
+/// its response entry.
+#[derive(Debug, Clone)]
+pub struct BatchResponse<V: FhirVersion> {
+	/// Paired request and response entries, in the order of the request.
+	entries: Vec<BatchResponseEntry<V>>,
+	/// Temporary `urn:uuid`s of created resources mapped to the server-assigned
+	/// IDs.
+	temp_ids: HashMap<String, String>,
+}
+
+impl<V: FhirVersion> Default for BatchResponse<V> {
+	fn default() -> Self {
+		Self { entries: Vec::new(), temp_ids: HashMap::new() }
+	}
+}
+
+impl<V: FhirVersion> BatchResponse<V>
+where
+	(StatusCode, V::OperationOutcome): Into<Error>,
+{
+	/// Pair the request entries with the entries of the response `Bundle`.
+	pub(crate) fn new(requests: Vec<Option<BundleEntry<V>>>, mut response: V::Bundle) -> Self {
+		let mut responses = response.take_entries().into_iter();
+		let entries: Vec<_> = requests
+			.into_iter()
+			.map(|request| BatchResponseEntry { request, response: responses.next().flatten() })
+			.collect();
+
+		let temp_ids = entries
+			.iter()
+			.filter_map(|entry| {
+				let full_url = entry.request.as_ref()?.full_url()?;
+				let id = entry.id()?;
+				full_url.starts_with("urn:uuid:").then(|| (full_url.clone(), id.to_owned()))
+			})
+			.collect();
+
+		Self { entries, temp_ids }
+	}
+
+	/// Get the paired request and response entries, in the order of the
+	/// request.
+	#[must_use]
+	pub fn entries(&self) -> &[BatchResponseEntry<V>] {
+		&self.entries
+	}
+
+	/// Turn into the paired request and response entries, in the order of the
+	/// request.
+	#[must_use]
+	pub fn into_entries(self) -> Vec<BatchResponseEntry<V>> {
+		self.entries
+	}
+
+	/// Get the result of every entry, in the order of the request.
+	pub fn results(&self) -> impl Iterator<Item = Result<Option<&V::Resource>, Error>> + '_ {
+		self.entries.iter().map(BatchResponseEntry::result)
+	}
+
+	/// Whether all entries succeeded.
+	#[must_use]
+	pub fn is_success(&self) -> bool {
+		self.entries.iter().all(BatchResponseEntry::is_success)
+	}
+
+	/// Get the server-assigned ID of a resource created with the given
+	/// temporary `urn:uuid`, as returned by [BatchTransaction::create].
+	#[must_use]
+	pub fn resolve_temp_id(&self, temp_id: &str) -> Option<&str> {
+		self.temp_ids.get(temp_id).map(String::as_str)
+	}
+
+	/// Get all temporary `urn:uuid`s of created resources mapped to the
+	/// server-assigned IDs.
+	#[must_use]
+	pub const fn temp_ids(&self) -> &HashMap<String, String> {
+		&self.temp_ids
+	}
+
+	/// Append the entries of another response, e.g. of a split batch.
+	fn extend(&mut self, other: Self) {
+		self.entries.extend(other.entries);
+		self.temp_ids.extend(other.temp_ids);
+	}
+
+	/// Return the error of the first failed entry, if any entry failed.
+	pub fn into_result(self) -> Result<Self, Error> {
+		if let Some(entry) = self.entries.iter().find(|entry| !entry.is_success()) {
+			entry.result()?;
+		}
+		Ok(self)
+	}
+}
+
+/// Request entry of a batch/transaction paired with its response entry.
+#[derive(Debug, Clone)]
+pub struct BatchResponseEntry<V: FhirVersion> {
+	/// The request entry as it was sent.
+	pub request: Option<BundleEntry<V>>,
+	/// The corresponding entry of the response `Bundle`.
+	pub response: Option<BundleEntry<V>>,
+}
+
+impl<V: FhirVersion> BatchResponseEntry<V>
+where
+	(StatusCode, V::OperationOutcome): Into<Error>,
+{
+	/// Get the response status code, if present and valid.
+	#[must_use]
+	pub fn status(&self) -> Option<StatusCode> {
+		let status = self.response.as_ref()?.response()?.status();
+		status.split_whitespace().next()?.parse().ok()
+	}
+
+	/// Whether the entry succeeded, i.e. responded with a successful status code.
+	#[must_use]
+	pub fn is_success(&self) -> bool {
+		self.status().is_some_and(|status| status.is_success())
+	}
+
+	/// Get the location of the response, e.g. the URL of a created resource.
+	#[must_use]
+	pub fn location(&self) -> Option<&str> {
+		self.response.as_ref()?.response()?.location()
+	}
+
+	/// Get the ETag of the response.
+	#[must_use]
+	pub fn etag(&self) -> Option<&str> {
+		self.response.as_ref()?.response()?.etag()
+	}
+
+	/// Get the ID of the affected resource, from the location or the returned
+	/// resource.
+	#[must_use]
+	pub fn id(&self) -> Option<&str> {
+		self.location()
+			.and_then(|location| ParsedReference::new(location).id())
+			.or_else(|| self.resource()?.id())
+	}
+
+	/// Get the version ID of the affected resource, from the ETag, the
+	/// `_history` part of the location or the returned resource.
+	#[must_use]
+	pub fn version_id(&self) -> Option<&str> {
+		self.etag()
+			.and_then(misc::parse_etag_str)
+			.or_else(|| {
+				let mut segments = self.location()?.rsplit('/');
+				let version_id = segments.next()?;
+				(segments.next()? == "_history").then_some(version_id)
+			})
+			.or_else(|| self.resource()?.version_id())
+	}
+
+	/// Get the returned resource.
+	#[must_use]
+	pub fn resource(&self) -> Option<&V::Resource> {
+		self.response.as_ref()?.resource()
+	}
+
+	/// Get the returned `OperationOutcome`, either from the response's outcome
+	/// or as returned resource.
+	#[must_use]
+	pub fn outcome(&self) -> Option<V::OperationOutcome> {
+		let entry = self.response.as_ref()?;
+		entry
+			.response()
+			.and_then(BundleEntryResponseExt::outcome)
+			.or_else(|| entry.resource())
+			.and_then(|resource| V::OperationOutcome::try_from(resource.clone()).ok())
+	}
+
+	/// Get the result of the entry, containing the returned resource on success.
+	pub fn result(&self) -> Result<Option<&V::Resource>, Error> {
+		if self.is_success() {
+			return Ok(self.resource());
+		}
+
+		let status = self.status().unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+		if let Some(outcome) = self.outcome() {
+			return Err((status, outcome).into());
+		}
+		let message = self
+			.response
+			.as_ref()
+			.and_then(BundleEntryExt::response)
+			.map_or("Missing response entry", BundleEntryResponseExt::status);
+		Err(Error::Response(status, message.to_owned()))
+	}
 }
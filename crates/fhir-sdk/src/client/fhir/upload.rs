@@ -0,0 +1,222 @@
+//! Upload of resource graphs in dependency order.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use reqwest::{StatusCode, Url};
+use serde::Serialize;
+use serde_json::Value;
+
+use super::{
+	references::{rewrite_references, visit_references},
+	transaction::new_temp_id,
+	Client, Error,
+};
+use crate::{extensions::GenericResource, version::FhirVersion};
+
+/// Builder for the upload of resources that reference each other via their
+/// local IDs, e.g. an Observation referencing `Patient/local-1` or
+/// `{base_url}/Patient/local-1`, where the Patient with ID `local-1` is part of
+/// the upload as well. The local IDs are not sent, the server assigns the IDs.
+///
+/// By default, all resources are created in one transaction, with the
+/// references rewritten to temporary UUIDs that the server resolves. For
+/// servers without transaction support, the resources can be created one by
+/// one in dependency order instead.
+#[derive(Debug, Clone)]
+#[must_use = "You probably want to send the upload"]
+pub struct GraphUpload<V: FhirVersion> {
+	/// The FHIR client.
+	client: Client<V>,
+	/// The resources to upload.
+	resources: Vec<V::Resource>,
+	/// Whether to create the resources one by one instead of in a transaction.
+	sequential: bool,
+}
+
+impl<V: FhirVersion> GraphUpload<V>
+where
+	(StatusCode, V::OperationOutcome): Into<Error>,
+{
+	/// Start building a new upload of the given resources.
+	pub const fn new(client: Client<V>, resources: Vec<V::Resource>) -> Self {
+		Self { client, resources, sequential: false }
+	}
+
+	/// Create the resources one by one, each after the resources it references,
+	/// instead of in one transaction. References are rewritten to the
+	/// server-assigned IDs. Fails on reference cycles.
+	pub const fn sequential(mut self) -> Self {
+		self.sequential = true;
+		self
+	}
+
+	/// Upload the resources. Returns a map from the local reference, e.g.
+	/// `Patient/local-1`, to the reference on the server, e.g. `Patient/123`.
+	pub async fn send(self) -> Result<HashMap<String, String>, Error> {
+		if self.sequential {
+			self.send_sequential().await
+		} else {
+			self.send_transaction().await
+		}
+	}
+
+	/// Upload all resources in one transaction.
+	async fn send_transaction(self) -> Result<HashMap<String, String>, Error> {
+		let base_url = self.client.url(&[]);
+		let temp_ids: Vec<(Option<String>, String)> = self
+			.resources
+			.iter()
+			.map(|resource| (local_reference(resource), new_temp_id()))
+			.collect();
+		let mut rewrites = HashMap::new();
+		for (reference, temp_id) in &temp_ids {
+			if let Some(reference) = reference {
+				insert_rewrite(&mut rewrites, &base_url, reference, temp_id.clone());
+			}
+		}
+
+		let mut transaction = self.client.transaction();
+		for (resource, (_, temp_id)) in self.resources.iter().zip(&temp_ids) {
+			let value = prepare_creation(resource, &rewrites)?;
+			transaction.create_with_temp_id(serde_json::from_value(value)?, temp_id.clone());
+		}
+		let response = transaction.send().await?.into_result()?;
+
+		let mut references = HashMap::new();
+		for (reference, temp_id) in temp_ids {
+			let Some(reference) = reference else { continue };
+			let Some((resource_type, _)) = reference.split_once('/') else { continue };
+			let Some(id) = response.resolve_temp_id(&temp_id) else { continue };
+			let server_reference = format!("{resource_type}/{id}");
+			references.insert(reference, server_reference);
+		}
+		Ok(references)
+	}
+
+	/// Create the resources one by one in dependency order.
+	async fn send_sequential(self) -> Result<HashMap<String, String>, Error> {
+		let base_url = self.client.url(&[]);
+		let mut references = HashMap::new();
+		let mut rewrites = HashMap::new();
+		let order = self.dependency_order(&base_url)?;
+		for resource in order.into_iter().filter_map(|index| self.resources.get(index)) {
+			let value = prepare_creation(resource, &rewrites)?;
+			let resource_type = resource.resource_type_str();
+			let (id, _version_id) = self.client.create_generic(resource_type, &value).await?;
+			tracing::debug!("Created {resource_type}/{id}");
+			if let Some(reference) = local_reference(resource) {
+				let server_reference = format!("{resource_type}/{id}");
+				insert_rewrite(&mut rewrites, &base_url, &reference, server_reference.clone());
+				references.insert(reference, server_reference);
+			}
+		}
+		Ok(references)
+	}
+
+	/// Sort the resources topologically, so that every resource comes after the
+	/// resources it references.
+	fn dependency_order(&self, base_url: &Url) -> Result<Vec<usize>, Error> {
+		let base_url = format!("{}/", base_url.as_str().trim_end_matches('/'));
+		let indices: HashMap<String, usize> = self
+			.resources
+			.iter()
+			.enumerate()
+			.filter_map(|(index, resource)| Some((local_reference(resource)?, index)))
+			.collect();
+
+		let mut dependencies = Vec::with_capacity(self.resources.len());
+		for (index, resource) in self.resources.iter().enumerate() {
+			let mut references = BTreeSet::new();
+			visit_references(&mut serde_json::to_value(resource)?, None, &mut |reference| {
+				references.insert(reference.clone());
+			});
+			let resource_dependencies: BTreeSet<usize> = references
+				.iter()
+				.map(|reference| reference.strip_prefix(&base_url).unwrap_or(reference))
+				.filter_map(|reference| indices.get(reference).copied())
+				.filter(|dependency| *dependency != index)
+				.collect();
+			dependencies.push(resource_dependencies);
+		}
+
+		let mut order = Vec::with_capacity(self.resources.len());
+		let mut done = HashSet::new();
+		while order.len() < self.resources.len() {
+			let ready: Vec<usize> = dependencies
+				.iter()
+				.enumerate()
+				.filter(|(index, resource_dependencies)| {
+					!done.contains(index)
+						&& resource_dependencies.iter().all(|dependency| done.contains(dependency))
+				})
+				.map(|(index, _)| index)
+				.collect();
+			if ready.is_empty() {
+				return Err(Error::ReferenceCycle(self.cycle(&dependencies, done)));
+			}
+			done.extend(ready.iter().copied());
+			order.extend(ready);
+		}
+		Ok(order)
+	}
+
+	/// Find the resources that are part of reference cycles, given the
+	/// resources that could already be sorted.
+	fn cycle(&self, dependencies: &[BTreeSet<usize>], mut done: HashSet<usize>) -> Vec<String> {
+		// Drop resources that no remaining resource depends on, as they are only
+		// blocked by the cycle, but not part of it.
+		loop {
+			let unused: Vec<usize> = (0 .. dependencies.len())
+				.filter(|index| {
+					!done.contains(index)
+						&& !dependencies.iter().enumerate().any(|(other, other_dependencies)| {
+							!done.contains(&other) && other_dependencies.contains(index)
+						})
+				})
+				.collect();
+			if unused.is_empty() {
+				break;
+			}
+			done.extend(unused);
+		}
+
+		self.resources
+			.iter()
+			.enumerate()
+			.filter(|(index, _)| !done.contains(index))
+			.filter_map(|(_, resource)| local_reference(resource))
+			.collect()
+	}
+}
+
+/// Get the local reference to the resource, e.g. `Patient/local-1`.
+fn local_reference<R: GenericResource>(resource: &R) -> Option<String> {
+	Some(format!("{}/{}", resource.resource_type_str(), resource.id()?))
+}
+
+/// Add the rewrite of the local reference to the target, both in relative form
+/// and absolute against the base URL.
+fn insert_rewrite(
+	rewrites: &mut HashMap<String, String>,
+	base_url: &Url,
+	reference: &str,
+	target: String,
+) {
+	let base_url = base_url.as_str().trim_end_matches('/');
+	rewrites.insert(format!("{base_url}/{reference}"), target.clone());
+	rewrites.insert(reference.to_owned(), target);
+}
+
+/// Serialize the resource for creation, rewriting its references and removing
+/// the local ID, as the server assigns the ID.
+fn prepare_creation<R: Serialize>(
+	resource: &R,
+	rewrites: &HashMap<String, String>,
+) -> Result<Value, Error> {
+	let mut value = serde_json::to_value(resource)?;
+	rewrite_references(&mut value, rewrites);
+	if let Value::Object(object) = &mut value {
+		object.remove("id");
+	}
+	Ok(value)
+}
@@ -0,0 +1,329 @@
+//! Recording and replaying HTTP interactions as fixtures for offline tests.
+
+use std::{
+	collections::BTreeMap,
+	fmt::Write as _,
+	path::{Path, PathBuf},
+	sync::Mutex,
+};
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use reqwest::{
+	header::{self, HeaderMap, HeaderName, HeaderValue},
+	StatusCode, Url,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{Error, Middleware, Next};
+
+/// A recorded request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedRequest {
+	/// HTTP method.
+	pub method: String,
+	/// URL path.
+	pub path: String,
+	/// Decoded query pairs, sorted to ignore their order.
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub query: Vec<(String, String)>,
+	/// Recorded subset of the headers.
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	pub headers: BTreeMap<String, String>,
+	/// Request body, if any.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub body: Option<String>,
+	/// Whether the body is base64 encoded, because it is not valid UTF-8.
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	pub body_base64: bool,
+}
+
+impl RecordedRequest {
+	/// Record the request with the subset of headers.
+	fn new(request: &reqwest::Request, headers: &[HeaderName]) -> Self {
+		let body = request.body().and_then(reqwest::Body::as_bytes).map(encode_body);
+		Self {
+			method: request.method().to_string(),
+			path: request.url().path().to_owned(),
+			query: normalized_query(request.url()),
+			headers: recorded_headers(request.headers(), headers),
+			body_base64: body.as_ref().is_some_and(|(_, base64)| *base64),
+			body: body.map(|(body, _)| body),
+		}
+	}
+
+	/// Whether the request matches the recorded one by method, path and query.
+	fn matches(&self, request: &reqwest::Request) -> bool {
+		self.method == request.method().as_str()
+			&& self.path == request.url().path()
+			&& self.query == normalized_query(request.url())
+	}
+}
+
+/// A recorded response.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedResponse {
+	/// Status code.
+	pub status: u16,
+	/// Recorded subset of the headers.
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	pub headers: BTreeMap<String, String>,
+	/// Response body.
+	#[serde(default, skip_serializing_if = "String::is_empty")]
+	pub body: String,
+	/// Whether the body is base64 encoded, because it is not valid UTF-8.
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	pub body_base64: bool,
+}
+
+impl RecordedResponse {
+	/// Convert the recorded response back to a response.
+	fn to_response(&self) -> Result<reqwest::Response, Error> {
+		let body = if self.body_base64 {
+			STANDARD
+				.decode(&self.body)
+				.map_err(|err| Error::Fixture(format!("Invalid base64 body: {err}")))?
+		} else {
+			self.body.clone().into_bytes()
+		};
+		let mut response = http::Response::new(body);
+		*response.status_mut() = StatusCode::from_u16(self.status)
+			.map_err(|_| Error::Fixture(format!("Invalid status code {}", self.status)))?;
+		for (name, value) in &self.headers {
+			let name = HeaderName::from_bytes(name.as_bytes())
+				.map_err(|_| Error::Fixture(format!("Invalid header name `{name}`")))?;
+			let value = HeaderValue::from_str(value)
+				.map_err(|_| Error::Fixture(format!("Invalid value for header `{name}`")))?;
+			response.headers_mut().insert(name, value);
+		}
+		Ok(reqwest::Response::from(response))
+	}
+}
+
+/// A recorded request/response pair.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedInteraction {
+	/// The request.
+	pub request: RecordedRequest,
+	/// The response.
+	pub response: RecordedResponse,
+}
+
+/// Middleware recording all requests and responses to a JSON fixture file,
+/// e.g. while running tests against a real server. Replay them with a
+/// [FixtureReplayer]. Register it via
+/// [`ClientBuilder::middleware`](super::ClientBuilder::middleware).
+///
+/// The file is rewritten after every request. Bodies that are not valid UTF-8
+/// are stored base64 encoded. Only a subset of the headers is recorded, by
+/// default `Content-Type`, `ETag`, `Last-Modified` and `Location`. Requests
+/// reaching the recorder already carry the `Authorization` header of the
+/// request settings and auth callback, but it is only recorded when added via
+/// [FixtureRecorder::header].
+#[derive(Debug)]
+pub struct FixtureRecorder {
+	/// Path of the fixture file.
+	path: PathBuf,
+	/// Headers to record.
+	headers: Vec<HeaderName>,
+	/// Recorded interactions.
+	interactions: Mutex<Vec<RecordedInteraction>>,
+	/// Lock held while writing the file, so that the writes happen in the
+	/// order of the recordings.
+	file: tokio::sync::Mutex<()>,
+}
+
+impl FixtureRecorder {
+	/// Create a new recorder, writing to the file at the path.
+	#[must_use]
+	pub fn new(path: impl Into<PathBuf>) -> Self {
+		Self {
+			path: path.into(),
+			headers: vec![
+				header::CONTENT_TYPE,
+				header::ETAG,
+				header::LAST_MODIFIED,
+				header::LOCATION,
+			],
+			interactions: Mutex::new(Vec::new()),
+			file: tokio::sync::Mutex::new(()),
+		}
+	}
+
+	/// Additionally record the header of requests and responses.
+	#[must_use]
+	pub fn header(mut self, name: HeaderName) -> Self {
+		self.headers.push(name);
+		self
+	}
+
+	/// Get the interactions recorded so far.
+	#[must_use]
+	pub fn interactions(&self) -> Vec<RecordedInteraction> {
+		self.lock().clone()
+	}
+
+	/// Lock the recorded interactions.
+	fn lock(&self) -> std::sync::MutexGuard<'_, Vec<RecordedInteraction>> {
+		#[allow(clippy::expect_used)] // only happens on panics, so we can panic again.
+		self.interactions.lock().expect("mutex poisened")
+	}
+}
+
+#[async_trait]
+impl Middleware for FixtureRecorder {
+	async fn handle(
+		&self,
+		request: reqwest::Request,
+		next: Next<'_>,
+	) -> Result<reqwest::Response, Error> {
+		let recorded_request = RecordedRequest::new(&request, &self.headers);
+		let response = next.run(request).await?;
+
+		let status = response.status();
+		let headers = response.headers().clone();
+		let body = response.bytes().await?;
+		let (recorded_body, body_base64) = encode_body(&body);
+		let recorded_response = RecordedResponse {
+			status: status.as_u16(),
+			headers: recorded_headers(&headers, &self.headers),
+			body: recorded_body,
+			body_base64,
+		};
+
+		let _file = self.file.lock().await;
+		let json = {
+			let mut interactions = self.lock();
+			interactions.push(RecordedInteraction {
+				request: recorded_request,
+				response: recorded_response,
+			});
+			serde_json::to_string_pretty(&*interactions)?
+		};
+		tokio::fs::write(&self.path, json).await.map_err(|err| {
+			Error::Fixture(format!("Failed writing fixture file {}: {err}", self.path.display()))
+		})?;
+
+		let mut response = http::Response::new(body);
+		*response.status_mut() = status;
+		*response.headers_mut() = headers;
+		Ok(reqwest::Response::from(response))
+	}
+}
+
+/// Middleware answering requests with the interactions recorded by a
+/// [FixtureRecorder], without sending any requests. Register it via
+/// [`ClientBuilder::middleware`](super::ClientBuilder::middleware).
+///
+/// Requests are matched by method, path and query, ignoring the order of query
+/// parameters. Each recorded interaction is used once, in the recorded order.
+/// Requests without matching interaction fail with
+/// [`Error::UnmatchedFixture`].
+#[derive(Debug)]
+pub struct FixtureReplayer {
+	/// Recorded interactions.
+	interactions: Vec<RecordedInteraction>,
+	/// Whether the interactions were used already.
+	used: Mutex<Vec<bool>>,
+}
+
+impl FixtureReplayer {
+	/// Create a new replayer of the interactions.
+	#[must_use]
+	pub fn new(interactions: Vec<RecordedInteraction>) -> Self {
+		let used = Mutex::new(vec![false; interactions.len()]);
+		Self { interactions, used }
+	}
+
+	/// Load the interactions from the fixture file.
+	pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+		let path = path.as_ref();
+		let json = std::fs::read_to_string(path).map_err(|err| {
+			Error::Fixture(format!("Failed reading fixture file {}: {err}", path.display()))
+		})?;
+		Ok(Self::new(serde_json::from_str(&json)?))
+	}
+
+	/// Get the interactions that were not replayed yet.
+	#[must_use]
+	pub fn unused(&self) -> Vec<RecordedInteraction> {
+		self.lock()
+			.iter()
+			.zip(&self.interactions)
+			.filter(|(used, _)| !**used)
+			.map(|(_, interaction)| interaction.clone())
+			.collect()
+	}
+
+	/// Lock the usage flags.
+	fn lock(&self) -> std::sync::MutexGuard<'_, Vec<bool>> {
+		#[allow(clippy::expect_used)] // only happens on panics, so we can panic again.
+		self.used.lock().expect("mutex poisened")
+	}
+}
+
+#[async_trait]
+impl Middleware for FixtureReplayer {
+	async fn handle(
+		&self,
+		request: reqwest::Request,
+		_next: Next<'_>,
+	) -> Result<reqwest::Response, Error> {
+		let interaction = {
+			let mut used = self.lock();
+			let found = used
+				.iter_mut()
+				.zip(&self.interactions)
+				.find(|(used, interaction)| !**used && interaction.request.matches(&request));
+			found.map(|(used, interaction)| {
+				*used = true;
+				interaction
+			})
+		};
+
+		match interaction {
+			Some(interaction) => interaction.response.to_response(),
+			None => {
+				let mut message = format!("{} {}", request.method(), request.url());
+				message.push_str("\nUnused recorded requests:");
+				for interaction in self.unused() {
+					let request = &interaction.request;
+					_ = write!(
+						message,
+						"\n- {} {} {:?}",
+						request.method, request.path, request.query
+					);
+				}
+				Err(Error::UnmatchedFixture(message))
+			}
+		}
+	}
+}
+
+/// The body as string and whether it had to be base64 encoded, because it is
+/// not valid UTF-8.
+fn encode_body(body: &[u8]) -> (String, bool) {
+	match std::str::from_utf8(body) {
+		Ok(body) => (body.to_owned(), false),
+		Err(_) => (STANDARD.encode(body), true),
+	}
+}
+
+/// Decoded query pairs of the URL, sorted.
+fn normalized_query(url: &Url) -> Vec<(String, String)> {
+	let mut query: Vec<_> =
+		url.query_pairs().map(|(key, value)| (key.into(), value.into())).collect();
+	query.sort();
+	query
+}
+
+/// The subset of the headers to record.
+fn recorded_headers(headers: &HeaderMap, names: &[HeaderName]) -> BTreeMap<String, String> {
+	names
+		.iter()
+		.filter_map(|name| {
+			let value = headers.get(name)?.to_str().ok()?;
+			Some((name.as_str().to_owned(), value.to_owned()))
+		})
+		.collect()
+}
@@ -0,0 +1,134 @@
+//! Client-side rate and concurrency limiting of requests.
+
+use std::{
+	sync::Mutex,
+	time::{Duration, Instant},
+};
+
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Metrics of the request limiting, see
+/// [`Client::limit_metrics`](super::Client::limit_metrics).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LimitMetrics {
+	/// Number of requests that passed the limits.
+	pub requests: u64,
+	/// Number of requests that had to wait for the rate or concurrency limit.
+	pub queued: u64,
+	/// Total time requests waited for the limits.
+	pub total_queue_time: Duration,
+	/// Longest time a single request waited for the limits.
+	pub max_queue_time: Duration,
+}
+
+/// Token bucket for rate limiting.
+#[derive(Debug)]
+struct TokenBucket {
+	/// Tokens refilled per second.
+	rate: f64,
+	/// Maximum number of tokens.
+	burst: f64,
+	/// Available tokens, negative if reserved ahead, and the time of the last
+	/// refill.
+	state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+	/// Reserve a token and return the duration to wait until it is available.
+	fn reserve(&self) -> Duration {
+		#[allow(clippy::expect_used)] // only happens on panics, so we can panic again.
+		let mut state = self.state.lock().expect("mutex poisened");
+		let (tokens, updated) = &mut *state;
+		let now = Instant::now();
+		*tokens =
+			now.duration_since(*updated).as_secs_f64().mul_add(self.rate, *tokens).min(self.burst);
+		*updated = now;
+		*tokens -= 1.0;
+		if *tokens >= 0.0 {
+			Duration::ZERO
+		} else {
+			Duration::from_secs_f64(-*tokens / self.rate)
+		}
+	}
+}
+
+/// Rate and concurrency limits for requests, shared across clones of the
+/// client.
+#[derive(Debug)]
+pub(crate) struct RequestLimiter {
+	/// Token bucket for the rate limit.
+	rate: Option<TokenBucket>,
+	/// Semaphore for the maximum number of requests in flight.
+	concurrency: Option<Semaphore>,
+	/// Collected metrics.
+	metrics: Mutex<LimitMetrics>,
+}
+
+impl RequestLimiter {
+	/// Create a new limiter. A rate of 0 requests per second disables rate
+	/// limiting.
+	pub(crate) fn new(rate_limit: Option<(u32, u32)>, max_in_flight: Option<usize>) -> Self {
+		let rate =
+			rate_limit.filter(|(per_second, _)| *per_second > 0).map(|(per_second, burst)| {
+				let burst = f64::from(burst.max(1));
+				TokenBucket {
+					rate: f64::from(per_second),
+					burst,
+					state: Mutex::new((burst, Instant::now())),
+				}
+			});
+		Self {
+			rate,
+			concurrency: max_in_flight.map(|max| Semaphore::new(max.max(1))),
+			metrics: Mutex::new(LimitMetrics::default()),
+		}
+	}
+
+	/// Wait until the request may be sent. The returned permit must be held
+	/// while the request is in flight.
+	pub(crate) async fn acquire(&self) -> Option<SemaphorePermit<'_>> {
+		let start = Instant::now();
+		let mut queued = false;
+
+		let permit = match &self.concurrency {
+			Some(semaphore) => match semaphore.try_acquire() {
+				Ok(permit) => Some(permit),
+				Err(_) => {
+					queued = true;
+					// The semaphore is never closed.
+					semaphore.acquire().await.ok()
+				}
+			},
+			None => None,
+		};
+
+		if let Some(bucket) = &self.rate {
+			let wait = bucket.reserve();
+			if !wait.is_zero() {
+				queued = true;
+				tokio::time::sleep(wait).await;
+			}
+		}
+
+		let waited = start.elapsed();
+		if queued {
+			tracing::debug!("Request was queued for {waited:?} by the client-side limits");
+		}
+		#[allow(clippy::expect_used)] // only happens on panics, so we can panic again.
+		let mut metrics = self.metrics.lock().expect("mutex poisened");
+		metrics.requests += 1;
+		if queued {
+			metrics.queued += 1;
+			metrics.total_queue_time += waited;
+			metrics.max_queue_time = metrics.max_queue_time.max(waited);
+		}
+
+		permit
+	}
+
+	/// Get the collected metrics.
+	pub(crate) fn metrics(&self) -> LimitMetrics {
+		#[allow(clippy::expect_used)] // only happens on panics, so we can panic again.
+		*self.metrics.lock().expect("mutex poisened")
+	}
+}
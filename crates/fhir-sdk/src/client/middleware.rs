@@ -0,0 +1,230 @@
+//! Middleware chain around the requests of the client.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use reqwest::{header, StatusCode, Url};
+
+use super::{
+	misc::{make_uuid_header_value, parse_major_fhir_version},
+	ClientData, Error,
+};
+
+/// Middleware wrapping every request of the client, e.g. for audit logging,
+/// custom headers, request signing or response inspection. Register it via
+/// [`ClientBuilder::middleware`](super::ClientBuilder::middleware).
+///
+/// The middleware gets the request with the headers of the
+/// [`RequestSettings`](super::RequestSettings) already applied, e.g. the
+/// `Authorization` header. It calls [Next::run] to pass it on to the next
+/// middleware and finally the HTTP client. It may also skip calling `next` or
+/// call it multiple times. The retries of the request settings happen below
+/// the chain, so middlewares see a request once and get the response of its
+/// last attempt.
+#[async_trait]
+pub trait Middleware: Send + Sync {
+	/// Handle the request, passing it on via `next`.
+	async fn handle(
+		&self,
+		request: reqwest::Request,
+		next: Next<'_>,
+	) -> Result<reqwest::Response, Error>;
+}
+
+#[async_trait]
+impl<M: Middleware + ?Sized> Middleware for Arc<M> {
+	async fn handle(
+		&self,
+		request: reqwest::Request,
+		next: Next<'_>,
+	) -> Result<reqwest::Response, Error> {
+		(**self).handle(request, next).await
+	}
+}
+
+/// The rest of the middleware chain.
+#[derive(Clone, Copy)]
+pub struct Next<'a> {
+	/// The client's data.
+	data: &'a ClientData,
+	/// FHIR version of the client.
+	fhir_version: &'static str,
+	/// Remaining middlewares.
+	middlewares: &'a [Arc<dyn Middleware>],
+}
+
+impl<'a> Next<'a> {
+	/// Create the chain of the client's middlewares.
+	pub(crate) fn new(data: &'a ClientData, fhir_version: &'static str) -> Self {
+		Self { data, fhir_version, middlewares: &data.middlewares }
+	}
+
+	/// Pass the request on to the next middleware and finally send it.
+	pub async fn run(self, request: reqwest::Request) -> Result<reqwest::Response, Error> {
+		if let Some((middleware, middlewares)) = self.middlewares.split_first() {
+			middleware.handle(request, Self { middlewares, ..self }).await
+		} else {
+			self.data.send(request).await
+		}
+	}
+
+	/// The configured base URL of the client.
+	#[must_use]
+	pub fn base_url(&self) -> &Url {
+		&self.data.base_url
+	}
+
+	/// The FHIR version of the client, e.g. `5.0.0`.
+	#[must_use]
+	pub const fn fhir_version(&self) -> &'static str {
+		self.fhir_version
+	}
+}
+
+impl std::fmt::Debug for Next<'_> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("Next")
+			.field("fhir_version", &self.fhir_version)
+			.field("middlewares", &self.middlewares.len())
+			.finish()
+	}
+}
+
+/// The default middlewares of the client, in order: [CorrelationId],
+/// [OriginCheck], [VersionCheck] and [AuthRefresh]. Use it with
+/// [`ClientBuilder::middlewares`](super::ClientBuilder::middlewares) to
+/// reorder or disable them.
+#[must_use]
+pub fn default_middlewares() -> Vec<Arc<dyn Middleware>> {
+	vec![
+		Arc::new(CorrelationId),
+		Arc::new(OriginCheck),
+		Arc::new(VersionCheck),
+		Arc::new(AuthRefresh),
+	]
+}
+
+/// Built-in middleware adding the `X-Correlation-Id` header with a new UUID if
+/// it is not already present, e.g. for a whole transaction or paging.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CorrelationId;
+
+#[async_trait]
+impl Middleware for CorrelationId {
+	async fn handle(
+		&self,
+		mut request: reqwest::Request,
+		next: Next<'_>,
+	) -> Result<reqwest::Response, Error> {
+		let correlation_id =
+			request.headers_mut().entry("X-Correlation-Id").or_insert_with(make_uuid_header_value);
+		let x_correlation_id = correlation_id.to_str().ok().map(ToOwned::to_owned);
+		tracing::Span::current().record("x_correlation_id", x_correlation_id);
+		next.run(request).await
+	}
+}
+
+/// Built-in middleware rejecting requests to a different origin than the base
+/// URL, unless allowed via
+/// [`ClientBuilder::allow_origin_mismatch`](super::ClientBuilder::allow_origin_mismatch).
+/// With [`ClientBuilder::restrict_to_base_path`](super::ClientBuilder::restrict_to_base_path),
+/// requests to paths outside of the base URL's path are rejected as well. The
+/// check only happens with both this middleware in the chain and the flag not
+/// allowing mismatches; either one disables it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OriginCheck;
+
+#[async_trait]
+impl Middleware for OriginCheck {
+	async fn handle(
+		&self,
+		request: reqwest::Request,
+		next: Next<'_>,
+	) -> Result<reqwest::Response, Error> {
+		// Make sure we are not forwarded to any malicious server.
+		if next.data.error_on_origin_mismatch && request.url().origin() != next.base_url().origin()
+		{
+			return Err(Error::DifferentOrigin(request.url().to_string()));
+		}
+		if next.data.error_on_origin_mismatch
+			&& next.data.error_on_path_mismatch
+			&& !within_base_path(request.url(), next.base_url())
+		{
+			return Err(Error::DifferentBasePath(request.url().to_string()));
+		}
+		next.run(request).await
+	}
+}
+
+/// Built-in middleware calling the auth callback on `401 Unauthorized`
+/// responses and retrying the request once with the new `Authorization`
+/// header.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AuthRefresh;
+
+#[async_trait]
+impl Middleware for AuthRefresh {
+	async fn handle(
+		&self,
+		request: reqwest::Request,
+		next: Next<'_>,
+	) -> Result<reqwest::Response, Error> {
+		let retry_request = request.try_clone().ok_or(Error::RequestNotClone)?;
+		let response = next.run(request).await?;
+
+		// On authorization failure, retry after refreshing the authorization header.
+		if response.status() == StatusCode::UNAUTHORIZED {
+			if !next.data.refresh_authorization().await? {
+				// There is no auth callback, return without retrying.
+				return Ok(response);
+			}
+			tracing::info!("Retrying request after authorization refresh");
+			let mut retry_request = retry_request;
+			if let Some(authorization) =
+				next.data.request_settings().headers().get(header::AUTHORIZATION)
+			{
+				retry_request.headers_mut().insert(header::AUTHORIZATION, authorization.clone());
+			}
+			return next.run(retry_request).await;
+		}
+
+		Ok(response)
+	}
+}
+
+/// Built-in middleware rejecting responses of a different major FHIR version
+/// than the client's, unless allowed via
+/// [`ClientBuilder::allow_version_mismatch`](super::ClientBuilder::allow_version_mismatch).
+/// The check only happens with both this middleware in the chain and the flag
+/// not allowing mismatches; either one disables it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VersionCheck;
+
+#[async_trait]
+impl Middleware for VersionCheck {
+	async fn handle(
+		&self,
+		request: reqwest::Request,
+		next: Next<'_>,
+	) -> Result<reqwest::Response, Error> {
+		let response = next.run(request).await?;
+
+		if next.data.error_on_version_mismatch {
+			if let Some(version) = parse_major_fhir_version(response.headers())? {
+				let expected =
+					next.fhir_version.split_once('.').map_or(next.fhir_version, |(major, _)| major);
+				if version != expected {
+					return Err(Error::DifferentFhirVersion(version.to_owned()));
+				}
+			}
+		}
+
+		Ok(response)
+	}
+}
+
+/// Whether the URL's path is the base URL's path or below it.
+fn within_base_path(url: &Url, base_url: &Url) -> bool {
+	let base_path = base_url.path().trim_end_matches('/');
+	url.path().strip_prefix(base_path).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
@@ -1,5 +1,7 @@
 //! Miscellaneous helpers.
 
+use std::time::{Duration, SystemTime};
+
 use ::uuid::Uuid;
 use reqwest::header::{self, HeaderMap, HeaderValue};
 
@@ -9,19 +11,14 @@ use super::Error;
 pub fn parse_etag(headers: &HeaderMap) -> Result<String, Error> {
 	let etag =
 		headers.get(header::ETAG).ok_or_else(|| Error::EtagFailure("None".to_owned()))?.to_str()?;
-	if etag.starts_with("W/\"") && etag.ends_with('"') {
-		let end = etag.split_at(3).1;
-		let version_id = end.split_at(end.len() - 1).0;
-		Ok(version_id.to_owned())
-	} else if etag.starts_with('"') && etag.ends_with('"') {
-		// Spec says one should use weak etags `W/"version"`, but we can work with it if
-		// the server does not do this.
-		let end = etag.split_at(1).1;
-		let version_id = end.split_at(end.len() - 1).0;
-		Ok(version_id.to_owned())
-	} else {
-		Err(Error::EtagFailure(etag.to_owned()))
-	}
+	parse_etag_str(etag).map(ToOwned::to_owned).ok_or_else(|| Error::EtagFailure(etag.to_owned()))
+}
+
+/// Parse an ETag value to a version ID. Returns `None` if it is not a valid ETag.
+pub fn parse_etag_str(etag: &str) -> Option<&str> {
+	// Spec says one should use weak etags `W/"version"`, but we can work with it if
+	// the server does not do this.
+	etag.strip_prefix("W/").unwrap_or(etag).strip_prefix('"')?.strip_suffix('"')
 }
 
 /// Parse an Location header to a resource ID and optional version ID.
@@ -58,6 +55,17 @@ pub fn parse_major_fhir_version(headers: &HeaderMap) -> Result<Option<&str>, Err
 	Ok(version)
 }
 
+/// Parse the `Retry-After` header, given either as seconds or HTTP date, to the
+/// duration to wait. Returns `None` if there is no valid header.
+pub fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+	let retry_after = headers.get(header::RETRY_AFTER)?.to_str().ok()?.trim();
+	if let Ok(seconds) = retry_after.parse::<u64>() {
+		return Some(Duration::from_secs(seconds));
+	}
+	let date = httpdate::parse_http_date(retry_after).ok()?;
+	Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
 /// Escape a search parameter value.
 pub fn escape_search_value(value: &str) -> String {
 	value.replace('\\', "\\\\").replace('|', "\\|").replace('$', "\\$").replace(',', "\\,")
@@ -94,6 +102,28 @@ mod tests {
 		assert!(matches!(result, Err(Error::EtagFailure(_))));
 	}
 
+	#[test]
+	fn retry_after_parsing() {
+		let mut headers = HeaderMap::new();
+		assert_eq!(parse_retry_after(&headers), None);
+
+		headers.insert(header::RETRY_AFTER, HeaderValue::from_static("120"));
+		assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(120)));
+
+		headers
+			.insert(header::RETRY_AFTER, HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"));
+		assert_eq!(parse_retry_after(&headers), Some(Duration::ZERO));
+
+		let future = SystemTime::now() + Duration::from_secs(3600);
+		let value = HeaderValue::from_str(&httpdate::fmt_http_date(future)).expect("valid header");
+		headers.insert(header::RETRY_AFTER, value);
+		let duration = parse_retry_after(&headers).expect("parsing Retry-After");
+		assert!(duration > Duration::from_secs(3500) && duration <= Duration::from_secs(3600));
+
+		headers.insert(header::RETRY_AFTER, HeaderValue::from_static("soon"));
+		assert_eq!(parse_retry_after(&headers), None);
+	}
+
 	#[test]
 	fn location_parsing() {
 		let mut headers = HeaderMap::new();
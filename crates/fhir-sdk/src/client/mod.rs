@@ -2,24 +2,67 @@
 
 mod aliases;
 mod auth;
+#[cfg(feature = "blocking")]
+mod blocking;
 mod builder;
+mod cache;
+mod capabilities;
+mod checkpoint;
 mod error;
 mod fhir;
+#[cfg(feature = "fixtures")]
+mod fixtures;
+mod limit;
+mod middleware;
 mod misc;
+#[cfg(feature = "otel")]
+mod otel;
 mod request;
 mod search;
+#[cfg(feature = "smart")]
+mod smart;
+mod tenant;
+mod transport;
+#[cfg(feature = "websocket")]
+mod websocket;
 
 use std::{marker::PhantomData, sync::Arc};
 
 use ::std::any::type_name;
-use misc::parse_major_fhir_version;
-use reqwest::{header, StatusCode, Url};
+use reqwest::{header, Url};
 
+#[cfg(feature = "blocking")]
+pub use self::blocking::{BlockingClient, BlockingPage};
+#[cfg(feature = "fixtures")]
+pub use self::fixtures::{
+	FixtureRecorder, FixtureReplayer, RecordedInteraction, RecordedRequest, RecordedResponse,
+};
+#[cfg(feature = "smart")]
+pub use self::smart::{
+	AuthorizationRequest, LaunchContext, SmartApp, SmartConfiguration, SmartSession, TokenResponse,
+};
+#[cfg(feature = "websocket")]
+pub use self::websocket::{SubscriptionWebSocket, WebSocketBinding};
 pub use self::{
-	aliases::*, auth::LoginManager, builder::ClientBuilder, error::Error, fhir::*,
-	request::RequestSettings, search::SearchParameters,
+	aliases::*,
+	auth::LoginManager,
+	builder::ClientBuilder,
+	cache::{CachedResponse, MemoryCache, ResponseCache},
+	capabilities::{Interaction, ServerCapabilities},
+	checkpoint::{CheckpointStore, MemoryCheckpointStore, SyncCheckpoint},
+	error::Error,
+	fhir::*,
+	limit::LimitMetrics,
+	middleware::{
+		default_middlewares, AuthRefresh, CorrelationId, Middleware, Next, OriginCheck,
+		VersionCheck,
+	},
+	request::{RequestSettings, RetryPolicy},
+	search::SearchParameters,
+	tenant::{Tenant, TenantClient},
+	transport::{HttpTransport, ReqwestTransport},
 };
-use self::{auth::AuthCallback, misc::make_uuid_header_value};
+use self::{auth::AuthCallback, limit::RequestLimiter};
 use crate::version::{DefaultVersion, FhirR4B, FhirR5, FhirStu3, FhirVersion};
 
 /// FHIR REST Client.
@@ -31,11 +74,24 @@ struct ClientData {
 	base_url: Url,
 	/// HTTP request client.
 	client: reqwest::Client,
+	/// HTTP transport sending the requests.
+	transport: Arc<dyn HttpTransport>,
 	/// Request settings.
 	request_settings: std::sync::Mutex<RequestSettings>,
 	/// Authorization callback method, returning the authorization header value.
 	auth_callback: tokio::sync::Mutex<Option<AuthCallback>>,
-
+	/// Cache for read responses.
+	cache: Option<Arc<dyn ResponseCache>>,
+	/// Client-side rate and concurrency limits.
+	limiter: RequestLimiter,
+	/// Middlewares wrapping every request.
+	middlewares: Vec<Arc<dyn Middleware>>,
+	/// Cached capabilities of the server.
+	capabilities: tokio::sync::Mutex<Option<Arc<ServerCapabilities>>>,
+
+	/// Whether to check requests against the server's capabilities before
+	/// sending them.
+	check_capabilities: bool,
 	/// Whether to error if the server responds with a different major FHIR
 	/// version.
 	error_on_version_mismatch: bool,
@@ -44,6 +100,9 @@ struct ClientData {
 	/// Reasoning is to avoid search results and references to resources on other
 	/// servers when this is not wanted.
 	error_on_origin_mismatch: bool,
+	/// Whether to additionally error before sending a request outside of the
+	/// base URL's path on the same origin.
+	error_on_path_mismatch: bool,
 }
 
 impl<V: FhirVersion> From<ClientData> for Client<V> {
@@ -78,11 +137,17 @@ impl<V: FhirVersion> Client<V> {
 		url
 	}
 
+	/// Get the metrics of the client-side rate and concurrency limits, see
+	/// [ClientBuilder::rate_limit] and [ClientBuilder::max_concurrent_requests].
+	#[must_use]
+	pub fn limit_metrics(&self) -> LimitMetrics {
+		self.0.limiter.metrics()
+	}
+
 	/// Get the request settings configured in this client.
 	#[must_use]
 	pub fn request_settings(&self) -> RequestSettings {
-		#[allow(clippy::expect_used)] // only happens on panics, so we can panic again.
-		self.0.request_settings.lock().expect("mutex poisened").clone()
+		self.0.request_settings()
 	}
 
 	/// Set the request settings for this client. Be warned that this can be
@@ -102,11 +167,28 @@ impl<V: FhirVersion> Client<V> {
 	where
 		F: FnOnce(RequestSettings) -> RequestSettings,
 	{
-		tracing::debug!("Patching request settings");
-		#[allow(clippy::expect_used)] // only happens on panics, so we can panic again.
-		let mut request_settings = self.0.request_settings.lock().expect("mutex poisened");
-		let patched = mutator(request_settings.clone());
-		*request_settings = patched;
+		self.0.patch_request_settings(mutator);
+	}
+
+	/// Get the key of the URL in the response cache. Includes the FHIR version,
+	/// as clones of the client for different versions share the cache.
+	fn cache_key(&self, url: &Url) -> String {
+		format!("{} {url}", V::VERSION)
+	}
+
+	/// Remove the cached response for the URL, if a cache is configured.
+	fn invalidate_cached(&self, url: &Url) {
+		if let Some(cache) = &self.0.cache {
+			cache.remove(&self.cache_key(url));
+		}
+	}
+
+	/// Remove all cached responses, if a cache is configured.
+	pub fn clear_cache(&self) {
+		if let Some(cache) = &self.0.cache {
+			tracing::debug!("Clearing response cache");
+			cache.clear();
+		}
 	}
 
 	/// Convert to a different version.
@@ -132,83 +214,53 @@ impl<V: FhirVersion> Client<V> {
 		self.convert_version()
 	}
 
-	/// Run a request using the internal request settings, calling the auth
-	/// callback to retrieve a new Authorization header on `unauthtorized`
-	/// responses. Also adds the `X-Correlation-Id` header if not already present.
-	#[tracing::instrument(level = "info", skip_all, fields(x_correlation_id))]
+	/// Run a request through the middleware chain, see [Middleware], after
+	/// adding the headers of the request settings. By default, the chain adds
+	/// the `X-Correlation-Id` header if not already present, checks the origin
+	/// and FHIR version and calls the auth callback to retrieve a new
+	/// Authorization header on `unauthtorized` responses.
 	async fn run_request(
 		&self,
-		mut request: reqwest::RequestBuilder,
+		request: reqwest::RequestBuilder,
 	) -> Result<reqwest::Response, Error> {
-		let (client, info_request_result) = request.build_split();
-		let mut info_request = info_request_result?;
-		let req_method = info_request.method().clone();
-		let req_url = info_request.url().clone();
-
-		// Check the URL origin if configured to ensure equality.
-		if self.0.error_on_origin_mismatch {
-			// Make sure we are not forwarded to any malicious server.
-			if info_request.url().origin() != self.0.base_url.origin() {
-				return Err(Error::DifferentOrigin(info_request.url().to_string()));
-			}
-		}
+		let request = request.build()?;
+		self.check_capabilities(&request).await?;
+		self.send_request(request).await
+	}
 
-		// Generate a new correlation ID for this request/transaction across login, if there was
-		// none.
-		let correlation_id = info_request
-			.headers_mut()
-			.entry("X-Correlation-Id")
-			.or_insert_with(make_uuid_header_value);
-		let x_correlation_id = correlation_id.to_str().ok().map(ToOwned::to_owned);
-		request = reqwest::RequestBuilder::from_parts(client, info_request);
-		tracing::Span::current().record("x_correlation_id", x_correlation_id);
-
-		// Try running the request
-		let mut request_settings = self.request_settings();
-		tracing::info!("Sending {req_method} request to {req_url} (potentially with retries)");
-		let mut response = request_settings
-			.make_request(request.try_clone().ok_or(Error::RequestNotClone)?)
-			.await?;
-
-		// On authorization failure, retry after refreshing the authorization header.
-		if response.status() == StatusCode::UNAUTHORIZED {
-			if let Ok(mut auth_callback) = self.0.auth_callback.try_lock() {
-				if let Some(auth_callback) = auth_callback.as_mut() {
-					tracing::info!("Hit unauthorized response, calling auth_callback");
-					let auth_value = auth_callback
-						.authenticate(self.0.client.clone())
-						.await
-						.map_err(|err| Error::AuthCallback(format!("{err:#}")))?;
-					self.patch_request_settings(move |settings| {
-						settings.header(header::AUTHORIZATION, auth_value)
-					});
-				} else {
-					// There is no auth callback, return without retrying.
-					return Ok(response);
-				}
-			} else {
-				// Auth callback was blocked, we assume there was a login in flight and update
-				// our request settings after it is done.
-				_ = self.0.auth_callback.lock().await;
-			}
-			// Retry request with new request settings.
-			request_settings = self.request_settings();
-			tracing::info!("Retrying request after authorization refresh");
-			response = request_settings.make_request(request).await?;
-		}
+	/// Run the request like [Self::run_request], but without checking the
+	/// server's capabilities.
+	#[tracing::instrument(level = "info", skip_all, fields(x_correlation_id))]
+	async fn send_request(
+		&self,
+		mut request: reqwest::Request,
+	) -> Result<reqwest::Response, Error> {
+		self.0.request_settings().prepare_request(&mut request);
+		tracing::info!(
+			"Sending {} request to {} (potentially with retries)",
+			request.method(),
+			request.url()
+		);
+
+		#[cfg(feature = "otel")]
+		let (interaction, start) = {
+			otel::inject_trace_context(request.headers_mut());
+			let interaction =
+				otel::Interaction::new(request.method(), request.url(), self.base_url());
+			(interaction, std::time::Instant::now())
+		};
 
-		tracing::info!("Got response: {}", response.status());
+		let result = Next::new(&self.0, V::VERSION).run(request).await;
 
-		// Test server FHIR version in response, if configured to do so.
-		if self.0.error_on_version_mismatch {
-			if let Some(version) = parse_major_fhir_version(response.headers())? {
-				let expected = V::VERSION.split_once('.').map_or(V::VERSION, |(major, _)| major);
-				if version != expected {
-					return Err(Error::DifferentFhirVersion(version.to_owned()));
-				}
-			}
-		}
+		#[cfg(feature = "otel")]
+		otel::metrics().record_request(
+			&interaction,
+			start.elapsed(),
+			result.as_ref().ok().map(reqwest::Response::status),
+		);
 
+		let response = result?;
+		tracing::info!("Got response: {}", response.status());
 		Ok(response)
 	}
 
@@ -237,6 +289,60 @@ impl<V> Clone for Client<V> {
 	}
 }
 
+impl ClientData {
+	/// Get the request settings.
+	fn request_settings(&self) -> RequestSettings {
+		#[allow(clippy::expect_used)] // only happens on panics, so we can panic again.
+		self.request_settings.lock().expect("mutex poisened").clone()
+	}
+
+	/// Patch the request settings atomically.
+	fn patch_request_settings<F>(&self, mutator: F)
+	where
+		F: FnOnce(RequestSettings) -> RequestSettings,
+	{
+		tracing::debug!("Patching request settings");
+		#[allow(clippy::expect_used)] // only happens on panics, so we can panic again.
+		let mut request_settings = self.request_settings.lock().expect("mutex poisened");
+		let patched = mutator(request_settings.clone());
+		*request_settings = patched;
+	}
+
+	/// Call the auth callback to refresh the `Authorization` header in the
+	/// request settings. If a login is already in flight, waits for it to
+	/// finish instead. Returns `false` if there is no auth callback configured,
+	/// i.e. retrying would not help.
+	async fn refresh_authorization(&self) -> Result<bool, Error> {
+		if let Ok(mut auth_callback) = self.auth_callback.try_lock() {
+			let Some(auth_callback) = auth_callback.as_mut() else {
+				return Ok(false);
+			};
+			tracing::info!("Hit unauthorized response, calling auth_callback");
+			#[cfg(feature = "otel")]
+			otel::metrics().record_auth_refresh();
+			let auth_value = auth_callback
+				.authenticate(self.client.clone())
+				.await
+				.map_err(|err| Error::AuthCallback(format!("{err:#}")))?;
+			self.patch_request_settings(move |settings| {
+				settings.header(header::AUTHORIZATION, auth_value)
+			});
+		} else {
+			// Auth callback was blocked, we assume there was a login in flight and update
+			// our request settings after it is done.
+			_ = self.auth_callback.lock().await;
+		}
+		Ok(true)
+	}
+
+	/// Send the request after the middlewares, using the current request
+	/// settings and respecting the client-side limits.
+	async fn send(&self, request: reqwest::Request) -> Result<reqwest::Response, Error> {
+		let request_settings = self.request_settings();
+		request_settings.make_request(self.transport.as_ref(), &self.limiter, request).await
+	}
+}
+
 impl std::fmt::Debug for ClientData {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		let auth_callback = match self.auth_callback.try_lock() {
@@ -253,10 +359,17 @@ impl std::fmt::Debug for ClientData {
 		f.debug_struct("ClientData")
 			.field("base_url", &self.base_url)
 			.field("client", &self.client)
+			.field("transport", &"<transport>")
 			.field("request_settings", &self.request_settings)
 			.field("auth_callback", &auth_callback)
+			.field("cache", &self.cache.as_ref().map(|_| "<cache>"))
+			.field("limiter", &self.limiter)
+			.field("middlewares", &self.middlewares.len())
+			.field("capabilities", &self.capabilities)
+			.field("check_capabilities", &self.check_capabilities)
 			.field("error_on_version_mismatch", &self.error_on_version_mismatch)
 			.field("error_on_origin_mismatch", &self.error_on_origin_mismatch)
+			.field("error_on_path_mismatch", &self.error_on_path_mismatch)
 			.finish()
 	}
 }
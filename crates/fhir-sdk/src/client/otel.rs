@@ -0,0 +1,199 @@
+//! OpenTelemetry metrics and trace context propagation.
+
+use std::sync::OnceLock;
+
+use opentelemetry::{
+	global,
+	metrics::{Counter, Histogram},
+	propagation::Injector,
+	KeyValue,
+};
+use reqwest::{
+	header::{HeaderMap, HeaderName, HeaderValue},
+	Method, StatusCode, Url,
+};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Name of the instrumentation scope.
+const SCOPE: &str = env!("CARGO_PKG_NAME");
+
+/// Instruments of the client, registered on the global meter provider.
+#[derive(Debug)]
+pub(crate) struct Metrics {
+	/// Duration of requests including retries and middlewares.
+	duration: Histogram<f64>,
+	/// Number of retries of requests.
+	retries: Counter<u64>,
+	/// Number of authorization refreshes via the auth callback.
+	auth_refreshes: Counter<u64>,
+	/// Number of failed requests, by status code or error.
+	errors: Counter<u64>,
+}
+
+/// Get the instruments, creating them on first use.
+pub(crate) fn metrics() -> &'static Metrics {
+	/// The instruments.
+	static METRICS: OnceLock<Metrics> = OnceLock::new();
+	METRICS.get_or_init(|| {
+		let meter = global::meter(SCOPE);
+		Metrics {
+			duration: meter
+				.f64_histogram("fhir.client.request.duration")
+				.with_unit("s")
+				.with_description("Duration of FHIR requests, including retries")
+				.init(),
+			retries: meter
+				.u64_counter("fhir.client.request.retries")
+				.with_description("Number of retried FHIR requests")
+				.init(),
+			auth_refreshes: meter
+				.u64_counter("fhir.client.auth.refreshes")
+				.with_description("Number of authorization refreshes")
+				.init(),
+			errors: meter
+				.u64_counter("fhir.client.request.errors")
+				.with_description("Number of failed FHIR requests, by status code")
+				.init(),
+		}
+	})
+}
+
+impl Metrics {
+	/// Record a finished request. Responses with error status and errors
+	/// without response are counted as errors.
+	pub(crate) fn record_request(
+		&self,
+		interaction: &Interaction,
+		duration: std::time::Duration,
+		status: Option<StatusCode>,
+	) {
+		let mut attributes = interaction.attributes();
+		let status_attribute = status.map_or_else(
+			|| KeyValue::new("error.type", "request"),
+			|status| KeyValue::new("http.response.status_code", i64::from(status.as_u16())),
+		);
+		attributes.push(status_attribute);
+		self.duration.record(duration.as_secs_f64(), &attributes);
+		if status.map_or(true, |status| status.is_client_error() || status.is_server_error()) {
+			self.errors.add(1, &attributes);
+		}
+	}
+
+	/// Record a retry of a request.
+	pub(crate) fn record_retry(&self, method: &Method) {
+		self.retries.add(1, &[KeyValue::new("http.request.method", method.to_string())]);
+	}
+
+	/// Record an authorization refresh.
+	pub(crate) fn record_auth_refresh(&self) {
+		self.auth_refreshes.add(1, &[]);
+	}
+}
+
+/// FHIR interaction of a request, derived from method and URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Interaction {
+	/// HTTP method.
+	method: Method,
+	/// Interaction type, e.g. `read` or `search-type`.
+	kind: &'static str,
+	/// Resource type, if the interaction is on a resource type.
+	resource_type: Option<String>,
+}
+
+impl Interaction {
+	/// Classify the request to the URL relative to the base URL.
+	pub(crate) fn new(method: &Method, url: &Url, base_url: &Url) -> Self {
+		let base_path = base_url.path().trim_end_matches('/');
+		let path = url.path().strip_prefix(base_path).unwrap_or(url.path());
+		let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+
+		let resource_type = segments
+			.first()
+			.filter(|segment| segment.starts_with(|c: char| c.is_ascii_uppercase()))
+			.map(|segment| (*segment).to_owned());
+		let is_operation = segments.iter().any(|segment| segment.starts_with('$'));
+		let is_history = segments.contains(&"_history");
+		let kind = match (method, segments.as_slice()) {
+			_ if is_operation => "operation",
+			(&Method::GET, ["metadata"]) => "capabilities",
+			(&Method::GET, [_, _, "_history", _]) => "vread",
+			(&Method::GET, _) if is_history => "history",
+			(&Method::GET, []) | (&Method::POST, ["_search"]) => "search-system",
+			(&Method::GET, [_]) | (&Method::POST, [_, "_search"]) => "search-type",
+			(&Method::GET, [_, _]) => "read",
+			(&Method::POST, []) => "batch-transaction",
+			(&Method::POST, [_]) => "create",
+			(&Method::PUT, _) => "update",
+			(&Method::PATCH, _) => "patch",
+			(&Method::DELETE, _) => "delete",
+			_ => "other",
+		};
+
+		Self { method: method.clone(), kind, resource_type }
+	}
+
+	/// Metric attributes of the interaction.
+	fn attributes(&self) -> Vec<KeyValue> {
+		let mut attributes = vec![
+			KeyValue::new("http.request.method", self.method.to_string()),
+			KeyValue::new("fhir.interaction", self.kind),
+		];
+		if let Some(resource_type) = &self.resource_type {
+			attributes.push(KeyValue::new("fhir.resource_type", resource_type.clone()));
+		}
+		attributes
+	}
+}
+
+/// Inject the trace context of the current span into the headers, e.g. the W3C
+/// `traceparent` header, using the globally configured propagator.
+pub(crate) fn inject_trace_context(headers: &mut HeaderMap) {
+	let context = tracing::Span::current().context();
+	global::get_text_map_propagator(|propagator| {
+		propagator.inject_context(&context, &mut HeaderInjector(headers));
+	});
+}
+
+/// Injector into a [HeaderMap].
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+	fn set(&mut self, key: &str, value: String) {
+		if let (Ok(name), Ok(value)) =
+			(HeaderName::from_bytes(key.as_bytes()), HeaderValue::from_str(&value))
+		{
+			self.0.insert(name, value);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn interaction_classification() -> anyhow::Result<()> {
+		let base_url = Url::parse("http://localhost/fhir/")?;
+		let classify = |method: Method, path: &str| -> anyhow::Result<_> {
+			let interaction = Interaction::new(&method, &base_url.join(path)?, &base_url);
+			Ok((interaction.kind, interaction.resource_type))
+		};
+
+		assert_eq!(classify(Method::GET, "metadata")?, ("capabilities", None));
+		assert_eq!(classify(Method::GET, "Patient/1")?, ("read", Some("Patient".to_owned())));
+		assert_eq!(classify(Method::GET, "Patient/1/_history/2")?.0, "vread");
+		assert_eq!(classify(Method::GET, "Patient/1/_history")?.0, "history");
+		assert_eq!(classify(Method::GET, "_history")?, ("history", None));
+		assert_eq!(classify(Method::GET, "Patient?name=x")?.0, "search-type");
+		assert_eq!(classify(Method::POST, "Patient/_search")?.0, "search-type");
+		assert_eq!(classify(Method::GET, "?_type=Patient")?.0, "search-system");
+		assert_eq!(classify(Method::POST, "Patient")?.0, "create");
+		assert_eq!(classify(Method::POST, "")?.0, "batch-transaction");
+		assert_eq!(classify(Method::PUT, "Patient/1")?.0, "update");
+		assert_eq!(classify(Method::PATCH, "Patient/1")?.0, "patch");
+		assert_eq!(classify(Method::DELETE, "Patient/1")?.0, "delete");
+		assert_eq!(classify(Method::POST, "Patient/1/$everything")?.0, "operation");
+		Ok(())
+	}
+}
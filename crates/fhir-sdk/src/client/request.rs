@@ -1,34 +1,156 @@
 //! HTTP Request implementation.
 
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
-use tokio_retry::{
-	strategy::{ExponentialBackoff, FixedInterval},
-	RetryIf,
+use reqwest::{
+	header::{HeaderMap, HeaderName, HeaderValue},
+	Method, StatusCode,
 };
+use tokio_retry::strategy::{jitter, ExponentialBackoff, FixedInterval};
 
-use super::{error::Error, misc::make_uuid_header_value};
+use super::{
+	error::Error,
+	limit::RequestLimiter,
+	misc::{make_uuid_header_value, parse_retry_after},
+	transport::HttpTransport,
+};
 
-/// Settings for the HTTP Requests.
+/// Policy which failed requests to retry and how, as part of the
+/// [RequestSettings].
 ///
-/// By default, 3 retries are done with a fixed retry_time of 1000 ms.
+/// By default, connection failures and timeouts are retried, as well as `429
+/// Too Many Requests` and `503 Service Unavailable` responses, honoring their
+/// `Retry-After` header. Non-idempotent requests, i.e. `POST` without
+/// `If-None-Exist` and `PATCH`, are only retried on connection failures, where
+/// the server did not receive the request.
 #[derive(Debug, Clone)]
-pub struct RequestSettings {
-	/// Number of retries to make.
-	retries: usize,
-	/// Duration to wait between the requests (either always for fixed or for
-	/// the first time for exponential backoff).
-	retry_time: Duration,
-	/// Max retry duration between requests. Only relevant for exponential
-	/// backoff.
-	max_retry_time: Option<Duration>,
-	/// Whether to use exponential backoff or not.
-	exp_backoff: bool,
-	/// Timeout for the requests.
-	timeout: Option<Duration>,
-	/// Additional headers to set for the requests.
-	headers: HeaderMap,
+pub struct RetryPolicy {
+	/// Response status codes to retry.
+	status_codes: Vec<StatusCode>,
+	/// Whether to wait for the duration in the `Retry-After` header instead of
+	/// the retry strategy's duration.
+	honor_retry_after: bool,
+	/// Maximum duration to wait for as requested by `Retry-After`.
+	max_retry_after: Option<Duration>,
+	/// Whether to randomize the durations between retries.
+	jitter: bool,
+	/// Maximum total time for all attempts, including waiting.
+	time_budget: Option<Duration>,
+	/// Whether to retry non-idempotent requests on other failures than
+	/// connection failures.
+	retry_non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+	fn default() -> Self {
+		Self {
+			status_codes: vec![StatusCode::TOO_MANY_REQUESTS, StatusCode::SERVICE_UNAVAILABLE],
+			honor_retry_after: true,
+			max_retry_after: Some(Duration::from_secs(60)),
+			jitter: false,
+			time_budget: None,
+			retry_non_idempotent: false,
+		}
+	}
+}
+
+impl RetryPolicy {
+	/// Set the response status codes to retry, replacing the default `429` and
+	/// `503`.
+	#[must_use]
+	pub fn status_codes(mut self, status_codes: impl IntoIterator<Item = StatusCode>) -> Self {
+		self.status_codes = status_codes.into_iter().collect();
+		self
+	}
+
+	/// Set whether to wait for the duration in the `Retry-After` header (given
+	/// in seconds or as HTTP date) instead of the retry strategy's duration.
+	/// Enabled by default.
+	#[must_use]
+	pub const fn honor_retry_after(mut self, honor: bool) -> Self {
+		self.honor_retry_after = honor;
+		self
+	}
+
+	/// Set the maximum duration to wait for as requested by the `Retry-After`
+	/// header, so a misbehaving server cannot stall the client. Defaults to 60
+	/// seconds. If set to `None`, the maximum delay of the exponential backoff
+	/// is used, if one is set (see [RequestSettings::exp_backoff]), otherwise
+	/// the duration is not limited.
+	#[must_use]
+	pub const fn max_retry_after(mut self, max: Option<Duration>) -> Self {
+		self.max_retry_after = max;
+		self
+	}
+
+	/// Set whether to randomize the durations between retries (full jitter),
+	/// to avoid many clients retrying at the same time. Disabled by default.
+	#[must_use]
+	pub const fn jitter(mut self, jitter: bool) -> Self {
+		self.jitter = jitter;
+		self
+	}
+
+	/// Set the maximum total time for all attempts of a request, including
+	/// waiting between them. No retry is made if it would exceed the budget.
+	#[must_use]
+	pub const fn time_budget(mut self, budget: Option<Duration>) -> Self {
+		self.time_budget = budget;
+		self
+	}
+
+	/// Set whether to retry non-idempotent requests, i.e. `POST` without
+	/// `If-None-Exist` and `PATCH`, on timeouts and retryable status codes.
+	/// They might have been processed by the server already, so retrying might
+	/// duplicate them. Disabled by default.
+	#[must_use]
+	pub const fn retry_non_idempotent(mut self, retry: bool) -> Self {
+		self.retry_non_idempotent = retry;
+		self
+	}
+
+	/// Whether the request may be retried on failures, where the server might
+	/// have processed it.
+	fn may_retry(&self, request: &reqwest::Request) -> bool {
+		self.retry_non_idempotent
+			|| match *request.method() {
+				Method::POST => request.headers().contains_key("If-None-Exist"),
+				Method::PATCH => false,
+				_ => true,
+			}
+	}
+
+	/// Whether the result of the request should be retried.
+	fn should_retry(
+		&self,
+		request: &reqwest::Request,
+		result: &Result<reqwest::Response, Error>,
+	) -> bool {
+		match result {
+			Ok(response) => {
+				self.status_codes.contains(&response.status()) && self.may_retry(request)
+			}
+			Err(Error::Request(err)) if err.is_connect() => true,
+			Err(err) => err.should_retry() && self.may_retry(request),
+		}
+	}
+
+	/// The duration to wait before retrying the result as requested by the
+	/// server, if it should be honored. It is limited to the maximum, falling
+	/// back to the given maximum of the backoff.
+	fn retry_after(
+		&self,
+		result: &Result<reqwest::Response, Error>,
+		backoff_max: Option<Duration>,
+	) -> Option<Duration> {
+		let response = result.as_ref().ok()?;
+		let retry_after =
+			self.honor_retry_after.then(|| parse_retry_after(response.headers())).flatten()?;
+		Some(match self.max_retry_after.or(backoff_max) {
+			Some(max) => retry_after.min(max),
+			None => retry_after,
+		})
+	}
 }
 
 impl Default for RequestSettings {
@@ -40,6 +162,7 @@ impl Default for RequestSettings {
 			exp_backoff: false,
 			timeout: None,
 			headers: HeaderMap::new(),
+			retry_policy: RetryPolicy::default(),
 		}
 	}
 }
@@ -70,7 +193,15 @@ impl RequestSettings {
 		self
 	}
 
-	/// Set the request timeout.
+	/// Set the policy which failures to retry and how.
+	#[must_use]
+	pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+		self.retry_policy = policy;
+		self
+	}
+
+	/// Set the request timeout. With the default transport, it also applies to
+	/// reading the response body.
 	#[must_use]
 	pub const fn timeout(mut self, timeout: Option<Duration>) -> Self {
 		self.timeout = timeout;
@@ -84,29 +215,42 @@ impl RequestSettings {
 		self
 	}
 
-	/// Make a HTTP request using the settings. Returns the response.
-	///
-	/// It is recommended to set the `X-Correlation-Id` header outside, for a whole transaction.
-	#[tracing::instrument(level = "debug", skip_all, fields(x_correlation_id, x_request_id))]
-	pub(crate) async fn make_request(
-		&self,
-		mut request: reqwest::RequestBuilder,
-	) -> Result<reqwest::Response, Error> {
-		if let Some(timeout) = self.timeout {
-			request = request.timeout(timeout);
-		}
+	/// Get the additional headers that are set for each request.
+	pub(crate) const fn headers(&self) -> &HeaderMap {
+		&self.headers
+	}
 
+	/// Add the headers of the settings to the request, where headers of the
+	/// request take precedence, as well as the `X-Request-Id` header if not
+	/// already set. This happens before the middlewares, so they see the final
+	/// headers. The `X-Correlation-Id` header is added by the
+	/// [`CorrelationId`](super::CorrelationId) middleware.
+	pub(crate) fn prepare_request(&self, request: &mut reqwest::Request) {
 		// Add or override default headers with request headers.
-		let (client, request_result) = request.build_split();
-		let mut request = request_result?;
 		let mut headers = self.headers.clone();
 		headers.extend(request.headers().clone());
 		*request.headers_mut() = headers;
 
-		// Add `X-Request-Id` and `X-Correlation-Id` header if not already set.
-		let id_value = make_uuid_header_value();
-		request.headers_mut().entry("X-Correlation-Id").or_insert_with(|| id_value.clone());
-		request.headers_mut().entry("X-Request-Id").or_insert(id_value);
+		// Add `X-Request-Id` header if not already set.
+		request.headers_mut().entry("X-Request-Id").or_insert_with(make_uuid_header_value);
+	}
+
+	/// Make a HTTP request via the transport using the settings, retrying on
+	/// failures. Returns the response. Each attempt waits for the client-side
+	/// limits and holds the permit only while it is in flight, not while
+	/// waiting for a retry. The headers are expected to be set already, see
+	/// [Self::prepare_request].
+	#[tracing::instrument(level = "debug", skip_all, fields(x_correlation_id, x_request_id))]
+	pub(crate) async fn make_request(
+		&self,
+		transport: &dyn HttpTransport,
+		limiter: &RequestLimiter,
+		mut request: reqwest::Request,
+	) -> Result<reqwest::Response, Error> {
+		// A timeout set on the request takes precedence. It is kept on the request, so
+		// that Reqwest applies it to reading the streamed body as well.
+		let timeout = request.timeout().copied().or(self.timeout);
+		*request.timeout_mut() = timeout;
 
 		// Construct the dynamic retry strategy iterator.
 		let strategy: Box<dyn Iterator<Item = Duration> + Send + Sync> = if self.exp_backoff {
@@ -129,17 +273,47 @@ impl RequestSettings {
 			.record("x_request_id", x_request_id);
 
 		// Send the request, but retry on specific failures.
-		RetryIf::spawn(
-			strategy.take(self.retries),
-			|| async {
-				tracing::debug!("Sending {} request to {}", request.method(), request.url());
-				let request = request.try_clone().ok_or(Error::RequestNotClone)?;
-				let response = client.execute(request).await?;
-				tracing::debug!("Got response: {}", response.status());
-				Ok(response)
-			},
-			Error::should_retry,
-		)
-		.await
+		let started = Instant::now();
+		let mut delays = strategy.take(self.retries);
+		loop {
+			tracing::debug!("Sending {} request to {}", request.method(), request.url());
+			let permit = limiter.acquire().await;
+			let result = match timeout {
+				Some(timeout) => tokio::time::timeout(timeout, transport.execute(&request))
+					.await
+					.unwrap_or(Err(Error::Timeout(timeout))),
+				None => transport.execute(&request).await,
+			};
+			drop(permit);
+			match &result {
+				Ok(response) => tracing::debug!("Got response: {}", response.status()),
+				Err(err) => tracing::debug!("Request failed: {err}"),
+			}
+
+			if !self.retry_policy.should_retry(&request, &result) {
+				return result;
+			}
+			let Some(mut delay) = delays.next() else {
+				return result;
+			};
+			if self.retry_policy.jitter {
+				delay = jitter(delay);
+			}
+			let backoff_max = self.max_retry_time.filter(|_| self.exp_backoff);
+			if let Some(retry_after) = self.retry_policy.retry_after(&result, backoff_max) {
+				delay = retry_after;
+			}
+			if let Some(budget) = self.retry_policy.time_budget {
+				if started.elapsed() + delay > budget {
+					tracing::debug!("Not retrying, the time budget would be exceeded");
+					return result;
+				}
+			}
+
+			tracing::debug!("Retrying request in {delay:?}");
+			#[cfg(feature = "otel")]
+			super::otel::metrics().record_retry(request.method());
+			tokio::time::sleep(delay).await;
+		}
 	}
 }
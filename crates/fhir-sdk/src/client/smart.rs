@@ -0,0 +1,494 @@
+//! SMART App Launch helpers: discovery, authorization code flow with PKCE and
+//! token refresh.
+
+use std::time::{Duration, Instant};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use reqwest::{header::HeaderValue, Url};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use super::{Error, LoginManager};
+
+/// Refresh access tokens this long before they expire.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(30);
+
+/// SMART configuration of a FHIR server, from its
+/// `.well-known/smart-configuration`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmartConfiguration {
+	/// URL of the OAuth2 authorization endpoint.
+	pub authorization_endpoint: Url,
+	/// URL of the OAuth2 token endpoint.
+	pub token_endpoint: Url,
+	/// Issuer of the ID tokens.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub issuer: Option<String>,
+	/// URL of the token revocation endpoint.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub revocation_endpoint: Option<Url>,
+	/// Supported SMART capabilities, e.g. `launch-ehr`.
+	#[serde(default)]
+	pub capabilities: Vec<String>,
+	/// Supported scopes.
+	#[serde(default)]
+	pub scopes_supported: Vec<String>,
+	/// Supported PKCE code challenge methods.
+	#[serde(default)]
+	pub code_challenge_methods_supported: Vec<String>,
+}
+
+impl SmartConfiguration {
+	/// Fetch the SMART configuration of the FHIR server with the base URL.
+	pub async fn discover(http: &reqwest::Client, fhir_base_url: &Url) -> Result<Self, Error> {
+		let mut url = fhir_base_url.clone();
+		url.path_segments_mut()
+			.map_err(|()| Error::UrlCannotBeBase)?
+			.pop_if_empty()
+			.extend([".well-known", "smart-configuration"]);
+
+		tracing::debug!("Discovering SMART configuration at {url}");
+		let response =
+			http.get(url).header(reqwest::header::ACCEPT, "application/json").send().await?;
+		let status = response.status();
+		if !status.is_success() {
+			return Err(Error::Response(status, response.text().await.unwrap_or_default()));
+		}
+		Ok(response.json().await?)
+	}
+}
+
+/// Registered SMART app, starting the authorization code flow with PKCE.
+#[derive(Clone)]
+pub struct SmartApp {
+	/// SMART configuration of the server.
+	config: SmartConfiguration,
+	/// FHIR base URL, sent as audience.
+	fhir_base_url: Url,
+	/// OAuth2 client ID.
+	client_id: String,
+	/// OAuth2 client secret of confidential apps.
+	client_secret: Option<String>,
+	/// Redirect URI registered for the app.
+	redirect_uri: Url,
+	/// Requested scopes, space-separated.
+	scope: String,
+}
+
+impl SmartApp {
+	/// Create a new app for the FHIR server with the configuration. Requests
+	/// the scopes `openid fhirUser launch` by default, see [Self::scope].
+	#[must_use]
+	pub fn new(
+		config: SmartConfiguration,
+		fhir_base_url: Url,
+		client_id: impl Into<String>,
+		redirect_uri: Url,
+	) -> Self {
+		Self {
+			config,
+			fhir_base_url,
+			client_id: client_id.into(),
+			client_secret: None,
+			redirect_uri,
+			scope: "openid fhirUser launch".to_owned(),
+		}
+	}
+
+	/// Create a new app for the FHIR server, discovering its SMART
+	/// configuration.
+	pub async fn discover(
+		http: &reqwest::Client,
+		fhir_base_url: Url,
+		client_id: impl Into<String>,
+		redirect_uri: Url,
+	) -> Result<Self, Error> {
+		let config = SmartConfiguration::discover(http, &fhir_base_url).await?;
+		Ok(Self::new(config, fhir_base_url, client_id, redirect_uri))
+	}
+
+	/// Set the requested scopes, space-separated, e.g.
+	/// `launch openid fhirUser patient/*.rs offline_access`.
+	#[must_use]
+	pub fn scope(mut self, scope: impl Into<String>) -> Self {
+		self.scope = scope.into();
+		self
+	}
+
+	/// Set the client secret of a confidential app, sent via basic
+	/// authentication to the token endpoint. Client ID and secret are
+	/// form-url-encoded first, as required by RFC 6749, section 2.3.1.
+	#[must_use]
+	pub fn client_secret(mut self, client_secret: impl Into<String>) -> Self {
+		self.client_secret = Some(client_secret.into());
+		self
+	}
+
+	/// Get the SMART configuration of the server.
+	#[must_use]
+	pub const fn config(&self) -> &SmartConfiguration {
+		&self.config
+	}
+
+	/// Start the authorization, creating the URL to redirect the user to. Pass
+	/// the `launch` parameter for EHR launches. Keep the returned request to
+	/// finish the authorization via [Self::exchange_code].
+	#[must_use]
+	pub fn authorize(&self, launch: Option<&str>) -> AuthorizationRequest {
+		let state = Uuid::new_v4().simple().to_string();
+		let code_verifier = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+		let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+
+		let mut url = self.config.authorization_endpoint.clone();
+		{
+			let mut query = url.query_pairs_mut();
+			query
+				.append_pair("response_type", "code")
+				.append_pair("client_id", &self.client_id)
+				.append_pair("redirect_uri", self.redirect_uri.as_str())
+				.append_pair("scope", &self.scope)
+				.append_pair("state", &state)
+				.append_pair("aud", self.fhir_base_url.as_str())
+				.append_pair("code_challenge", &code_challenge)
+				.append_pair("code_challenge_method", "S256");
+			if let Some(launch) = launch {
+				query.append_pair("launch", launch);
+			}
+		}
+
+		AuthorizationRequest { url, state, code_verifier }
+	}
+
+	/// Exchange the authorization code at the token endpoint, starting a
+	/// session.
+	pub async fn exchange_code(
+		&self,
+		http: &reqwest::Client,
+		authorization: &AuthorizationRequest,
+		code: &str,
+	) -> Result<SmartSession, Error> {
+		let token = self
+			.token_request(
+				http,
+				&[
+					("grant_type", "authorization_code"),
+					("code", code),
+					("redirect_uri", self.redirect_uri.as_str()),
+					("code_verifier", &authorization.code_verifier),
+				],
+			)
+			.await?;
+		Ok(SmartSession::new(self.clone(), token))
+	}
+
+	/// Get new tokens using the refresh token.
+	pub async fn refresh(
+		&self,
+		http: &reqwest::Client,
+		refresh_token: &str,
+	) -> Result<TokenResponse, Error> {
+		self.token_request(
+			http,
+			&[("grant_type", "refresh_token"), ("refresh_token", refresh_token)],
+		)
+		.await
+	}
+
+	/// Send the form to the token endpoint, authenticating the app.
+	async fn token_request(
+		&self,
+		http: &reqwest::Client,
+		form: &[(&str, &str)],
+	) -> Result<TokenResponse, Error> {
+		tracing::debug!("Requesting SMART access token at {}", self.config.token_endpoint);
+		let mut request = http
+			.post(self.config.token_endpoint.clone())
+			.header(reqwest::header::ACCEPT, "application/json");
+		request = match &self.client_secret {
+			Some(secret) => request
+				.basic_auth(form_urlencode(&self.client_id), Some(form_urlencode(secret)))
+				.form(form),
+			None => {
+				let mut form = form.to_vec();
+				form.push(("client_id", self.client_id.as_str()));
+				request.form(&form)
+			}
+		};
+
+		let response = request.send().await?;
+		let status = response.status();
+		if !status.is_success() {
+			let body = response.text().await.unwrap_or_default();
+			let message = serde_json::from_str::<OAuthError>(&body).map_or(body, |error| {
+				format!("{}: {}", error.error, error.error_description.unwrap_or_default())
+			});
+			return Err(Error::Smart(format!("Token request failed ({status}): {message}")));
+		}
+		Ok(response.json().await?)
+	}
+}
+
+impl std::fmt::Debug for SmartApp {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("SmartApp")
+			.field("config", &self.config)
+			.field("fhir_base_url", &self.fhir_base_url)
+			.field("client_id", &self.client_id)
+			.field("client_secret", &self.client_secret.as_ref().map(|_| "<redacted>"))
+			.field("redirect_uri", &self.redirect_uri)
+			.field("scope", &self.scope)
+			.finish()
+	}
+}
+
+/// Started authorization of a [SmartApp].
+#[derive(Debug, Clone)]
+pub struct AuthorizationRequest {
+	/// URL to redirect the user to.
+	url: Url,
+	/// Random state to protect against CSRF.
+	state: String,
+	/// PKCE code verifier.
+	code_verifier: String,
+}
+
+impl AuthorizationRequest {
+	/// URL of the authorization endpoint to redirect the user to.
+	#[must_use]
+	pub const fn url(&self) -> &Url {
+		&self.url
+	}
+
+	/// Random `state` parameter of the authorization.
+	#[must_use]
+	pub fn state(&self) -> &str {
+		&self.state
+	}
+
+	/// Extract the authorization code from the URL the user was redirected
+	/// back to, verifying the `state` parameter.
+	pub fn code_from_redirect(&self, redirect_url: &Url) -> Result<String, Error> {
+		let mut code = None;
+		let mut state = None;
+		let mut error = None;
+		for (key, value) in redirect_url.query_pairs() {
+			match key.as_ref() {
+				"code" => code = Some(value.into_owned()),
+				"state" => state = Some(value.into_owned()),
+				"error" => error = Some(value.into_owned()),
+				_ => {}
+			}
+		}
+
+		if let Some(error) = error {
+			return Err(Error::Smart(format!("Authorization failed: {error}")));
+		}
+		if state.as_deref() != Some(self.state.as_str()) {
+			return Err(Error::Smart("Authorization state does not match".to_owned()));
+		}
+		code.ok_or_else(|| Error::Smart("Missing authorization code in redirect".to_owned()))
+	}
+}
+
+/// OAuth2 error response.
+#[derive(Debug, Deserialize)]
+struct OAuthError {
+	/// Error code.
+	error: String,
+	/// Human-readable description.
+	#[serde(default)]
+	error_description: Option<String>,
+}
+
+/// Response of the token endpoint, including the SMART launch context.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TokenResponse {
+	/// The access token.
+	pub access_token: String,
+	/// Token type, usually `Bearer`.
+	pub token_type: String,
+	/// Lifetime of the access token in seconds.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub expires_in: Option<u64>,
+	/// Granted scopes, space-separated.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub scope: Option<String>,
+	/// Refresh token, if granted, e.g. via `offline_access`.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub refresh_token: Option<String>,
+	/// OpenID Connect ID token.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub id_token: Option<String>,
+	/// Patient in context.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub patient: Option<String>,
+	/// Encounter in context.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub encounter: Option<String>,
+	/// Current user, as sent by some servers next to the ID token.
+	#[serde(default, rename = "fhirUser", skip_serializing_if = "Option::is_none")]
+	pub fhir_user: Option<String>,
+	/// Whether the app should display a patient banner.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub need_patient_banner: Option<bool>,
+}
+
+impl std::fmt::Debug for TokenResponse {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("TokenResponse")
+			.field("access_token", &"<redacted>")
+			.field("token_type", &self.token_type)
+			.field("expires_in", &self.expires_in)
+			.field("scope", &self.scope)
+			.field("refresh_token", &self.refresh_token.as_ref().map(|_| "<redacted>"))
+			.field("id_token", &self.id_token.as_ref().map(|_| "<redacted>"))
+			.field("patient", &self.patient)
+			.field("encounter", &self.encounter)
+			.field("fhir_user", &self.fhir_user)
+			.field("need_patient_banner", &self.need_patient_banner)
+			.finish()
+	}
+}
+
+/// Launch context of a SMART session.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LaunchContext {
+	/// ID of the patient in context.
+	pub patient: Option<String>,
+	/// ID of the encounter in context.
+	pub encounter: Option<String>,
+	/// URL of the current user's resource, e.g. a `Practitioner`.
+	pub fhir_user: Option<String>,
+}
+
+impl LaunchContext {
+	/// Update the context with the values of the token response.
+	fn merge(self, token: &TokenResponse) -> Self {
+		let fhir_user = token
+			.fhir_user
+			.clone()
+			.or_else(|| token.id_token.as_deref().and_then(id_token_fhir_user));
+		Self {
+			patient: token.patient.clone().or(self.patient),
+			encounter: token.encounter.clone().or(self.encounter),
+			fhir_user: fhir_user.or(self.fhir_user),
+		}
+	}
+}
+
+/// Authorized SMART session. Pass it to
+/// [`ClientBuilder::auth_callback`](super::ClientBuilder::auth_callback) to
+/// authenticate the client's requests.
+///
+/// The client only calls the session after an unauthorized response: The first
+/// request is sent without access token and answered with the session's
+/// current access token on retry. Afterwards, the access token is refreshed
+/// when the server rejects it or it has expired by then. To skip the first
+/// round trip, also set the initial `Authorization` header of
+/// [Self::authorization] via [`RequestSettings::header`](super::RequestSettings::header).
+#[derive(Debug, Clone)]
+pub struct SmartSession {
+	/// The app the session belongs to.
+	app: SmartApp,
+	/// Latest token response.
+	token: TokenResponse,
+	/// Launch context of the session.
+	context: LaunchContext,
+	/// When the access token expires.
+	expires_at: Option<Instant>,
+	/// Whether the access token was already handed out to the client.
+	token_used: bool,
+}
+
+impl SmartSession {
+	/// Create the session from the token response.
+	fn new(app: SmartApp, token: TokenResponse) -> Self {
+		let context = LaunchContext::default().merge(&token);
+		Self { app, expires_at: expires_at(&token), context, token, token_used: false }
+	}
+
+	/// Use the new token response, keeping refresh token and launch context
+	/// if they were not sent again.
+	fn update(&mut self, mut token: TokenResponse) {
+		if token.refresh_token.is_none() {
+			token.refresh_token = self.token.refresh_token.take();
+		}
+		self.expires_at = expires_at(&token);
+		self.context = std::mem::take(&mut self.context).merge(&token);
+		self.token = token;
+		self.token_used = false;
+	}
+
+	/// Latest token response.
+	#[must_use]
+	pub const fn token(&self) -> &TokenResponse {
+		&self.token
+	}
+
+	/// Launch context of the session, i.e. `patient`, `encounter` and
+	/// `fhirUser`. The `fhirUser` is taken from the ID token without verifying
+	/// its signature, if the token response does not contain it directly.
+	#[must_use]
+	pub const fn launch_context(&self) -> &LaunchContext {
+		&self.context
+	}
+
+	/// Whether the access token is expired or about to expire.
+	#[must_use]
+	pub fn is_expired(&self) -> bool {
+		self.expires_at.is_some_and(|expires_at| Instant::now() + EXPIRY_MARGIN >= expires_at)
+	}
+
+	/// Refresh the access token using the refresh token.
+	pub async fn refresh(&mut self, http: &reqwest::Client) -> Result<(), Error> {
+		let Some(refresh_token) = self.token.refresh_token.as_deref() else {
+			return Err(Error::Smart("No refresh token available".to_owned()));
+		};
+		let token = self.app.refresh(http, refresh_token).await?;
+		self.update(token);
+		Ok(())
+	}
+
+	/// Value of the `Authorization` header for the access token.
+	pub fn authorization(&self) -> Result<HeaderValue, Error> {
+		let mut value = HeaderValue::try_from(format!("Bearer {}", self.token.access_token))
+			.map_err(|err| Error::Smart(format!("Invalid access token: {err}")))?;
+		value.set_sensitive(true);
+		Ok(value)
+	}
+}
+
+impl LoginManager for SmartSession {
+	type Error = Error;
+
+	async fn authenticate(&mut self, client: reqwest::Client) -> Result<HeaderValue, Self::Error> {
+		// The current access token is handed out first, it is only refreshed when it
+		// expired or was rejected already.
+		if self.token_used || self.is_expired() {
+			self.refresh(&client).await?;
+		}
+		self.token_used = true;
+		self.authorization()
+	}
+}
+
+/// Encode the client credential for basic authentication, see RFC 6749,
+/// section 2.3.1.
+fn form_urlencode(value: &str) -> String {
+	form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
+
+/// When the access token of the token response expires. Expiry times too far
+/// in the future to represent are treated as no expiry.
+fn expires_at(token: &TokenResponse) -> Option<Instant> {
+	token.expires_in.and_then(|seconds| Instant::now().checked_add(Duration::from_secs(seconds)))
+}
+
+/// Extract the `fhirUser` claim from the ID token, without verification.
+fn id_token_fhir_user(id_token: &str) -> Option<String> {
+	let payload = id_token.split('.').nth(1)?;
+	let payload = URL_SAFE_NO_PAD.decode(payload.trim_end_matches('=')).ok()?;
+	let claims: serde_json::Value = serde_json::from_slice(&payload).ok()?;
+	claims.get("fhirUser")?.as_str().map(str::to_owned)
+}
@@ -0,0 +1,153 @@
+//! Multi-tenant client, routing requests to the tenants' servers.
+
+use std::{
+	collections::HashMap,
+	sync::{Arc, RwLock},
+};
+
+use reqwest::{
+	header::{HeaderMap, HeaderName, HeaderValue},
+	Url,
+};
+
+use super::{auth::AuthCallback, Client, ClientBuilder, Error, LoginManager, ResponseCache};
+use crate::version::{DefaultVersion, FhirVersion};
+
+/// Settings of a tenant of the [TenantClient].
+pub struct Tenant {
+	/// The tenant's FHIR base URL.
+	base_url: Url,
+	/// The tenant's auth callback.
+	auth_callback: Option<AuthCallback>,
+	/// Additional headers to set on the tenant's requests.
+	headers: HeaderMap,
+	/// Cache for the tenant's read responses.
+	cache: Option<Arc<dyn ResponseCache>>,
+}
+
+impl Tenant {
+	/// Create a new tenant with its FHIR base URL, e.g. with the tenant as path
+	/// segment like `https://example.com/fhir/{tenant}/`. Requests to
+	/// different origins than the base URL or to paths outside of it, e.g. of
+	/// other tenants, are rejected per tenant, unless origin mismatches are
+	/// allowed for the [TenantClient].
+	#[must_use]
+	pub fn new(base_url: Url) -> Self {
+		Self { base_url, auth_callback: None, headers: HeaderMap::new(), cache: None }
+	}
+
+	/// Set the tenant's authorization callback, see
+	/// [ClientBuilder::auth_callback].
+	#[must_use]
+	pub fn auth_callback(mut self, login_manager: impl LoginManager + 'static) -> Self {
+		self.auth_callback = Some(AuthCallback::new(login_manager));
+		self
+	}
+
+	/// Insert a header to be set on each of the tenant's requests, in addition
+	/// to the headers of the [`RequestSettings`](super::RequestSettings).
+	#[must_use]
+	pub fn header(mut self, header: HeaderName, value: HeaderValue) -> Self {
+		self.headers.insert(header, value);
+		self
+	}
+
+	/// Cache the tenant's read responses, see [ClientBuilder::cache]. Caches
+	/// are never shared between tenants.
+	#[must_use]
+	pub fn cache(mut self, cache: impl ResponseCache + 'static) -> Self {
+		self.cache = Some(Arc::new(cache));
+		self
+	}
+}
+
+impl std::fmt::Debug for Tenant {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("Tenant")
+			.field("base_url", &self.base_url)
+			.field("auth_callback", &self.auth_callback.as_ref().map(|_| "<login_manager>"))
+			.field("headers", &self.headers)
+			.field("cache", &self.cache.as_ref().map(|_| "<cache>"))
+			.finish()
+	}
+}
+
+/// Client for multiple tenants, each with its own FHIR base URL, auth callback
+/// and headers. The tenants' clients share the connection pool and the other
+/// settings of the [ClientBuilder] the tenant client was created with.
+/// Client-side rate limits apply per tenant.
+pub struct TenantClient<V = DefaultVersion> {
+	/// Template to build the tenants' clients from.
+	template: ClientBuilder<V>,
+	/// Clients of the tenants by tenant ID.
+	tenants: RwLock<HashMap<String, Client<V>>>,
+}
+
+impl<V: FhirVersion> TenantClient<V> {
+	/// Create a new tenant client with the settings of the builder, except for
+	/// the base URL, which is set per tenant, and the cache, which can only be
+	/// set per tenant. Use [Self::add_tenant] to add tenants.
+	pub fn new(builder: ClientBuilder<V>) -> Result<Self, Error> {
+		Ok(Self { template: builder.into_tenant_template()?, tenants: RwLock::new(HashMap::new()) })
+	}
+
+	/// Add a tenant, replacing any previous tenant with the same ID. Returns
+	/// the tenant's client.
+	pub fn add_tenant(&self, id: impl Into<String>, tenant: Tenant) -> Result<Client<V>, Error> {
+		let mut settings = self.template.get_request_settings();
+		for (name, value) in &tenant.headers {
+			settings = settings.header(name.clone(), value.clone());
+		}
+
+		let mut builder = self
+			.template
+			.clone()
+			.base_url(tenant.base_url)
+			.request_settings(settings)
+			.restrict_to_base_path();
+		if let Some(cache) = tenant.cache {
+			builder = builder.cache(cache);
+		}
+		let client = match tenant.auth_callback {
+			Some(auth_callback) => builder.auth_callback(auth_callback).build()?,
+			None => builder.build()?,
+		};
+
+		#[allow(clippy::expect_used)] // only happens on panics, so we can panic again.
+		self.tenants.write().expect("lock poisened").insert(id.into(), client.clone());
+		Ok(client)
+	}
+
+	/// Remove the tenant, returning its client.
+	pub fn remove_tenant(&self, id: &str) -> Option<Client<V>> {
+		#[allow(clippy::expect_used)] // only happens on panics, so we can panic again.
+		self.tenants.write().expect("lock poisened").remove(id)
+	}
+
+	/// Get the client of the tenant to make requests for it.
+	pub fn tenant(&self, id: &str) -> Result<Client<V>, Error> {
+		#[allow(clippy::expect_used)] // only happens on panics, so we can panic again.
+		let tenants = self.tenants.read().expect("lock poisened");
+		tenants.get(id).cloned().ok_or_else(|| Error::UnknownTenant(id.to_owned()))
+	}
+
+	/// Get the IDs of all tenants.
+	#[must_use]
+	pub fn tenant_ids(&self) -> Vec<String> {
+		#[allow(clippy::expect_used)] // only happens on panics, so we can panic again.
+		self.tenants.read().expect("lock poisened").keys().cloned().collect()
+	}
+}
+
+impl<V> std::fmt::Debug for TenantClient<V> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let tenants = match self.tenants.try_read() {
+			Ok(tenants) => format!("{:?}", tenants.keys().collect::<Vec<_>>()),
+			Err(_) => "<locked>".to_owned(),
+		};
+		f.debug_struct("TenantClient")
+			.field("template", &self.template)
+			.field("tenants", &tenants)
+			.finish()
+	}
+}
@@ -100,6 +100,163 @@ async fn send_custom_request_features() -> anyhow::Result<()> {
 }
 
 
+#[tokio::test]
+async fn retry_policy() -> anyhow::Result<()> {
+	setup_logging().await;
+	let server = MockServer::start().await;
+
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/my/custom/path"))
+		.respond_with(
+			ResponseTemplate::new(StatusCode::SERVICE_UNAVAILABLE)
+				.insert_header("Retry-After", "1"),
+		)
+		.with_priority(1)
+		.named("Service unavailable")
+		.expect(1)
+		.up_to_n_times(1)
+		.mount(&server)
+		.await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/my/custom/path"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK))
+		.with_priority(5)
+		.named("Success")
+		.expect(1)
+		.mount(&server)
+		.await;
+	Mock::given(matchers::method(Method::POST))
+		.and(matchers::path("/my/custom/path"))
+		.respond_with(ResponseTemplate::new(StatusCode::TOO_MANY_REQUESTS))
+		.named("Too many requests")
+		.expect(4)
+		.mount(&server)
+		.await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/my/clamped/path"))
+		.respond_with(
+			ResponseTemplate::new(StatusCode::SERVICE_UNAVAILABLE)
+				.insert_header("Retry-After", "3600"),
+		)
+		.with_priority(1)
+		.named("Long Retry-After")
+		.expect(1)
+		.up_to_n_times(1)
+		.mount(&server)
+		.await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/my/clamped/path"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK))
+		.with_priority(5)
+		.named("Success after long Retry-After")
+		.expect(1)
+		.mount(&server)
+		.await;
+
+	let settings = RequestSettings::default().retries(2).fixed_retry(Duration::from_millis(10));
+	let client = <Client>::builder()
+		.base_url(Url::parse(&server.uri())?)
+		.request_settings(settings.clone())
+		.build()?;
+	let url = format!("{}/my/custom/path", server.uri());
+
+	// Retried after waiting for `Retry-After`.
+	let start = std::time::Instant::now();
+	let response = client.send_custom_request(|http| http.get(&url)).await?;
+	assert_eq!(response.status(), StatusCode::OK);
+	assert!(start.elapsed() >= Duration::from_secs(1));
+
+	// Non-idempotent requests are not retried by default.
+	let response = client.send_custom_request(|http| http.post(&url)).await?;
+	assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+	// But they are when opted in.
+	client.set_request_settings(
+		settings.retry_policy(RetryPolicy::default().retry_non_idempotent(true)),
+	);
+	let response = client.send_custom_request(|http| http.post(&url)).await?;
+	assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+	// `Retry-After` is limited to the maximum.
+	client.set_request_settings(
+		RequestSettings::default()
+			.retries(1)
+			.retry_policy(RetryPolicy::default().max_retry_after(Some(Duration::from_millis(10)))),
+	);
+	let start = std::time::Instant::now();
+	let clamped_url = format!("{}/my/clamped/path", server.uri());
+	let response = client.send_custom_request(|http| http.get(&clamped_url)).await?;
+	assert_eq!(response.status(), StatusCode::OK);
+	assert!(start.elapsed() < Duration::from_secs(1));
+
+	server.verify().await;
+	Ok(())
+}
+
+
+#[tokio::test]
+async fn request_limits() -> anyhow::Result<()> {
+	use futures::{stream, StreamExt, TryStreamExt};
+
+	setup_logging().await;
+	let server = MockServer::start().await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/my/custom/path"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK).set_delay(Duration::from_millis(50)))
+		.expect(4)
+		.mount(&server)
+		.await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/my/retried/path"))
+		.respond_with(ResponseTemplate::new(StatusCode::SERVICE_UNAVAILABLE))
+		.with_priority(1)
+		.expect(1)
+		.up_to_n_times(1)
+		.mount(&server)
+		.await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/my/retried/path"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK))
+		.with_priority(5)
+		.expect(1)
+		.mount(&server)
+		.await;
+
+	let client = <Client>::builder()
+		.base_url(Url::parse(&server.uri())?)
+		.request_settings(RequestSettings::default().fixed_retry(Duration::from_millis(10)))
+		.rate_limit(10, 2)
+		.max_concurrent_requests(1)
+		.build()?;
+	let url = format!("{}/my/custom/path", server.uri());
+
+	let start = std::time::Instant::now();
+	let responses: Vec<_> = stream::iter(0 .. 4)
+		.map(|_| async { client.send_custom_request(|http| http.get(&url)).await })
+		.buffer_unordered(4)
+		.try_collect()
+		.await?;
+	assert_eq!(responses.len(), 4);
+	// Requests of 50 ms run one after another, the last one is additionally
+	// delayed by 50 ms to keep the rate after the burst is used up.
+	assert!(start.elapsed() >= Duration::from_millis(250));
+
+	let metrics = client.limit_metrics();
+	assert_eq!(metrics.requests, 4);
+	assert_eq!(metrics.queued, 3);
+	assert!(metrics.max_queue_time >= Duration::from_millis(50));
+
+	// Retries pass the limits again.
+	let retried_url = format!("{}/my/retried/path", server.uri());
+	let response = client.send_custom_request(|http| http.get(&retried_url)).await?;
+	assert_eq!(response.status(), StatusCode::OK);
+	assert_eq!(client.limit_metrics().requests, 6);
+
+	server.verify().await;
+	Ok(())
+}
+
+
 async fn mock_version_mismatch() -> MockServer {
 	let server = MockServer::start().await;
 
@@ -148,88 +305,1974 @@ async fn check_major_fhir_version() -> anyhow::Result<()> {
 }
 
 #[tokio::test]
-async fn check_url_origin() -> anyhow::Result<()> {
+async fn custom_middlewares() -> anyhow::Result<()> {
+	/// Middleware setting a tenant header and recording the `Authorization`
+	/// headers and response statuses.
+	struct Tenant(Arc<std::sync::Mutex<Vec<(Option<HeaderValue>, StatusCode)>>>);
+
+	#[async_trait::async_trait]
+	impl Middleware for Tenant {
+		async fn handle(
+			&self,
+			mut request: reqwest::Request,
+			next: Next<'_>,
+		) -> Result<reqwest::Response, Error> {
+			request.headers_mut().insert("X-Tenant", HeaderValue::from_static("tenant-a"));
+			let authorization = request.headers().get(header::AUTHORIZATION).cloned();
+			let response = next.run(request).await?;
+			self.0.lock().unwrap().push((authorization, response.status()));
+			Ok(response)
+		}
+	}
+
 	setup_logging().await;
 	let mocks = mock_version_mismatch().await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/Patient/2"))
+		.and(matchers::header("X-Tenant", "tenant-a"))
+		.respond_with(ResponseTemplate::new(StatusCode::NO_CONTENT))
+		.named("Tenant request")
+		.expect(1)
+		.mount(&mocks)
+		.await;
 
-	let client = <Client>::builder().base_url("http://localhost/".parse()?).build()?;
-
-	let result = client.send_custom_request(|http| http.get("http://localhost:5555/")).await;
-	assert!(matches!(result, Err(Error::DifferentOrigin(_))));
+	let statuses = Arc::new(std::sync::Mutex::new(Vec::new()));
+	let client = <Client>::builder()
+		.base_url(Url::parse(&mocks.uri())?)
+		.request_settings(
+			RequestSettings::default()
+				.header(header::AUTHORIZATION, HeaderValue::from_static("Bearer token")),
+		)
+		.middleware(Tenant(statuses.clone()))
+		.build()?;
+	let url = format!("{}/Patient/2", mocks.uri());
+	let response = client.send_custom_request(|http| http.get(url)).await?;
+	assert_eq!(response.status(), StatusCode::NO_CONTENT);
+	// Middlewares see the headers of the request settings.
+	assert_eq!(
+		*statuses.lock().unwrap(),
+		[(Some(HeaderValue::from_static("Bearer token")), StatusCode::NO_CONTENT)]
+	);
 
-	// Allow different origins this time.
+	// Disable the version check by leaving it out of the chain.
 	let client = <Client>::builder()
-		.base_url("http://localhost/".parse()?)
-		.allow_version_mismatch()
-		.allow_origin_mismatch()
+		.base_url(Url::parse(&mocks.uri())?)
+		.middlewares(vec![Arc::new(CorrelationId), Arc::new(OriginCheck), Arc::new(AuthRefresh)])
 		.build()?;
+	let url = format!("{}/Patient/1", mocks.uri());
+	let response = client.send_custom_request(|http| http.get(url)).await?;
+	assert_eq!(response.status(), StatusCode::OK);
 
+	// Leaving out the correlation ID middleware disables the header.
+	let client = <Client>::builder()
+		.base_url(Url::parse(&mocks.uri())?)
+		.middlewares(vec![Arc::new(OriginCheck), Arc::new(AuthRefresh)])
+		.build()?;
 	let url = format!("{}/Patient/1", mocks.uri());
 	let response = client.send_custom_request(|http| http.get(url)).await?;
 	assert_eq!(response.status(), StatusCode::OK);
+	let requests = mocks.received_requests().await.unwrap_or_default();
+	let request = requests.last().unwrap();
+	assert!(!request.headers.contains_key("X-Correlation-Id"));
+	assert!(request.headers.contains_key("X-Request-Id"));
 
+	mocks.verify().await;
 	Ok(())
 }
 
+#[tokio::test]
+async fn custom_transport() -> anyhow::Result<()> {
+	/// Transport answering with the request path and recording the requests.
+	struct Echo(std::sync::Mutex<Vec<http::Request<Vec<u8>>>>);
+
+	#[async_trait::async_trait]
+	impl HttpTransport for Echo {
+		async fn send(
+			&self,
+			request: http::Request<Vec<u8>>,
+		) -> Result<http::Response<Vec<u8>>, Error> {
+			let response = http::Response::new(request.uri().path().as_bytes().to_vec());
+			self.0.lock().unwrap().push(request);
+			Ok(response)
+		}
+	}
 
-async fn mock_x_correlation_id() -> MockServer {
-	let server = MockServer::start().await;
+	/// Transport answering as if it followed a redirect.
+	struct Redirected;
+
+	#[async_trait::async_trait]
+	impl HttpTransport for Redirected {
+		async fn send(
+			&self,
+			_request: http::Request<Vec<u8>>,
+		) -> Result<http::Response<Vec<u8>>, Error> {
+			let mut response = http::Response::new(Vec::new());
+			let url = Url::parse("http://fhir.invalid/Patient/2")
+				.map_err(|err| Error::UrlParse(err.to_string()))?;
+			response.extensions_mut().insert(url);
+			Ok(response)
+		}
+	}
+
+	/// Transport never answering.
+	struct Hanging;
+
+	#[async_trait::async_trait]
+	impl HttpTransport for Hanging {
+		async fn send(
+			&self,
+			_request: http::Request<Vec<u8>>,
+		) -> Result<http::Response<Vec<u8>>, Error> {
+			std::future::pending().await
+		}
+	}
+
+	setup_logging().await;
+	let transport = Arc::new(Echo(std::sync::Mutex::new(Vec::new())));
+	let client = <Client>::builder()
+		.base_url(Url::parse("http://fhir.invalid/")?)
+		.request_settings(
+			RequestSettings::default()
+				.header(header::ACCEPT_LANGUAGE, HeaderValue::from_static("de")),
+		)
+		.transport(transport.clone())
+		.build()?;
+	let response = client
+		.send_custom_request(|http| http.post("http://fhir.invalid/Patient").body("{}"))
+		.await?;
+	assert_eq!(response.status(), StatusCode::OK);
+	assert_eq!(response.url().as_str(), "http://fhir.invalid/Patient");
+	assert_eq!(response.text().await?, "/Patient");
+
+	let requests = transport.0.lock().unwrap();
+	assert_eq!(requests.len(), 1);
+	assert_eq!(requests[0].method(), Method::POST);
+	assert_eq!(requests[0].body(), b"{}");
+	assert_eq!(requests[0].headers().get(header::ACCEPT_LANGUAGE).unwrap(), "de");
+	assert!(requests[0].headers().contains_key("X-Request-Id"));
+	drop(requests);
+
+	// Timeouts apply to custom transports as well.
+	let settings = RequestSettings::default().timeout(Some(Duration::from_millis(50))).retries(0);
+	let client = <Client>::builder()
+		.base_url(Url::parse("http://fhir.invalid/")?)
+		.request_settings(settings)
+		.transport(Hanging)
+		.build()?;
+	let result = client.send_custom_request(|http| http.get("http://fhir.invalid/Patient/1")).await;
+	assert!(matches!(result, Err(Error::Timeout(_))));
+
+	// Transports can report the final URL.
+	let client = <Client>::builder()
+		.base_url(Url::parse("http://fhir.invalid/")?)
+		.transport(Redirected)
+		.build()?;
+	let response =
+		client.send_custom_request(|http| http.get("http://fhir.invalid/Patient/1")).await?;
+	assert_eq!(response.url().as_str(), "http://fhir.invalid/Patient/2");
 
+	// The default transport streams the response and keeps the final URL after redirects.
+	let server = MockServer::start().await;
 	Mock::given(matchers::method(Method::GET))
-		.and(matchers::header("X-Correlation-Id", "custom-id"))
-		.respond_with(ResponseTemplate::new(StatusCode::UNAUTHORIZED))
-		.with_priority(1)
-		.named("Custom correlation ID failure retry")
+		.and(matchers::path("/Patient/1"))
+		.respond_with(
+			ResponseTemplate::new(StatusCode::MOVED_PERMANENTLY)
+				.insert_header("Location", format!("{}/Patient/2", server.uri())),
+		)
 		.expect(1)
-		.up_to_n_times(1)
 		.mount(&server)
 		.await;
-
 	Mock::given(matchers::method(Method::GET))
-		.and(matchers::header("X-Correlation-Id", "custom-id"))
+		.and(matchers::path("/Patient/2"))
 		.respond_with(ResponseTemplate::new(StatusCode::OK))
-		.with_priority(2)
-		.named("Custom correlation ID success")
 		.expect(1)
-		.up_to_n_times(1)
 		.mount(&server)
 		.await;
+	let client = <Client>::builder().base_url(Url::parse(&server.uri())?).build()?;
+	let url = format!("{}/Patient/1", server.uri());
+	let response = client.send_custom_request(|http| http.get(url)).await?;
+	assert_eq!(response.url().path(), "/Patient/2");
+	server.verify().await;
+
+	Ok(())
+}
 
+#[tokio::test]
+async fn tenant_client() -> anyhow::Result<()> {
+	setup_logging().await;
+	let server = MockServer::start().await;
 	Mock::given(matchers::method(Method::GET))
-		.and(matchers::header_exists("X-Correlation-Id"))
+		.and(matchers::path("/fhir/a/Patient/1"))
+		.and(matchers::header("X-Tenant", "a"))
 		.respond_with(ResponseTemplate::new(StatusCode::OK))
-		.with_priority(100)
-		.named("Any correlation ID success")
+		.named("Tenant A")
+		.expect(1)
+		.mount(&server)
+		.await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/fhir/b/Patient/1"))
+		.and(matchers::header("Authorization", "Bearer b"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK))
+		.with_priority(1)
+		.named("Tenant B authorized")
+		.expect(1)
+		.mount(&server)
+		.await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/fhir/b/Patient/1"))
+		.respond_with(ResponseTemplate::new(StatusCode::UNAUTHORIZED))
+		.with_priority(5)
+		.named("Tenant B unauthorized")
 		.expect(1)
-		.up_to_n_times(1)
 		.mount(&server)
 		.await;
 
-	server
+	let tenants = TenantClient::new(<Client>::builder())?;
+	tenants.add_tenant(
+		"a",
+		Tenant::new(Url::parse(&format!("{}/fhir/a/", server.uri()))?)
+			.header(header::HeaderName::from_static("x-tenant"), HeaderValue::from_static("a")),
+	)?;
+	tenants.add_tenant(
+		"b",
+		Tenant::new(Url::parse(&format!("{}/fhir/b/", server.uri()))?).auth_callback(
+			|_http: reqwest::Client| async move { anyhow::Ok(HeaderValue::from_static("Bearer b")) },
+		),
+	)?;
+	let mut ids = tenants.tenant_ids();
+	ids.sort();
+	assert_eq!(ids, ["a", "b"]);
+
+	for tenant in ["a", "b"] {
+		let url = format!("{}/fhir/{tenant}/Patient/1", server.uri());
+		let response = tenants.tenant(tenant)?.send_custom_request(|http| http.get(url)).await?;
+		assert_eq!(response.status(), StatusCode::OK);
+	}
+
+	// The origin is checked against the tenant's base URL.
+	let result =
+		tenants.tenant("a")?.send_custom_request(|http| http.get("http://other.invalid/")).await;
+	assert!(matches!(result, Err(Error::DifferentOrigin(_))));
+	// Other tenants on the same origin are rejected as well.
+	let url = format!("{}/fhir/b/Patient/1", server.uri());
+	let result = tenants.tenant("a")?.send_custom_request(|http| http.get(url)).await;
+	assert!(matches!(result, Err(Error::DifferentBasePath(_))));
+	let url = format!("{}/fhir/ab/Patient/1", server.uri());
+	let result = tenants.tenant("a")?.send_custom_request(|http| http.get(url)).await;
+	assert!(matches!(result, Err(Error::DifferentBasePath(_))));
+	assert!(matches!(tenants.tenant("c"), Err(Error::UnknownTenant(_))));
+
+	server.verify().await;
+	Ok(())
+}
+
+/// Start an HTTPS server with the certificates in `tests/certs`, requiring a
+/// client certificate of the test CA. It responds with the common name of the
+/// client certificate.
+fn start_mtls_server() -> anyhow::Result<Url> {
+	use std::{
+		io::{Read, Write},
+		net::TcpListener,
+	};
+
+	use openssl::{
+		nid::Nid,
+		ssl::{SslAcceptor, SslFiletype, SslMethod, SslVerifyMode},
+	};
+
+	let certs = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/certs");
+	let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls())?;
+	acceptor.set_private_key_file(format!("{certs}/server-key.pem"), SslFiletype::PEM)?;
+	acceptor.set_certificate_chain_file(format!("{certs}/server.pem"))?;
+	acceptor.set_ca_file(format!("{certs}/ca.pem"))?;
+	acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+	let acceptor = acceptor.build();
+
+	let listener = TcpListener::bind("127.0.0.1:0")?;
+	let url = Url::parse(&format!("https://127.0.0.1:{}/", listener.local_addr()?.port()))?;
+	std::thread::spawn(move || {
+		for stream in listener.incoming().flatten() {
+			// Failing handshakes are part of the tests.
+			let Ok(mut stream) = acceptor.accept(stream) else {
+				continue;
+			};
+			let common_name = stream
+				.ssl()
+				.peer_certificate()
+				.and_then(|certificate| {
+					let entry =
+						certificate.subject_name().entries_by_nid(Nid::COMMONNAME).next()?;
+					entry.data().as_utf8().ok().map(|name| name.to_string())
+				})
+				.unwrap_or_default();
+
+			let mut request = Vec::new();
+			let mut buffer = [0; 4096];
+			while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+				match stream.read(&mut buffer) {
+					Ok(0) | Err(_) => break,
+					Ok(read) => request.extend_from_slice(&buffer[.. read]),
+				}
+			}
+			let response = format!(
+				"HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{common_name}",
+				common_name.len()
+			);
+			_ = stream.write_all(response.as_bytes());
+			_ = stream.shutdown();
+		}
+	});
+	Ok(url)
 }
 
 #[tokio::test]
-async fn use_correlation_id() -> anyhow::Result<()> {
+async fn mutual_tls() -> anyhow::Result<()> {
 	setup_logging().await;
-	let mocks = mock_x_correlation_id().await;
+	let url = start_mtls_server()?;
+	let certs = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/certs");
+	let ca = reqwest::Certificate::from_pem(&std::fs::read(format!("{certs}/ca.pem"))?)?;
+	let pem_identity = reqwest::Identity::from_pkcs8_pem(
+		&std::fs::read(format!("{certs}/client.pem"))?,
+		&std::fs::read(format!("{certs}/client-key.pem"))?,
+	)?;
+	let pkcs12_identity = reqwest::Identity::from_pkcs12_der(
+		&std::fs::read(format!("{certs}/client.p12"))?,
+		"password",
+	)?;
+	let no_retries = RequestSettings::default().retries(0);
 
 	let client = <Client>::builder()
-		.base_url(Url::parse(&mocks.uri())?)
-		.auth_callback(move |_http: reqwest::Client| {
-			std::future::ready(anyhow::Ok(HeaderValue::from_static("Bearer <mock>")))
-		})
+		.base_url(url.clone())
+		.user_agent("mtls-test".to_owned())
+		.add_root_certificate(ca.clone())
+		.identity(pem_identity.clone())
 		.build()?;
+	let response = client.send_custom_request(|http| http.get(url.clone())).await?;
+	assert_eq!(response.text().await?, "fhir-sdk test client");
 
-	let url = client.base_url();
+	let client = <Client>::builder()
+		.base_url(url.clone())
+		.add_root_certificate(ca.clone())
+		.identity(pkcs12_identity)
+		.max_tls_version(reqwest::tls::Version::TLS_1_2)
+		.build()?;
+	let response = client.send_custom_request(|http| http.get(url.clone())).await?;
+	assert_eq!(response.text().await?, "fhir-sdk test client");
 
-	let response = client
-		.send_custom_request(|http| http.get(url.clone()).header("X-Correlation-Id", "custom-id"))
-		.await?;
-	assert_eq!(response.status(), StatusCode::OK);
+	// Missing client certificate.
+	let client = <Client>::builder()
+		.base_url(url.clone())
+		.request_settings(no_retries.clone())
+		.add_root_certificate(ca.clone())
+		.build()?;
+	let result = async {
+		let response = client.send_custom_request(|http| http.get(url.clone())).await?;
+		anyhow::Ok(response.text().await?)
+	};
+	assert!(result.await.is_err());
 
-	let response = client.send_custom_request(|http| http.get(url.clone())).await?;
-	assert_eq!(response.status(), StatusCode::OK);
+	// Server certificate of an untrusted CA.
+	let client = <Client>::builder()
+		.base_url(url.clone())
+		.request_settings(no_retries)
+		.identity(pem_identity.clone())
+		.build()?;
+	let result = client.send_custom_request(|http| http.get(url.clone())).await;
+	assert!(result.is_err());
+
+	// TLS settings cannot be applied to a custom Reqwest client.
+	let result = <Client>::builder()
+		.base_url(url.clone())
+		.client(reqwest::Client::new())
+		.identity(pem_identity)
+		.build();
+	assert!(matches!(result, Err(Error::BuilderConflict("identity"))));
+
+	Ok(())
+}
+
+#[cfg(feature = "smart")]
+#[tokio::test]
+async fn smart_app_launch() -> anyhow::Result<()> {
+	use std::collections::HashMap;
+
+	use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+	setup_logging().await;
+	let server = MockServer::start().await;
+	let base_url = Url::parse(&format!("{}/fhir/", server.uri()))?;
+	let patient_url = format!("{}/fhir/Patient/123", server.uri());
+	let id_token =
+		format!("e30.{}.c2ln", URL_SAFE_NO_PAD.encode(r#"{"fhirUser":"Practitioner/9"}"#));
+
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/fhir/.well-known/smart-configuration"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK).set_body_json(json!({
+			"authorization_endpoint": format!("{}/authorize", server.uri()),
+			"token_endpoint": format!("{}/token", server.uri()),
+			"capabilities": ["launch-ehr", "client-public"],
+			"code_challenge_methods_supported": ["S256"],
+		})))
+		.named("Discovery")
+		.expect(1)
+		.mount(&server)
+		.await;
+	Mock::given(matchers::method(Method::POST))
+		.and(matchers::path("/token"))
+		.and(matchers::body_string_contains("grant_type=authorization_code"))
+		.and(matchers::body_string_contains("code=abc"))
+		.and(matchers::body_string_contains("code_verifier="))
+		.and(matchers::body_string_contains("client_id=my-app"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK).set_body_json(json!({
+			"access_token": "first",
+			"token_type": "Bearer",
+			"expires_in": 3600,
+			"refresh_token": "refresh",
+			"id_token": id_token,
+			"patient": "123",
+			"encounter": "456",
+		})))
+		.named("Code exchange")
+		.expect(1)
+		.mount(&server)
+		.await;
+	Mock::given(matchers::method(Method::POST))
+		.and(matchers::path("/token"))
+		.and(matchers::body_string_contains("grant_type=refresh_token"))
+		.and(matchers::body_string_contains("refresh_token=refresh"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK).set_body_json(json!({
+			"access_token": "second",
+			"token_type": "Bearer",
+			"expires_in": 3600,
+		})))
+		.named("Refresh")
+		.expect(1)
+		.mount(&server)
+		.await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/fhir/Patient/123"))
+		.and(matchers::header("Authorization", "Bearer second"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK))
+		.with_priority(1)
+		.named("Refreshed token")
+		.expect(1)
+		.mount(&server)
+		.await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/fhir/Patient/123"))
+		.and(matchers::header("Authorization", "Bearer first"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK))
+		.up_to_n_times(1)
+		.with_priority(2)
+		.named("Initial token")
+		.expect(1)
+		.mount(&server)
+		.await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/fhir/Patient/123"))
+		.respond_with(ResponseTemplate::new(StatusCode::UNAUTHORIZED))
+		.with_priority(5)
+		.named("Unauthorized")
+		.expect(2)
+		.mount(&server)
+		.await;
+
+	let http = reqwest::Client::new();
+	let redirect_uri = Url::parse("https://app.example/callback")?;
+	let app = SmartApp::discover(&http, base_url.clone(), "my-app", redirect_uri).await?;
+	let authorization = app.authorize(Some("launch-id"));
+	let params: HashMap<_, _> = authorization.url().query_pairs().into_owned().collect();
+	assert_eq!(params["aud"], base_url.as_str());
+	assert_eq!(params["launch"], "launch-id");
+	assert_eq!(params["state"], authorization.state());
+	assert_eq!(params["code_challenge_method"], "S256");
+	assert_eq!(params["code_challenge"].len(), 43);
+
+	let redirect = Url::parse("https://app.example/callback?code=abc&state=wrong")?;
+	assert!(matches!(authorization.code_from_redirect(&redirect), Err(Error::Smart(_))));
+	let redirect = Url::parse(&format!(
+		"https://app.example/callback?code=abc&state={}",
+		authorization.state()
+	))?;
+	let code = authorization.code_from_redirect(&redirect)?;
+	let session = app.exchange_code(&http, &authorization, &code).await?;
+	assert_eq!(
+		session.launch_context(),
+		&LaunchContext {
+			patient: Some("123".to_owned()),
+			encounter: Some("456".to_owned()),
+			fhir_user: Some("Practitioner/9".to_owned()),
+		}
+	);
+
+	// The session hands out its access token first and refreshes it once it is
+	// rejected.
+	let client = <Client>::builder().base_url(base_url).auth_callback(session).build()?;
+	for _ in 0 .. 2 {
+		let url = patient_url.clone();
+		let response = client.send_custom_request(|http| http.get(url)).await?;
+		assert_eq!(response.status(), StatusCode::OK);
+	}
+
+	server.verify().await;
+	Ok(())
+}
+
+#[cfg(feature = "smart")]
+#[tokio::test]
+async fn smart_confidential_client() -> anyhow::Result<()> {
+	use base64::{engine::general_purpose::STANDARD, Engine};
+
+	setup_logging().await;
+	let server = MockServer::start().await;
+	// Client ID and secret are form-url-encoded before basic authentication.
+	let credentials = STANDARD.encode("my+app:s%3Acret%26");
+	Mock::given(matchers::method(Method::POST))
+		.and(matchers::path("/token"))
+		.and(matchers::header("Authorization", format!("Basic {credentials}")))
+		.and(matchers::body_string_contains("grant_type=refresh_token"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK).set_body_json(json!({
+			"access_token": "token",
+			"token_type": "Bearer",
+		})))
+		.expect(1)
+		.mount(&server)
+		.await;
+
+	let config = SmartConfiguration {
+		authorization_endpoint: Url::parse(&format!("{}/authorize", server.uri()))?,
+		token_endpoint: Url::parse(&format!("{}/token", server.uri()))?,
+		issuer: None,
+		revocation_endpoint: None,
+		capabilities: Vec::new(),
+		scopes_supported: Vec::new(),
+		code_challenge_methods_supported: Vec::new(),
+	};
+	let app = SmartApp::new(
+		config,
+		Url::parse(&format!("{}/fhir/", server.uri()))?,
+		"my app",
+		Url::parse("https://app.example/callback")?,
+	)
+	.client_secret("s:cret&");
+	let token = app.refresh(&reqwest::Client::new(), "refresh").await?;
+	assert_eq!(token.access_token, "token");
+
+	server.verify().await;
+	Ok(())
+}
+
+#[cfg(feature = "fixtures")]
+#[tokio::test]
+async fn record_replay_fixtures() -> anyhow::Result<()> {
+	setup_logging().await;
+	let server = MockServer::start().await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/Patient"))
+		.and(matchers::query_param("name", "Max"))
+		.respond_with(
+			ResponseTemplate::new(StatusCode::OK)
+				.insert_header("ETag", "W/\"1\"")
+				.set_body_json(json!({ "resourceType": "Bundle" })),
+		)
+		.expect(1)
+		.mount(&server)
+		.await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/Binary/1"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK).set_body_bytes(vec![0xff, 0xfe, 0]))
+		.expect(1)
+		.mount(&server)
+		.await;
+	let base_url = server.uri();
+	let path = std::env::temp_dir().join(format!("fhir-sdk-fixture-{}.json", uuid::Uuid::new_v4()));
+
+	let recorder = Arc::new(FixtureRecorder::new(&path));
+	let client = <Client>::builder()
+		.base_url(Url::parse(&base_url)?)
+		.middleware(recorder.clone())
+		.build()?;
+	let url = format!("{base_url}/Patient?name=Max&_count=2");
+	let response = client.send_custom_request(|http| http.get(url)).await?;
+	assert_eq!(response.status(), StatusCode::OK);
+	assert_eq!(response.json::<serde_json::Value>().await?, json!({ "resourceType": "Bundle" }));
+	// Bodies that are not valid UTF-8 are recorded base64 encoded.
+	let url = format!("{base_url}/Binary/1");
+	let response = client.send_custom_request(|http| http.get(url)).await?;
+	assert_eq!(response.bytes().await?, vec![0xff_u8, 0xfe, 0]);
+	let interactions = recorder.interactions();
+	assert_eq!(interactions.len(), 2);
+	assert!(!interactions[0].response.body_base64);
+	assert!(interactions[1].response.body_base64);
+	server.verify().await;
+	drop(server);
+
+	let replayer = Arc::new(FixtureReplayer::load(&path)?);
+	std::fs::remove_file(&path)?;
+	let client = <Client>::builder()
+		.base_url(Url::parse(&base_url)?)
+		.middleware(replayer.clone())
+		.build()?;
+	// The query order does not matter.
+	let url = format!("{base_url}/Patient?_count=2&name=Max");
+	let response = client.send_custom_request(|http| http.get(&url)).await?;
+	assert_eq!(response.status(), StatusCode::OK);
+	assert_eq!(response.headers().get("etag"), Some(&HeaderValue::from_static("W/\"1\"")));
+	assert_eq!(response.json::<serde_json::Value>().await?, json!({ "resourceType": "Bundle" }));
+	let binary_url = format!("{base_url}/Binary/1");
+	let response = client.send_custom_request(|http| http.get(binary_url)).await?;
+	assert_eq!(response.bytes().await?, vec![0xff_u8, 0xfe, 0]);
+	assert!(replayer.unused().is_empty());
+
+	// Every interaction is only replayed once.
+	let result = client.send_custom_request(|http| http.get(&url)).await;
+	assert!(matches!(result, Err(Error::UnmatchedFixture(_))));
+
+	Ok(())
+}
+
+#[tokio::test]
+async fn check_url_origin() -> anyhow::Result<()> {
+	setup_logging().await;
+	let mocks = mock_version_mismatch().await;
+
+	let client = <Client>::builder().base_url("http://localhost/".parse()?).build()?;
+
+	let result = client.send_custom_request(|http| http.get("http://localhost:5555/")).await;
+	assert!(matches!(result, Err(Error::DifferentOrigin(_))));
+
+	// Allow different origins this time.
+	let client = <Client>::builder()
+		.base_url("http://localhost/".parse()?)
+		.allow_version_mismatch()
+		.allow_origin_mismatch()
+		.build()?;
+
+	let url = format!("{}/Patient/1", mocks.uri());
+	let response = client.send_custom_request(|http| http.get(url)).await?;
+	assert_eq!(response.status(), StatusCode::OK);
+
+	Ok(())
+}
+
+
+async fn mock_x_correlation_id() -> MockServer {
+	let server = MockServer::start().await;
+
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::header("X-Correlation-Id", "custom-id"))
+		.respond_with(ResponseTemplate::new(StatusCode::UNAUTHORIZED))
+		.with_priority(1)
+		.named("Custom correlation ID failure retry")
+		.expect(1)
+		.up_to_n_times(1)
+		.mount(&server)
+		.await;
+
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::header("X-Correlation-Id", "custom-id"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK))
+		.with_priority(2)
+		.named("Custom correlation ID success")
+		.expect(1)
+		.up_to_n_times(1)
+		.mount(&server)
+		.await;
+
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::header_exists("X-Correlation-Id"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK))
+		.with_priority(100)
+		.named("Any correlation ID success")
+		.expect(1)
+		.up_to_n_times(1)
+		.mount(&server)
+		.await;
+
+	server
+}
+
+#[tokio::test]
+async fn use_correlation_id() -> anyhow::Result<()> {
+	setup_logging().await;
+	let mocks = mock_x_correlation_id().await;
+
+	let client = <Client>::builder()
+		.base_url(Url::parse(&mocks.uri())?)
+		.auth_callback(move |_http: reqwest::Client| {
+			std::future::ready(anyhow::Ok(HeaderValue::from_static("Bearer <mock>")))
+		})
+		.build()?;
+
+	let url = client.base_url();
+
+	let response = client
+		.send_custom_request(|http| http.get(url.clone()).header("X-Correlation-Id", "custom-id"))
+		.await?;
+	assert_eq!(response.status(), StatusCode::OK);
+
+	let response = client.send_custom_request(|http| http.get(url.clone())).await?;
+	assert_eq!(response.status(), StatusCode::OK);
+
+	mocks.verify().await;
+	Ok(())
+}
+
+#[cfg(feature = "r5")]
+async fn mock_response_cache() -> MockServer {
+	let server = MockServer::start().await;
+	let patient = json!({ "resourceType": "Patient", "id": "1" });
+
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/Patient/1"))
+		.and(matchers::header("If-None-Match", "W/\"1\""))
+		.respond_with(ResponseTemplate::new(StatusCode::NOT_MODIFIED))
+		.with_priority(1)
+		.named("Revalidated read")
+		.expect(1)
+		.mount(&server)
+		.await;
+
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/Patient/1"))
+		.respond_with(
+			ResponseTemplate::new(StatusCode::OK)
+				.insert_header("ETag", "W/\"1\"")
+				.set_body_json(patient),
+		)
+		.with_priority(5)
+		.named("Full read")
+		.expect(2)
+		.mount(&server)
+		.await;
+
+	Mock::given(matchers::method(Method::PUT))
+		.and(matchers::path("/Patient/1"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK).insert_header("ETag", "W/\"2\""))
+		.named("Update")
+		.expect(1)
+		.mount(&server)
+		.await;
+
+	Mock::given(matchers::method(Method::POST))
+		.and(matchers::path("/"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK).set_body_json(json!({
+			"resourceType": "Bundle",
+			"type": "batch-response",
+			"entry": [{ "response": { "status": "204 No Content" } }],
+		})))
+		.named("Batch delete")
+		.expect(1)
+		.mount(&server)
+		.await;
+
+	server
+}
+
+#[cfg(feature = "r5")]
+#[tokio::test]
+async fn response_cache() -> anyhow::Result<()> {
+	use fhir_model::r5::resources::ResourceType;
+
+	setup_logging().await;
+	let mocks = mock_response_cache().await;
+
+	let cache = Arc::new(MemoryCache::new(10));
+	let client =
+		<Client>::builder().base_url(Url::parse(&mocks.uri())?).cache(cache.clone()).build()?;
+	let url = client.url(&["Patient", "1"]);
+
+	let key = client.cache_key(&url);
+
+	let first: Option<serde_json::Value> = client.read_generic(url.clone(), None).await?;
+	assert!(cache.get(&key).is_some());
+	let second: Option<serde_json::Value> = client.read_generic(url.clone(), None).await?;
+	assert_eq!(first, second);
+
+	let patient = json!({ "resourceType": "Patient", "id": "1" });
+	client.update_generic("Patient", "1", &patient, None).await?;
+	assert!(cache.get(&key).is_none());
+
+	let third: Option<serde_json::Value> = client.read_generic(url, None).await?;
+	assert_eq!(first, third);
+	assert!(cache.get(&key).is_some());
+
+	// Batches invalidate the written resources once they were sent.
+	let mut batch = client.batch();
+	batch.delete(ResourceType::Patient, "1");
+	assert!(cache.get(&key).is_some());
+	batch.send().await?.into_result()?;
+	assert!(cache.get(&key).is_none());
+
+	mocks.verify().await;
+	Ok(())
+}
+
+#[cfg(feature = "r5")]
+#[tokio::test]
+async fn resolve_references() -> anyhow::Result<()> {
+	use fhir_model::r5::{
+		resources::{Patient, Resource, ResourceType},
+		types::Reference,
+	};
+
+	use crate::extensions::{AnyResource, GenericResource};
+
+	setup_logging().await;
+	let server = MockServer::start().await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/Patient/2"))
+		.respond_with(
+			ResponseTemplate::new(StatusCode::OK)
+				.set_body_json(json!({ "resourceType": "Patient", "id": "2" })),
+		)
+		.named("Read referenced")
+		.expect(1)
+		.mount(&server)
+		.await;
+
+	let client = <Client>::builder().base_url(Url::parse(&server.uri())?).build()?;
+	let observation: Resource = serde_json::from_value(json!({
+		"resourceType": "Observation",
+		"id": "1",
+		"status": "final",
+		"code": { "text": "test" },
+		"contained": [{ "resourceType": "Patient", "id": "p1" }],
+	}))?;
+	let bundle: <FhirR5 as FhirVersion>::Bundle = serde_json::from_value(json!({
+		"resourceType": "Bundle",
+		"type": "transaction",
+		"entry": [{
+			"fullUrl": "urn:uuid:4b5f5a3c-0000-4000-8000-000000000000",
+			"resource": { "resourceType": "Patient", "id": "u1" },
+		}],
+	}))?;
+	let resolver = client.reference_resolver().contained_in(&observation).bundle(&bundle);
+
+	let resolved = resolver.resolve(&Reference::local(ResourceType::Patient, "p1")).await?;
+	assert_eq!(resolved.source, ResolutionSource::Contained);
+	assert_eq!(resolved.resource.id(), Some("p1"));
+
+	let reference = Reference::builder()
+		.reference("urn:uuid:4b5f5a3c-0000-4000-8000-000000000000".to_owned())
+		.build()?;
+	let resolved = resolver.resolve(&reference).await?;
+	assert_eq!(resolved.source, ResolutionSource::Bundle);
+	assert_eq!(resolved.resource.id(), Some("u1"));
+
+	let reference = Reference::relative(ResourceType::Patient, "2");
+	let resolved = resolver.resolve_as::<Patient>(&reference).await?;
+	assert_eq!(resolved.source, ResolutionSource::Server);
+	assert_eq!(resolved.resource.id(), Some("2"));
+
+	let missing = resolver.resolve(&Reference::local(ResourceType::Patient, "p2")).await;
+	assert!(matches!(missing, Err(Error::ResourceNotFound(_))));
+
+	server.verify().await;
+	Ok(())
+}
+
+#[cfg(feature = "r5")]
+#[tokio::test]
+async fn resolve_references_in_bulk() -> anyhow::Result<()> {
+	use fhir_model::r5::resources::Observation;
+
+	setup_logging().await;
+	let server = MockServer::start().await;
+	Mock::given(matchers::method(Method::POST))
+		.and(matchers::path("/"))
+		.and(matchers::body_partial_json(json!({
+			"type": "batch",
+			"entry": [
+				{ "request": { "method": "GET", "url": "Patient/1" } },
+				{ "request": { "method": "GET", "url": "Patient/2" } },
+			],
+		})))
+		.respond_with(ResponseTemplate::new(StatusCode::OK).set_body_json(json!({
+			"resourceType": "Bundle",
+			"type": "batch-response",
+			"entry": [
+				{
+					"resource": { "resourceType": "Patient", "id": "1" },
+					"response": { "status": "200 OK" },
+				},
+				{
+					"resource": { "resourceType": "OperationOutcome", "issue": [] },
+					"response": { "status": "404 Not Found" },
+				},
+			],
+		})))
+		.named("Batch read")
+		.expect(2)
+		.mount(&server)
+		.await;
+	// References to other servers are read one by one.
+	let other = MockServer::start().await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/Patient/3"))
+		.respond_with(
+			ResponseTemplate::new(StatusCode::OK)
+				.set_body_json(json!({ "resourceType": "Patient", "id": "3" })),
+		)
+		.named("Other read")
+		.expect(2)
+		.mount(&other)
+		.await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/Patient/4"))
+		.respond_with(ResponseTemplate::new(StatusCode::NOT_FOUND))
+		.named("Other read not found")
+		.expect(2)
+		.mount(&other)
+		.await;
+
+	let client =
+		<Client>::builder().base_url(Url::parse(&server.uri())?).allow_origin_mismatch().build()?;
+	let other_patient = format!("{}/Patient/3", other.uri());
+	let missing_patient = format!("{}/Patient/4", other.uri());
+	let observations: Vec<Observation> = [
+		"Patient/1",
+		"Patient/2",
+		"Patient/1",
+		"#local",
+		other_patient.as_str(),
+		missing_patient.as_str(),
+	]
+	.into_iter()
+	.map(|reference| {
+		serde_json::from_value(json!({
+			"resourceType": "Observation",
+			"status": "final",
+			"code": { "text": "test" },
+			"subject": { "reference": reference },
+		}))
+	})
+	.collect::<Result<_, _>>()?;
+
+	let resolved = client
+		.resolve_references(&observations, |observation| observation.subject.as_ref())
+		.await?;
+	assert_eq!(resolved.len(), 2);
+	assert!(resolved.contains_key("Patient/1"));
+	assert!(resolved.contains_key(&other_patient));
+
+	let resolved = client.resolve_reference_path(&observations, "Observation.subject").await?;
+	assert_eq!(resolved.len(), 2);
+	assert!(resolved.contains_key("Patient/1"));
+
+	server.verify().await;
+	other.verify().await;
+	Ok(())
+}
+
+#[cfg(feature = "r5")]
+#[tokio::test]
+async fn batch_response() -> anyhow::Result<()> {
+	use fhir_model::r5::resources::{Patient, ResourceType};
+
+	setup_logging().await;
+	let server = MockServer::start().await;
+	Mock::given(matchers::method(Method::POST))
+		.and(matchers::path("/"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK).set_body_json(json!({
+			"resourceType": "Bundle",
+			"type": "batch-response",
+			"entry": [
+				{ "response": { "status": "201 Created", "location": "Patient/7/_history/1" } },
+				{
+					"response": {
+						"status": "404 Not Found",
+						"outcome": {
+							"resourceType": "OperationOutcome",
+							"issue": [{ "severity": "error", "code": "not-found" }],
+						},
+					},
+				},
+			],
+		})))
+		.named("Batch")
+		.expect(1)
+		.mount(&server)
+		.await;
+
+	let client = <Client>::builder().base_url(Url::parse(&server.uri())?).build()?;
+	let mut batch = client.batch();
+	let temp_id = batch.create(Patient::builder().build()?);
+	batch.delete(ResourceType::Patient, "8");
+	let response = batch.send().await?;
+
+	assert_eq!(response.resolve_temp_id(&temp_id), Some("7"));
+	let entries = response.entries();
+	assert_eq!(entries[0].status(), Some(StatusCode::CREATED));
+	assert_eq!(entries[0].version_id(), Some("1"));
+	assert!(entries[0].result().is_ok());
+	assert_eq!(entries[1].status(), Some(StatusCode::NOT_FOUND));
+	assert!(matches!(
+		entries[1].result(),
+		Err(Error::OperationOutcomeR5(StatusCode::NOT_FOUND, _))
+	));
+	assert!(!response.is_success());
+	assert!(response.into_result().is_err());
+
+	server.verify().await;
+	Ok(())
+}
+
+#[cfg(feature = "r5")]
+#[tokio::test]
+async fn batch_chunking() -> anyhow::Result<()> {
+	use fhir_model::r5::resources::Patient;
+
+	setup_logging().await;
+	let server = MockServer::start().await;
+	Mock::given(matchers::method(Method::POST))
+		.and(matchers::path("/"))
+		.respond_with(|request: &wiremock::Request| {
+			let bundle: serde_json::Value = request.body_json().unwrap();
+			let entries: Vec<_> = bundle["entry"]
+				.as_array()
+				.unwrap()
+				.iter()
+				.map(|entry| {
+					// Echo the temporary ID as server ID to check the order.
+					let id = entry["fullUrl"].as_str().unwrap().trim_start_matches("urn:uuid:");
+					json!({
+						"response": { "status": "201 Created", "location": format!("Patient/{id}") },
+					})
+				})
+				.collect();
+			ResponseTemplate::new(StatusCode::OK).set_body_json(json!({
+				"resourceType": "Bundle",
+				"type": "batch-response",
+				"entry": entries,
+			}))
+		})
+		.named("Batch chunk")
+		.expect(3)
+		.mount(&server)
+		.await;
+
+	let client = <Client>::builder().base_url(Url::parse(&server.uri())?).build()?;
+	let mut batch = client.batch().max_entries(2).concurrency(2);
+	let temp_ids: Vec<String> =
+		(0 .. 5).map(|_| batch.create(Patient::builder().build().unwrap())).collect();
+	let response = batch.send().await?.into_result()?;
+
+	assert_eq!(response.entries().len(), 5);
+	for (temp_id, entry) in temp_ids.iter().zip(response.entries()) {
+		assert_eq!(response.resolve_temp_id(temp_id), entry.id());
+		assert_eq!(entry.id(), temp_id.strip_prefix("urn:uuid:"));
+	}
+
+	server.verify().await;
+	Ok(())
+}
+
+/// Respond to batches/transactions with a successful response entry per
+/// request entry.
+#[cfg(feature = "r5")]
+fn respond_batch_success(request: &wiremock::Request) -> ResponseTemplate {
+	let bundle: serde_json::Value = request.body_json().unwrap();
+	let entries: Vec<_> = bundle["entry"]
+		.as_array()
+		.unwrap()
+		.iter()
+		.map(|_| json!({ "response": { "status": "200 OK" } }))
+		.collect();
+	ResponseTemplate::new(StatusCode::OK).set_body_json(json!({
+		"resourceType": "Bundle",
+		"type": "transaction-response",
+		"entry": entries,
+	}))
+}
+
+#[cfg(feature = "r5")]
+#[tokio::test]
+async fn transaction_conditional_requests() -> anyhow::Result<()> {
+	use fhir_model::r5::resources::{Resource, ResourceType};
+
+	setup_logging().await;
+	let server = MockServer::start().await;
+	Mock::given(matchers::method(Method::POST))
+		.and(matchers::path("/"))
+		.respond_with(respond_batch_success)
+		.named("Transaction")
+		.expect(1)
+		.mount(&server)
+		.await;
+
+	let client = <Client>::builder().base_url(Url::parse(&server.uri())?).build()?;
+	let patient: Resource =
+		serde_json::from_value(json!({ "resourceType": "Patient", "id": "local" }))?;
+	let observation: Resource = serde_json::from_value(json!({
+		"resourceType": "Observation",
+		"status": "final",
+		"code": { "text": "test" },
+		"subject": {
+			"reference": "Patient/local",
+			"display": "Patient",
+			"_display": {
+				"extension": [{ "url": "http://example.com/display", "valueString": "test" }],
+			},
+		},
+		"extension": [{
+			"url": "http://example.com/expression",
+			"valueExpression": { "language": "text/fhirpath", "reference": "Patient/local" },
+		}],
+	}))?;
+	let parameters: Resource =
+		serde_json::from_value(json!({ "resourceType": "Parameters", "parameter": [] }))?;
+
+	let mut transaction = client.transaction();
+	let patient_uuid = transaction.create_if_none_exist(
+		patient.clone(),
+		SearchParameters::empty().and_raw("identifier", "a|1"),
+	);
+	transaction.create(observation);
+	transaction.update_conditional(patient, SearchParameters::empty().and_raw("identifier", "a|2"));
+	transaction.patch(ResourceType::Patient, "3", parameters)?;
+	transaction.search(ResourceType::Patient, SearchParameters::empty().and_raw("name", "a b"));
+	assert_eq!(transaction.rewrite_references()?, 1);
+	let response = transaction.send().await?.into_result()?;
+
+	let requests: Vec<serde_json::Value> = response
+		.entries()
+		.iter()
+		.map(|entry| serde_json::to_value(&entry.request))
+		.collect::<Result<_, _>>()?;
+	assert_eq!(requests[0]["request"]["ifNoneExist"], "identifier=a%7C1");
+	assert_eq!(requests[1]["resource"]["subject"]["reference"], patient_uuid.as_str());
+	assert_eq!(
+		requests[1]["resource"]["extension"][0]["valueExpression"]["reference"],
+		"Patient/local"
+	);
+	assert_eq!(requests[2]["request"]["url"], "Patient?identifier=a%7C2");
+	assert_eq!(requests[3]["request"]["method"], "PATCH");
+	assert_eq!(requests[4]["request"]["url"], "Patient?name=a+b");
+
+	server.verify().await;
+	Ok(())
+}
+
+#[cfg(feature = "r5")]
+#[tokio::test]
+async fn upload_graph_sequential() -> anyhow::Result<()> {
+	use fhir_model::r5::resources::Resource;
+
+	setup_logging().await;
+	let server = MockServer::start().await;
+	for (resource_type, id) in [("Patient", "p1"), ("Encounter", "e1"), ("Observation", "o1")] {
+		Mock::given(matchers::method(Method::POST))
+			.and(matchers::path(format!("/{resource_type}")))
+			.respond_with(
+				ResponseTemplate::new(StatusCode::CREATED)
+					.insert_header("Location", format!("{resource_type}/{id}/_history/1")),
+			)
+			.named(format!("Create {resource_type}"))
+			.expect(1)
+			.mount(&server)
+			.await;
+	}
+
+	let client = <Client>::builder().base_url(Url::parse(&server.uri())?).build()?;
+	// Given in reverse dependency order.
+	let observation: Resource = serde_json::from_value(json!({
+		"resourceType": "Observation",
+		"id": "obs",
+		"status": "final",
+		"code": { "text": "test" },
+		"subject": { "reference": "Patient/pat" },
+		"encounter": { "reference": format!("{}/Encounter/enc", server.uri()) },
+	}))?;
+	let encounter: Resource = serde_json::from_value(json!({
+		"resourceType": "Encounter",
+		"id": "enc",
+		"status": "completed",
+		"subject": { "reference": "Patient/pat" },
+	}))?;
+	let patient: Resource =
+		serde_json::from_value(json!({ "resourceType": "Patient", "id": "pat" }))?;
+	let references = client
+		.upload_graph(vec![observation.clone(), encounter, patient])
+		.sequential()
+		.send()
+		.await?;
+
+	assert_eq!(references.len(), 3);
+	assert_eq!(references["Patient/pat"], "Patient/p1");
+	assert_eq!(references["Encounter/enc"], "Encounter/e1");
+	assert_eq!(references["Observation/obs"], "Observation/o1");
+
+	let requests = server.received_requests().await.unwrap();
+	let paths: Vec<&str> = requests.iter().map(|request| request.url.path()).collect();
+	assert_eq!(paths, ["/Patient", "/Encounter", "/Observation"]);
+	let created: serde_json::Value = requests[2].body_json()?;
+	assert_eq!(created["subject"]["reference"], "Patient/p1");
+	assert_eq!(created["encounter"]["reference"], "Encounter/e1");
+	assert!(created.get("id").is_none());
+
+	// Cycles cannot be created one by one.
+	let patient: Resource = serde_json::from_value(json!({
+		"resourceType": "Patient",
+		"id": "pat",
+		"generalPractitioner": [{ "reference": "Practitioner/prac" }],
+	}))?;
+	let practitioner: Resource = serde_json::from_value(json!({
+		"resourceType": "Practitioner",
+		"id": "prac",
+		"extension": [{
+			"url": "http://example.com/patient",
+			"valueReference": { "reference": "Patient/pat" },
+		}],
+	}))?;
+	let result =
+		client.upload_graph(vec![patient, practitioner, observation]).sequential().send().await;
+	assert!(matches!(result, Err(Error::ReferenceCycle(cycle)) if cycle.len() == 2));
+
+	server.verify().await;
+	Ok(())
+}
+
+#[cfg(feature = "r5")]
+#[tokio::test]
+async fn upload_graph_transaction() -> anyhow::Result<()> {
+	use fhir_model::r5::resources::Resource;
+
+	setup_logging().await;
+	let server = MockServer::start().await;
+	Mock::given(matchers::method(Method::POST))
+		.and(matchers::path("/"))
+		.respond_with(|request: &wiremock::Request| {
+			let bundle: serde_json::Value = request.body_json().unwrap();
+			let entries: Vec<_> = bundle["entry"]
+				.as_array()
+				.unwrap()
+				.iter()
+				.enumerate()
+				.map(|(index, entry)| {
+					let location = format!(
+						"{}/{index}/_history/1",
+						entry["resource"]["resourceType"].as_str().unwrap()
+					);
+					json!({ "response": { "status": "201 Created", "location": location } })
+				})
+				.collect();
+			ResponseTemplate::new(StatusCode::OK).set_body_json(json!({
+				"resourceType": "Bundle",
+				"type": "transaction-response",
+				"entry": entries,
+			}))
+		})
+		.named("Transaction")
+		.expect(1)
+		.mount(&server)
+		.await;
+
+	let client = <Client>::builder().base_url(Url::parse(&server.uri())?).build()?;
+	let observation: Resource = serde_json::from_value(json!({
+		"resourceType": "Observation",
+		"id": "obs",
+		"status": "final",
+		"code": { "text": "test" },
+		"subject": { "reference": format!("{}/Patient/pat", server.uri()) },
+	}))?;
+	let patient: Resource =
+		serde_json::from_value(json!({ "resourceType": "Patient", "id": "pat" }))?;
+	let references = client.upload_graph(vec![observation, patient]).send().await?;
+
+	assert_eq!(references.len(), 2);
+	assert_eq!(references["Observation/obs"], "Observation/0");
+	assert_eq!(references["Patient/pat"], "Patient/1");
+
+	let requests = server.received_requests().await.unwrap();
+	let bundle: serde_json::Value = requests[0].body_json()?;
+	let entries = bundle["entry"].as_array().unwrap();
+	assert_eq!(entries[0]["resource"]["subject"]["reference"], entries[1]["fullUrl"]);
+	assert!(entries.iter().all(|entry| entry["resource"].get("id").is_none()));
+
+	server.verify().await;
+	Ok(())
+}
+
+#[cfg(feature = "r5")]
+#[tokio::test]
+async fn patch_from_diff() -> anyhow::Result<()> {
+	use fhir_model::r5::resources::Patient;
+
+	setup_logging().await;
+	let server = MockServer::start().await;
+	Mock::given(matchers::method(Method::PATCH))
+		.and(matchers::path("/Patient/1"))
+		.and(matchers::header("Content-Type", "application/json-patch+json"))
+		.and(matchers::body_json(json!([
+			{ "op": "test", "path": "/meta/versionId", "value": "2" },
+			{ "op": "remove", "path": "/active" },
+			{ "op": "add", "path": "/birthDate", "value": "2000-01-01" },
+		])))
+		.respond_with(ResponseTemplate::new(StatusCode::OK))
+		.named("JSON Patch")
+		.expect(1)
+		.mount(&server)
+		.await;
+	Mock::given(matchers::method(Method::PATCH))
+		.and(matchers::path("/Patient/1"))
+		.and(matchers::header("If-Match", "W/\"2\""))
+		.and(matchers::body_json(json!({
+			"resourceType": "Parameters",
+			"parameter": [
+				{
+					"name": "operation",
+					"part": [
+						{ "name": "type", "valueCode": "delete" },
+						{ "name": "path", "valueString": "Patient.active" },
+					],
+				},
+				{
+					"name": "operation",
+					"part": [
+						{ "name": "type", "valueCode": "add" },
+						{ "name": "path", "valueString": "Patient" },
+						{ "name": "name", "valueString": "birthDate" },
+						{ "name": "value", "valueDate": "2000-01-01" },
+					],
+				},
+			],
+		})))
+		.respond_with(ResponseTemplate::new(StatusCode::OK))
+		.named("FHIRPath Patch")
+		.expect(1)
+		.mount(&server)
+		.await;
+
+	let client = <Client>::builder().base_url(Url::parse(&server.uri())?).build()?;
+	let old: Patient = serde_json::from_value(json!({
+		"resourceType": "Patient",
+		"id": "1",
+		"meta": { "versionId": "2" },
+		"active": true,
+	}))?;
+	let mut new = old.clone();
+	new.active = None;
+	new.birth_date = Some("2000-01-01".parse()?);
+
+	PatchViaJson::from_diff(client.clone(), &old, &new)?.send().await?;
+	PatchViaFhir::from_diff(client, &old, &new)?.send().await?;
+
+	server.verify().await;
+	Ok(())
+}
+
+#[cfg(feature = "r5")]
+#[tokio::test]
+async fn history_changes() -> anyhow::Result<()> {
+	use fhir_model::r5::resources::Patient;
+	use futures::TryStreamExt;
+
+	use crate::changes::{ChangeKind, DiffOptions};
+
+	setup_logging().await;
+	let server = MockServer::start().await;
+	let version = |version_id: &str, family: &str| {
+		json!({
+			"resource": {
+				"resourceType": "Patient",
+				"id": "1",
+				"meta": { "versionId": version_id, "lastUpdated": format!("2024-01-0{version_id}T00:00:00Z") },
+				"name": [{ "family": family }],
+			},
+		})
+	};
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/Patient/1/_history"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK).set_body_json(json!({
+			"resourceType": "Bundle",
+			"type": "history",
+			"entry": [
+				{
+					"request": { "method": "DELETE", "url": "Patient/1" },
+					"response": { "status": "204 No Content", "etag": "W/\"4\"" },
+				},
+				version("3", "Roe"),
+				version("2", "Doe"),
+				version("1", "Doe"),
+			],
+		})))
+		.named("Patient history")
+		.expect(1)
+		.mount(&server)
+		.await;
+
+	let client = <Client>::builder().base_url(Url::parse(&server.uri())?).build()?;
+	let versions: Vec<_> =
+		client.history_changes::<Patient>("1", DiffOptions::default()).await?.try_collect().await?;
+
+	assert_eq!(versions.len(), 3);
+	assert_eq!(versions[0].version_id.as_deref(), Some("4"));
+	assert_eq!(versions[0].changes.len(), 1);
+	assert_eq!(versions[0].changes[0].path, "Patient");
+	assert_eq!(versions[0].changes[0].kind, ChangeKind::Removed);
+	assert_eq!(versions[1].version_id.as_deref(), Some("3"));
+	assert_eq!(versions[1].changes.len(), 1);
+	assert_eq!(versions[1].changes[0].path, "Patient.name[0].family");
+	assert_eq!(versions[1].changes[0].kind, ChangeKind::Changed);
+	assert_eq!(versions[2].version_id.as_deref(), Some("2"));
+	assert!(versions[2].changes.is_empty());
+
+	server.verify().await;
+	Ok(())
+}
+
+#[cfg(feature = "r5")]
+#[tokio::test]
+async fn system_history() -> anyhow::Result<()> {
+	use fhir_model::r5::resources::Resource;
+	use futures::TryStreamExt;
+
+	setup_logging().await;
+	let server = MockServer::start().await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/_history"))
+		.and(matchers::query_param("_since", "2024-01-01T00:00:00Z"))
+		.and(matchers::query_param("_count", "2"))
+		.and(matchers::query_param("_sort", "_lastUpdated"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK).set_body_json(json!({
+			"resourceType": "Bundle",
+			"type": "history",
+			"entry": [
+				{
+					"resource": { "resourceType": "Patient", "id": "1", "meta": { "versionId": "1" } },
+					"request": { "method": "POST", "url": "Patient" },
+					"response": { "status": "201 Created", "etag": "W/\"1\"" },
+				},
+				{
+					"request": { "method": "DELETE", "url": "Observation/2" },
+					"response": { "status": "204 No Content", "etag": "W/\"3\"" },
+				},
+			],
+		})))
+		.named("System history")
+		.expect(1)
+		.mount(&server)
+		.await;
+
+	let client = <Client>::builder().base_url(Url::parse(&server.uri())?).build()?;
+	let page = client
+		.system_history()
+		.since("2024-01-01T00:00:00Z".parse()?)
+		.count(2)
+		.sort("_lastUpdated")
+		.send()
+		.await?;
+	assert!(matches!(page.entries().next(), Some(Resource::Patient(_))));
+	let entries: Vec<_> = page.all_history_entries().try_collect().await?;
+
+	assert_eq!(entries.len(), 2);
+	assert!(entries[0].is_create());
+	assert_eq!(entries[0].resource_type(), Some("Patient"));
+	assert_eq!(entries[0].id(), Some("1"));
+	assert_eq!(entries[0].version_id(), Some("1"));
+	assert!(entries[1].is_delete());
+	assert!(entries[1].resource.is_none());
+	assert_eq!(entries[1].resource_type(), Some("Observation"));
+	assert_eq!(entries[1].id(), Some("2"));
+	assert_eq!(entries[1].version_id(), Some("3"));
+
+	server.verify().await;
+	Ok(())
+}
+
+#[cfg(feature = "r5")]
+#[tokio::test]
+async fn changes_since() -> anyhow::Result<()> {
+	use fhir_model::r5::resources::ResourceType;
+	use futures::TryStreamExt;
+
+	setup_logging().await;
+	let server = MockServer::start().await;
+	let version = |resource_type: &str, id: &str, version_id: &str, day: u8, method: &str| {
+		json!({
+			"resource": {
+				"resourceType": resource_type,
+				"id": id,
+				"meta": { "versionId": version_id, "lastUpdated": format!("2024-01-0{day}T00:00:00Z") },
+			},
+			"request": { "method": method, "url": resource_type },
+		})
+	};
+	let deletion = json!({
+		"request": { "method": "DELETE", "url": "Patient/2" },
+		"response": { "status": "204 No Content", "etag": "W/\"2\"" },
+	});
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/Patient/_history"))
+		.and(matchers::query_param("_sort", "_lastUpdated"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK).set_body_json(json!({
+			"resourceType": "Bundle",
+			"type": "history",
+			"link": [{ "relation": "next", "url": format!("{}/Patient/_history?page=2", server.uri()) }],
+			"entry": [version("Patient", "1", "1", 1, "POST"), version("Patient", "1", "2", 3, "PUT")],
+		})))
+		.named("Initial patient history")
+		.expect(1)
+		.mount(&server)
+		.await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/Patient/_history"))
+		.and(matchers::query_param("page", "2"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK).set_body_json(json!({
+			"resourceType": "Bundle",
+			"type": "history",
+			"entry": [deletion.clone()],
+		})))
+		.named("Second patient history page")
+		.with_priority(1)
+		.expect(1)
+		.mount(&server)
+		.await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/Patient/_history"))
+		.and(matchers::query_param("_since", "2024-01-03T00:00:00Z"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK).set_body_json(json!({
+			"resourceType": "Bundle",
+			"type": "history",
+			"entry": [
+				version("Patient", "1", "2", 3, "PUT"),
+				deletion,
+				version("Patient", "3", "1", 4, "POST"),
+			],
+		})))
+		.named("Incremental patient history")
+		.with_priority(1)
+		.expect(1 ..)
+		.mount(&server)
+		.await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/Organization/_history"))
+		.and(matchers::query_param("_sort", "_lastUpdated"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK).set_body_json(json!({
+			"resourceType": "Bundle",
+			"type": "history",
+			"entry": [version("Organization", "1", "1", 2, "POST")],
+		})))
+		.named("Initial organization history")
+		.expect(1)
+		.mount(&server)
+		.await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/Organization/_history"))
+		.and(matchers::query_param("_since", "2024-01-03T00:00:00Z"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK).set_body_json(json!({
+			"resourceType": "Bundle",
+			"type": "history",
+		})))
+		.named("Incremental organization history")
+		.with_priority(1)
+		.expect(1 ..)
+		.mount(&server)
+		.await;
+
+	let store = Arc::new(MemoryCheckpointStore::new());
+	let client = <Client>::builder().base_url(Url::parse(&server.uri())?).build()?;
+	let mut stream = Box::pin(
+		client
+			.changes_since([ResourceType::Patient, ResourceType::Organization], None)
+			.interval(Duration::from_millis(10))
+			.checkpoint_store(store.clone())
+			.stream(),
+	);
+	let mut events = Vec::new();
+	for _ in 0 .. 4 {
+		events.push(stream.try_next().await?.ok_or_else(|| anyhow::anyhow!("stream ended"))?);
+	}
+
+	// Saved before fetching the second patient page.
+	let checkpoint = store.load().ok_or_else(|| anyhow::anyhow!("no checkpoint"))?;
+	assert_eq!(checkpoint.since, Some("2024-01-03T00:00:00Z".parse()?));
+	assert_eq!(checkpoint.seen.into_iter().collect::<Vec<_>>(), ["Patient/1/_history/2"]);
+
+	events.push(stream.try_next().await?.ok_or_else(|| anyhow::anyhow!("stream ended"))?);
+	let changes: Vec<_> = events
+		.iter()
+		.map(|event| match event {
+			SyncEvent::Created(_) => ("created", event.resource_type(), event.id()),
+			SyncEvent::Updated(_) => ("updated", event.resource_type(), event.id()),
+			SyncEvent::Deleted { .. } => ("deleted", event.resource_type(), event.id()),
+		})
+		.collect();
+	assert_eq!(
+		changes,
+		[
+			("created", "Patient", Some("1")),
+			("created", "Organization", Some("1")),
+			("updated", "Patient", Some("1")),
+			("deleted", "Patient", Some("2")),
+			("created", "Patient", Some("3")),
+		]
+	);
+
+	let checkpoint = store.load().ok_or_else(|| anyhow::anyhow!("no checkpoint"))?;
+	assert_eq!(checkpoint.since, Some("2024-01-03T00:00:00Z".parse()?));
+	assert_eq!(
+		checkpoint.seen.into_iter().collect::<Vec<_>>(),
+		["Patient/1/_history/2", "Patient/2/_history/2"]
+	);
+
+	server.verify().await;
+	Ok(())
+}
+
+#[cfg(feature = "r5")]
+#[tokio::test]
+async fn changes_since_checkpoint_failure() -> anyhow::Result<()> {
+	use std::sync::atomic::AtomicBool;
+
+	use fhir_model::r5::resources::ResourceType;
+	use futures::TryStreamExt;
+
+	/// Store that fails to save the first time.
+	#[derive(Debug, Default)]
+	struct FlakyStore {
+		failed: AtomicBool,
+		inner: MemoryCheckpointStore,
+	}
+
+	impl CheckpointStore for FlakyStore {
+		fn load(&self) -> Option<SyncCheckpoint> {
+			self.inner.load()
+		}
+
+		fn save(
+			&self,
+			checkpoint: &SyncCheckpoint,
+		) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+			if !self.failed.swap(true, Ordering::SeqCst) {
+				return Err("disk full".into());
+			}
+			self.inner.save(checkpoint)
+		}
+	}
+
+	setup_logging().await;
+	let server = MockServer::start().await;
+	let version = |id: &str, day: u8| {
+		json!({
+			"resource": {
+				"resourceType": "Patient",
+				"id": id,
+				"meta": { "versionId": "1", "lastUpdated": format!("2024-01-0{day}T00:00:00Z") },
+			},
+			"request": { "method": "POST", "url": "Patient" },
+		})
+	};
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/Patient/_history"))
+		.and(matchers::query_param("_sort", "_lastUpdated"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK).set_body_json(json!({
+			"resourceType": "Bundle",
+			"type": "history",
+			"link": [{ "relation": "next", "url": format!("{}/Patient/_history?page=2", server.uri()) }],
+			"entry": [version("1", 1)],
+		})))
+		.named("Patient history")
+		.expect(1)
+		.mount(&server)
+		.await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/Patient/_history"))
+		.and(matchers::query_param("page", "2"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK).set_body_json(json!({
+			"resourceType": "Bundle",
+			"type": "history",
+			"entry": [version("2", 2)],
+		})))
+		.named("Second patient history page")
+		.with_priority(1)
+		.expect(1)
+		.mount(&server)
+		.await;
+
+	let store = Arc::new(FlakyStore::default());
+	let client = <Client>::builder().base_url(Url::parse(&server.uri())?).build()?;
+	let mut stream = Box::pin(
+		client
+			.changes_since([ResourceType::Patient], None)
+			.checkpoint_store(store.clone())
+			.stream(),
+	);
+
+	let event = stream.try_next().await?.ok_or_else(|| anyhow::anyhow!("stream ended"))?;
+	assert_eq!(event.id(), Some("1"));
+
+	// The failed save is yielded before the second page is fetched.
+	let result = stream.try_next().await;
+	assert!(matches!(result, Err(Error::Checkpoint(_))));
+	assert_eq!(server.received_requests().await.unwrap_or_default().len(), 1);
+	assert!(store.load().is_none());
+
+	let event = stream.try_next().await?.ok_or_else(|| anyhow::anyhow!("stream ended"))?;
+	assert_eq!(event.id(), Some("2"));
+	let checkpoint = store.load().ok_or_else(|| anyhow::anyhow!("no checkpoint"))?;
+	assert_eq!(checkpoint.since, Some("2024-01-01T00:00:00Z".parse()?));
+
+	server.verify().await;
+	Ok(())
+}
+
+#[cfg(all(feature = "r5", feature = "blocking"))]
+#[test]
+fn blocking_client() -> anyhow::Result<()> {
+	use fhir_model::r5::resources::Patient;
+
+	use crate::extensions::AnyResource;
+
+	// The mock server needs its own runtime, the blocking client must not be used in
+	// an async context.
+	let runtime = tokio::runtime::Runtime::new()?;
+	runtime.block_on(setup_logging());
+	let server = runtime.block_on(MockServer::start());
+	let patient = |id: &str| json!({ "resource": { "resourceType": "Patient", "id": id } });
+	runtime.block_on(async {
+		Mock::given(matchers::method(Method::GET))
+			.and(matchers::path("/Patient"))
+			.and(matchers::query_param("page", "2"))
+			.respond_with(ResponseTemplate::new(StatusCode::OK).set_body_json(json!({
+				"resourceType": "Bundle",
+				"type": "searchset",
+				"entry": [patient("2")],
+			})))
+			.with_priority(1)
+			.named("Second page")
+			.expect(1)
+			.mount(&server)
+			.await;
+		Mock::given(matchers::method(Method::GET))
+			.and(matchers::path("/Patient"))
+			.respond_with(ResponseTemplate::new(StatusCode::OK).set_body_json(json!({
+				"resourceType": "Bundle",
+				"type": "searchset",
+				"link": [{ "relation": "next", "url": format!("{}/Patient?page=2", server.uri()) }],
+				"entry": [patient("1")],
+			})))
+			.with_priority(5)
+			.named("First page")
+			.expect(1)
+			.mount(&server)
+			.await;
+		Mock::given(matchers::method(Method::POST))
+			.and(matchers::path("/Patient"))
+			.respond_with(
+				ResponseTemplate::new(StatusCode::CREATED)
+					.insert_header("Location", format!("{}/Patient/3/_history/1", server.uri())),
+			)
+			.named("Create")
+			.expect(1)
+			.mount(&server)
+			.await;
+	});
+
+	let client = <Client>::builder().base_url(Url::parse(&server.uri())?).build_blocking()?;
+	let patients = client
+		.search::<Patient>(SearchParameters::empty())?
+		.all_matches()
+		.collect::<Result<Vec<_>, _>>()?;
+	let ids: Vec<_> = patients.iter().map(AnyResource::id).collect();
+	assert_eq!(ids, [Some("1"), Some("2")]);
+
+	let (id, version_id) = client.create(&Patient::builder().build()?)?;
+	assert_eq!(id, "3");
+	assert_eq!(version_id.as_deref(), Some("1"));
+
+	runtime.block_on(server.verify());
+	Ok(())
+}
+
+#[cfg(feature = "r5")]
+#[tokio::test]
+async fn capability_aware_client() -> anyhow::Result<()> {
+	use fhir_model::r5::resources::{Observation, Patient, ResourceType};
+
+	setup_logging().await;
+	let server = MockServer::start().await;
+	// The capabilities are requested with the headers of the request settings.
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/metadata"))
+		.and(matchers::header("X-Tenant", "a"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK).set_body_json(json!({
+			"resourceType": "CapabilityStatement",
+			"status": "active",
+			"date": "2024-01-01",
+			"kind": "instance",
+			"fhirVersion": "5.0.0",
+			"format": ["json"],
+			"rest": [{
+				"mode": "server",
+				"interaction": [{ "code": "batch" }],
+				"resource": [{
+					"type": "Patient",
+					"interaction": [{ "code": "read" }, { "code": "search-type" }],
+					"searchParam": [{ "name": "name", "type": "string" }],
+					"operation": [{
+						"name": "everything",
+						"definition": "http://hl7.org/fhir/OperationDefinition/Patient-everything",
+					}],
+				}],
+			}],
+		})))
+		.named("Capabilities")
+		.expect(1)
+		.mount(&server)
+		.await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/Patient/1"))
+		.respond_with(
+			ResponseTemplate::new(StatusCode::OK)
+				.set_body_json(json!({ "resourceType": "Patient", "id": "1" })),
+		)
+		.named("Read")
+		.expect(2)
+		.mount(&server)
+		.await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/Patient"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK).set_body_json(json!({
+			"resourceType": "Bundle",
+			"type": "searchset",
+			"link": [{ "relation": "next", "url": format!("{}?_getpages=abc", server.uri()) }],
+		})))
+		.named("Search")
+		.expect(1)
+		.mount(&server)
+		.await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/"))
+		.and(matchers::query_param("_getpages", "abc"))
+		.respond_with(
+			ResponseTemplate::new(StatusCode::OK)
+				.set_body_json(json!({ "resourceType": "Bundle", "type": "searchset" })),
+		)
+		.named("Next page")
+		.expect(1)
+		.mount(&server)
+		.await;
+
+	let client = <Client>::builder()
+		.base_url(Url::parse(&server.uri())?)
+		.request_settings(
+			RequestSettings::default()
+				.header(header::HeaderName::from_static("x-tenant"), HeaderValue::from_static("a")),
+		)
+		.check_capabilities()
+		.build()?;
+
+	// Capabilities are fetched once on the first requests and cached.
+	let (first, second) = tokio::join!(client.read::<Patient>("1"), client.read::<Patient>("1"));
+	assert!(first?.is_some());
+	assert!(second?.is_some());
+	let result = client.delete(ResourceType::Patient, "1").await;
+	assert!(matches!(result, Err(Error::Unsupported(_))));
+	let result = client.read::<Observation>("1").await;
+	assert!(matches!(result, Err(Error::Unsupported(_))));
+
+	// Paging links are not checked as system-level searches.
+	let page = client.search::<Patient>(SearchParameters::empty()).await?;
+	assert!(page.next_page().await.transpose()?.is_some());
+
+	// Operations are checked against the resource type in the path.
+	let url = format!("{}/Observation/1/$everything", server.uri());
+	let result = client.send_custom_request(|http| http.get(url)).await;
+	assert!(matches!(result, Err(Error::Unsupported(_))));
+	let url = format!("{}/$everything", server.uri());
+	let result = client.send_custom_request(|http| http.get(url)).await;
+	assert!(matches!(result, Err(Error::Unsupported(_))));
+
+	let capabilities = client.server_capabilities().await?;
+	assert!(capabilities.supports_interaction(ResourceType::Patient, Interaction::Read));
+	assert!(!capabilities.supports_interaction(ResourceType::Patient, Interaction::Delete));
+	assert!(!capabilities.supports_interaction(ResourceType::Observation, Interaction::Read));
+	assert!(capabilities.supports_interaction(ResourceType::Patient, Interaction::Batch));
+	assert!(!capabilities.supports_interaction(ResourceType::Patient, Interaction::Transaction));
+	assert_eq!(capabilities.supported_search_params(ResourceType::Patient), ["name"]);
+	assert!(capabilities.supports_operation("$everything"));
+	assert!(capabilities.supports_resource_operation(ResourceType::Patient, "everything"));
+	assert!(!capabilities.supports_resource_operation(ResourceType::Observation, "everything"));
+	assert!(!capabilities.supports_system_operation("everything"));
+	assert!(!capabilities.supports_operation("validate"));
+
+	server.verify().await;
+	Ok(())
+}
+
+#[cfg(feature = "websocket")]
+async fn mock_subscription_websocket() -> (MockServer, tokio::net::TcpListener) {
+	let server = MockServer::start().await;
+	let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+	let websocket_url = format!("ws://{}/websocket", listener.local_addr().unwrap());
+
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/Subscription/1/$get-ws-binding-token"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK).set_body_json(json!({
+			"resourceType": "Parameters",
+			"parameter": [
+				{ "name": "token", "valueString": "test-token" },
+				{ "name": "expiration", "valueDateTime": "2100-01-01T00:00:00Z" },
+				{ "name": "subscription", "valueString": "Subscription/1" },
+				{ "name": "websocket-url", "valueUrl": websocket_url }
+			]
+		})))
+		.named("Get websocket binding token")
+		.expect(2)
+		.mount(&server)
+		.await;
+
+	(server, listener)
+}
+
+#[cfg(feature = "websocket")]
+#[tokio::test]
+async fn subscription_websocket() -> anyhow::Result<()> {
+	use futures::{SinkExt, StreamExt};
+	use tokio_tungstenite::tungstenite::Message;
+
+	setup_logging().await;
+	let (mocks, listener) = mock_subscription_websocket().await;
+
+	let websocket_server = tokio::spawn(async move {
+		// First connection breaks after the handshake, second one delivers an event.
+		for notification_type in ["handshake", "event-notification"] {
+			let (stream, _) = listener.accept().await.unwrap();
+			let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+			let bind = socket.next().await.unwrap().unwrap();
+			assert_eq!(bind, Message::text("bind-with-token test-token"));
+
+			socket.send(Message::text("ping Subscription/1")).await.unwrap();
+			let bundle = json!({
+				"resourceType": "Bundle",
+				"type": "subscription-notification",
+				"id": notification_type,
+			});
+			socket.send(Message::text(bundle.to_string())).await.unwrap();
+			_ = socket.close(None).await;
+		}
+	});
+
+	let client =
+		<Client>::builder().base_url(Url::parse(&mocks.uri())?).allow_origin_mismatch().build()?;
+	let notifications = client
+		.subscription_websocket("1")
+		.max_reconnects(1)
+		.reconnect_delay(Duration::from_millis(10))
+		.connect()
+		.await?;
+	let ids = notifications
+		.take(2)
+		.map(|bundle| bundle.map(|bundle| bundle.id.clone()))
+		.collect::<Vec<_>>()
+		.await;
+	assert_eq!(ids.len(), 2);
+	assert_eq!(ids[0].as_ref().unwrap().as_deref(), Some("handshake"));
+	assert_eq!(ids[1].as_ref().unwrap().as_deref(), Some("event-notification"));
 
+	websocket_server.await?;
 	mocks.verify().await;
 	Ok(())
 }
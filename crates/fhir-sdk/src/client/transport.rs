@@ -0,0 +1,130 @@
+//! Pluggable HTTP transport of the client.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use reqwest::{ResponseBuilderExt, Url};
+
+use super::Error;
+
+/// HTTP transport sending the requests of the client, e.g. to use a different
+/// HTTP stack (hyper, a tower `Service`), custom TLS or a mock. Register it via
+/// [`ClientBuilder::transport`](super::ClientBuilder::transport). Defaults to
+/// the [ReqwestTransport].
+///
+/// Requests and responses are [`http`] types with complete bodies. The client
+/// applies the [`RequestSettings`](super::RequestSettings), i.e. headers,
+/// timeouts and retries, and the middlewares around the transport. Errors of
+/// custom transports should be wrapped in [`Error::Transport`], which are
+/// retried like connection problems. Responses are attributed to the request
+/// URL, unless the transport inserts the final [Url], e.g. after redirects,
+/// into the response's extensions.
+///
+/// Only the sending is replaced: requests are still built with the Reqwest
+/// client, e.g. in [`Client::send_custom_request`](super::Client::send_custom_request),
+/// and [`Middleware`](super::Middleware)s see Reqwest requests and responses.
+#[async_trait]
+pub trait HttpTransport: Send + Sync {
+	/// Send the request and return the response.
+	async fn send(&self, request: http::Request<Vec<u8>>)
+		-> Result<http::Response<Vec<u8>>, Error>;
+
+	/// Send the Reqwest request of the client. By default, it is converted to
+	/// and from the [`http`] types for [Self::send]. Transports able to handle
+	/// Reqwest requests directly can override this to avoid buffering the
+	/// response body.
+	async fn execute(&self, request: &reqwest::Request) -> Result<reqwest::Response, Error> {
+		let response = self.send(to_http_request(request)?).await?;
+		from_http_response(response, request.url())
+	}
+}
+
+#[async_trait]
+impl<T: HttpTransport + ?Sized> HttpTransport for Arc<T> {
+	async fn send(
+		&self,
+		request: http::Request<Vec<u8>>,
+	) -> Result<http::Response<Vec<u8>>, Error> {
+		(**self).send(request).await
+	}
+
+	async fn execute(&self, request: &reqwest::Request) -> Result<reqwest::Response, Error> {
+		(**self).execute(request).await
+	}
+}
+
+/// Default transport sending the requests via a [reqwest::Client]. Responses of
+/// the client's requests are streamed, not buffered.
+#[derive(Debug, Clone, Default)]
+pub struct ReqwestTransport(reqwest::Client);
+
+impl ReqwestTransport {
+	/// Create a new transport using the Reqwest client.
+	#[must_use]
+	pub const fn new(client: reqwest::Client) -> Self {
+		Self(client)
+	}
+}
+
+impl From<reqwest::Client> for ReqwestTransport {
+	fn from(client: reqwest::Client) -> Self {
+		Self::new(client)
+	}
+}
+
+#[async_trait]
+impl HttpTransport for ReqwestTransport {
+	async fn send(
+		&self,
+		request: http::Request<Vec<u8>>,
+	) -> Result<http::Response<Vec<u8>>, Error> {
+		let request = reqwest::Request::try_from(request)?;
+		let response = self.0.execute(request).await?;
+
+		let mut http_response = http::Response::new(Vec::new());
+		*http_response.status_mut() = response.status();
+		*http_response.version_mut() = response.version();
+		*http_response.headers_mut() = response.headers().clone();
+		*http_response.body_mut() = response.bytes().await?.to_vec();
+		Ok(http_response)
+	}
+
+	async fn execute(&self, request: &reqwest::Request) -> Result<reqwest::Response, Error> {
+		let request = request.try_clone().ok_or(Error::RequestNotClone)?;
+		Ok(self.0.execute(request).await?)
+	}
+}
+
+/// Convert the request for the transport. Fails for streaming bodies.
+fn to_http_request(request: &reqwest::Request) -> Result<http::Request<Vec<u8>>, Error> {
+	let body = match request.body() {
+		Some(body) => body.as_bytes().ok_or(Error::RequestNotClone)?.to_vec(),
+		None => Vec::new(),
+	};
+	let uri = http::Uri::try_from(request.url().as_str())
+		.map_err(|err| Error::Transport(Box::new(err)))?;
+
+	let mut http_request = http::Request::new(body);
+	*http_request.method_mut() = request.method().clone();
+	*http_request.uri_mut() = uri;
+	*http_request.version_mut() = request.version();
+	*http_request.headers_mut() = request.headers().clone();
+	Ok(http_request)
+}
+
+/// Convert the response of the transport, with the final URL from the
+/// extensions or else the URL of the request.
+fn from_http_response(
+	response: http::Response<Vec<u8>>,
+	url: &Url,
+) -> Result<reqwest::Response, Error> {
+	let (parts, body) = response.into_parts();
+	let url = parts.extensions.get::<Url>().unwrap_or(url).clone();
+	let mut builder =
+		http::Response::builder().status(parts.status).version(parts.version).url(url);
+	if let Some(headers) = builder.headers_mut() {
+		*headers = parts.headers;
+	}
+	let response = builder.body(body).map_err(|err| Error::Transport(Box::new(err)))?;
+	Ok(reqwest::Response::from(response))
+}
@@ -0,0 +1,308 @@
+//! Subscription notifications via the `websocket` channel type.
+
+use std::time::Duration;
+
+use fhir_model::DateTime;
+use futures::{stream, SinkExt, Stream, StreamExt};
+use reqwest::{header, StatusCode, Url};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+	tungstenite::{self, client::IntoClientRequest, Message},
+	MaybeTlsStream, WebSocketStream,
+};
+
+use super::{Client, Error};
+use crate::{
+	extensions::{ParameterExt, ParameterValueExt, ParametersExt},
+	version::FhirVersion,
+};
+
+/// Connected websocket stream.
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Result of the `$get-ws-binding-token` operation, containing everything needed
+/// to bind a websocket connection to a subscription.
+#[derive(Debug, Clone)]
+pub struct WebSocketBinding {
+	/// Token to bind the websocket connection with.
+	pub token: String,
+	/// Date and time the token is valid until, if the server provided it.
+	pub expiration: Option<DateTime>,
+	/// URL of the server's websocket endpoint.
+	pub websocket_url: Url,
+}
+
+impl WebSocketBinding {
+	/// Extract the binding information from the operation's `Parameters`.
+	fn from_parameters<P: ParametersExt>(parameters: &P) -> Result<Self, Error> {
+		let string_parameter = |name: &'static str| {
+			parameters
+				.find_parameter(name)
+				.and_then(ParameterExt::value)
+				.and_then(ParameterValueExt::as_string)
+				.ok_or(Error::MissingParameter(name))
+		};
+
+		let token = string_parameter("token")?.to_owned();
+		let websocket_url = string_parameter("websocket-url")?;
+		let websocket_url =
+			websocket_url.parse().map_err(|_| Error::UrlParse(websocket_url.to_owned()))?;
+		let expiration = parameters
+			.find_parameter("expiration")
+			.and_then(ParameterExt::value)
+			.and_then(ParameterValueExt::as_date_time)
+			.cloned();
+
+		Ok(Self { token, expiration, websocket_url })
+	}
+}
+
+impl<V: FhirVersion> Client<V>
+where
+	(StatusCode, V::OperationOutcome): Into<Error>,
+{
+	/// Operation `$get-ws-binding-token` on `Subscription`, returning a token
+	/// and the URL to bind a websocket connection to the subscription. Available
+	/// in R5 and via the subscriptions backport in R4B.
+	pub async fn operation_subscription_get_ws_binding_token(
+		&self,
+		id: &str,
+	) -> Result<WebSocketBinding, Error> {
+		let url = self.url(&["Subscription", id, "$get-ws-binding-token"]);
+		let request = self.0.client.get(url).header(header::ACCEPT, V::MIME_TYPE);
+
+		let response = self.run_request(request).await?;
+		if response.status().is_success() {
+			let parameters: V::Parameters = response.json().await?;
+			WebSocketBinding::from_parameters(&parameters)
+		} else {
+			Err(Error::from_response::<V>(response).await)
+		}
+	}
+
+	/// Begin building a websocket connection to receive the notifications of a
+	/// `Subscription` with `websocket` channel type.
+	pub fn subscription_websocket(&self, id: impl Into<String>) -> SubscriptionWebSocket<V> {
+		SubscriptionWebSocket::new(self.clone(), id.into())
+	}
+}
+
+/// Builder for a websocket connection to a `Subscription`'s notifications.
+///
+/// By default, a broken connection is re-established up to 3 times in a row,
+/// waiting 1000 ms before each attempt. Missing heartbeats are not detected
+/// unless a heartbeat timeout is set.
+#[derive(Debug, Clone)]
+#[must_use = "You probably want to connect to the websocket"]
+pub struct SubscriptionWebSocket<V: FhirVersion> {
+	/// FHIR client.
+	client: Client<V>,
+	/// ID of the subscription to bind to.
+	id: String,
+	/// Maximum time without any message before the connection is considered
+	/// broken.
+	heartbeat_timeout: Option<Duration>,
+	/// Number of consecutive reconnects to try before giving up.
+	max_reconnects: usize,
+	/// Duration to wait before reconnecting.
+	reconnect_delay: Duration,
+}
+
+impl<V: FhirVersion> SubscriptionWebSocket<V>
+where
+	(StatusCode, V::OperationOutcome): Into<Error>,
+{
+	/// Start building a new websocket connection.
+	pub const fn new(client: Client<V>, id: String) -> Self {
+		Self {
+			client,
+			id,
+			heartbeat_timeout: None,
+			max_reconnects: 3,
+			reconnect_delay: Duration::from_millis(1000),
+		}
+	}
+
+	/// Set the maximum time without receiving any message, after which the
+	/// connection is considered broken and is re-established. Should be set
+	/// a bit higher than the subscription's `heartbeatPeriod`.
+	pub const fn heartbeat_timeout(mut self, timeout: Option<Duration>) -> Self {
+		self.heartbeat_timeout = timeout;
+		self
+	}
+
+	/// Set the number of consecutive reconnects to try before giving up.
+	pub const fn max_reconnects(mut self, reconnects: usize) -> Self {
+		self.max_reconnects = reconnects;
+		self
+	}
+
+	/// Set the duration to wait before reconnecting.
+	pub const fn reconnect_delay(mut self, delay: Duration) -> Self {
+		self.reconnect_delay = delay;
+		self
+	}
+
+	/// Connect to the websocket and bind it to the subscription. Returns a
+	/// `Stream` of the received notification `Bundle`s, including handshakes and
+	/// heartbeats.
+	///
+	/// The connection is re-established automatically with a fresh binding token
+	/// when it breaks. The stream ends after yielding the error once all
+	/// reconnects failed. Messages that fail to decode are yielded as errors
+	/// without ending the stream.
+	pub async fn connect(
+		self,
+	) -> Result<impl Stream<Item = Result<V::Bundle, Error>> + Send + 'static, Error> {
+		let socket = self.bind().await?;
+		let connection = Connection { settings: self, socket: Some(socket), failures: 0 };
+		Ok(stream::unfold(Some(connection), |connection| async move {
+			let mut connection = connection?;
+			let (result, keep_going) = connection.next_notification().await?;
+			Some((result, keep_going.then_some(connection)))
+		}))
+	}
+
+	/// Request a new binding token, connect to the websocket and bind it to the
+	/// subscription.
+	async fn bind(&self) -> Result<Socket, Error> {
+		let binding = self.client.operation_subscription_get_ws_binding_token(&self.id).await?;
+
+		// Same reasoning as for HTTP requests, but websocket URLs have a different scheme.
+		if self.client.0.error_on_origin_mismatch {
+			let base_url = self.client.base_url();
+			if binding.websocket_url.host() != base_url.host()
+				|| binding.websocket_url.port_or_known_default() != base_url.port_or_known_default()
+			{
+				return Err(Error::DifferentOrigin(binding.websocket_url.to_string()));
+			}
+		}
+
+		tracing::info!("Connecting to subscription websocket at {}", binding.websocket_url);
+		let mut socket = match self.open(&binding.websocket_url).await {
+			Err(Error::WebSocket(tungstenite::Error::Http(response)))
+				if response.status() == StatusCode::UNAUTHORIZED =>
+			{
+				if !self.client.0.refresh_authorization().await? {
+					return Err(Error::WebSocket(tungstenite::Error::Http(response)));
+				}
+				tracing::info!("Retrying websocket connection after authorization refresh");
+				self.open(&binding.websocket_url).await?
+			}
+			result => result?,
+		};
+
+		socket.send(Message::text(format!("bind-with-token {}", binding.token))).await?;
+		tracing::debug!("Bound websocket to subscription {}", self.id);
+		Ok(socket)
+	}
+
+	/// Open the websocket connection, sending along the client's configured
+	/// request headers (e.g. `Authorization`).
+	async fn open(&self, url: &Url) -> Result<Socket, Error> {
+		let mut request = url.as_str().into_client_request()?;
+		request.headers_mut().extend(self.client.request_settings().headers().clone());
+		let (socket, _response) = tokio_tungstenite::connect_async(request).await?;
+		Ok(socket)
+	}
+}
+
+/// Bound websocket connection state for the notification stream.
+struct Connection<V: FhirVersion> {
+	/// Settings to (re-)connect with.
+	settings: SubscriptionWebSocket<V>,
+	/// Current websocket, `None` if it needs to be re-established.
+	socket: Option<Socket>,
+	/// Number of consecutive connection failures.
+	failures: usize,
+}
+
+impl<V: FhirVersion> Connection<V>
+where
+	(StatusCode, V::OperationOutcome): Into<Error>,
+{
+	/// Receive the next notification, reconnecting if necessary. Returns the
+	/// result and whether the stream should continue afterwards.
+	async fn next_notification(&mut self) -> Option<(Result<V::Bundle, Error>, bool)> {
+		loop {
+			let Some(socket) = self.socket.as_mut() else {
+				tokio::time::sleep(self.settings.reconnect_delay).await;
+				match self.settings.bind().await {
+					Ok(socket) => self.socket = Some(socket),
+					Err(err) => {
+						if let Some(err) = self.failed(err) {
+							return Some((Err(err), false));
+						}
+					}
+				}
+				continue;
+			};
+
+			let received = match self.settings.heartbeat_timeout {
+				Some(timeout) => {
+					tokio::time::timeout(timeout, socket.next()).await.unwrap_or_else(|_elapsed| {
+						Some(Err(tungstenite::Error::Io(std::io::Error::new(
+							std::io::ErrorKind::TimedOut,
+							"No heartbeat received in time",
+						))))
+					})
+				}
+				None => socket.next().await,
+			};
+
+			let err = match received {
+				Some(Ok(Message::Text(text))) => {
+					self.failures = 0;
+					match decode_notification::<V>(text.as_bytes()) {
+						Some(result) => return Some((result, true)),
+						None => continue,
+					}
+				}
+				Some(Ok(Message::Binary(data))) => {
+					self.failures = 0;
+					match decode_notification::<V>(&data) {
+						Some(result) => return Some((result, true)),
+						None => continue,
+					}
+				}
+				// Pings are answered automatically.
+				Some(Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_))) => continue,
+				Some(Ok(Message::Close(_))) | None => {
+					Error::WebSocket(tungstenite::Error::ConnectionClosed)
+				}
+				Some(Err(err)) => err.into(),
+			};
+			self.socket = None;
+			if let Some(err) = self.failed(err) {
+				return Some((Err(err), false));
+			}
+		}
+	}
+
+	/// Register a connection failure. Returns the error if there are no reconnects
+	/// left.
+	fn failed(&mut self, err: Error) -> Option<Error> {
+		self.failures += 1;
+		if self.failures > self.settings.max_reconnects {
+			tracing::error!("Subscription websocket failed, giving up: {err}");
+			Some(err)
+		} else {
+			tracing::warn!("Subscription websocket failed, reconnecting: {err}");
+			None
+		}
+	}
+}
+
+/// Decode a notification `Bundle` from a websocket message. Returns `None` for
+/// non-JSON messages, which some servers send as keep-alive or binding
+/// confirmation.
+fn decode_notification<V: FhirVersion>(message: &[u8]) -> Option<Result<V::Bundle, Error>> {
+	if message.iter().find(|byte| !byte.is_ascii_whitespace()) != Some(&b'{') {
+		tracing::debug!(
+			"Ignoring non-JSON websocket message: {}",
+			String::from_utf8_lossy(message)
+		);
+		return None;
+	}
+	Some(serde_json::from_slice(message).map_err(Error::from))
+}
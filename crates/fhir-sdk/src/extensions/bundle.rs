@@ -5,7 +5,7 @@ use std::{
 	str::FromStr,
 };
 
-use fhir_model::for_all_versions;
+use fhir_model::{for_all_versions, Instant};
 use serde::{de::DeserializeOwned, Serialize};
 
 use super::{GenericResource, SearchEntryModeExt};
@@ -35,6 +35,11 @@ pub trait BundleExt {
 	/// Iterate over owned entries, consuming this Bundle.
 	fn into_entries(self) -> impl Iterator<Item = Self::Entry> + Send + 'static;
 
+	/// Whether this `Bundle` has the type used for subscription notifications (`history` in the
+	/// R4B backport, `subscription-notification` in R5).
+	#[cfg_attr(not(feature = "server"), allow(dead_code))] // Feature specific.
+	fn is_subscription_notification(&self) -> bool;
+
 	/// Create a new `Bundle` of type batch.
 	fn make_batch(entries: Vec<Option<Self::Entry>>) -> Self;
 	/// Create a new `Bundle` of type transaction.
@@ -75,6 +80,10 @@ macro_rules! impl_bundle_ext {
 					self.0.entry.into_iter().flatten()
 				}
 
+				fn is_subscription_notification(&self) -> bool {
+					impl_bundle_ext!(@is_subscription_notification $version self)
+				}
+
 				fn make_batch(entries: Vec<Option<Self::Entry>>) -> Self {
 					#[allow(clippy::unwrap_used)] // Will always succeed.
 					Self::builder().r#type(BundleType::Batch).entry(entries).build().unwrap()
@@ -87,6 +96,15 @@ macro_rules! impl_bundle_ext {
 			}
 		}
 	};
+	(@is_subscription_notification stu3 $s:expr) => {
+		false
+	};
+	(@is_subscription_notification r4b $s:expr) => {
+		$s.r#type == BundleType::History
+	};
+	(@is_subscription_notification $version:ident $s:expr) => {
+		$s.r#type == BundleType::SubscriptionNotification
+	};
 }
 #[cfg(feature = "builders")]
 mod bundle_ext {
@@ -118,6 +136,16 @@ pub trait BundleEntryExt {
 		+ Unpin
 		+ Send
 		+ Sync;
+	/// `BundleEntryResponse` type.
+	type Response: BundleEntryResponseExt<Resource = Self::Resource>
+		+ Serialize
+		+ DeserializeOwned
+		+ Debug
+		+ Clone
+		+ PartialEq
+		+ Unpin
+		+ Send
+		+ Sync;
 	/// Search entry mode.
 	type SearchEntryMode: SearchEntryModeExt
 		+ Serialize
@@ -140,6 +168,8 @@ pub trait BundleEntryExt {
 	fn full_url(&self) -> Option<&String>;
 	/// Get the request field.
 	fn request(&self) -> Option<&Self::Request>;
+	/// Get the response field.
+	fn response(&self) -> Option<&Self::Response>;
 	/// Get the inner resource.
 	fn resource(&self) -> Option<&Self::Resource>;
 
@@ -162,7 +192,7 @@ macro_rules! impl_bundle_entry_ext {
 		mod $version {
 			use fhir_model::$version::{
 				codes::SearchEntryMode,
-				resources::{BundleEntry, BundleEntryRequest, Resource},
+				resources::{BundleEntry, BundleEntryRequest, BundleEntryResponse, Resource},
 			};
 
 			use super::*;
@@ -170,6 +200,7 @@ macro_rules! impl_bundle_entry_ext {
 			impl BundleEntryExt for BundleEntry {
 				type Resource = Resource;
 				type Request = BundleEntryRequest;
+				type Response = BundleEntryResponse;
 				type SearchEntryMode = SearchEntryMode;
 
 				fn search_mode(&self) -> Option<&Self::SearchEntryMode> {
@@ -184,6 +215,10 @@ macro_rules! impl_bundle_entry_ext {
 					self.request.as_ref()
 				}
 
+				fn response(&self) -> Option<&Self::Response> {
+					self.response.as_ref()
+				}
+
 				fn resource(&self) -> Option<&Self::Resource> {
 					self.resource.as_ref()
 				}
@@ -227,6 +262,10 @@ mod bundle_entry_ext {
 pub trait BundleEntryRequestExt {
 	/// Is this request a DELETE request (as per method)?
 	fn is_delete(&self) -> bool;
+	/// Get the method, e.g. `PUT`.
+	fn method(&self) -> &str;
+	/// Get the URL.
+	fn url(&self) -> &str;
 
 	/// Create new `BundleEntryRequest` with only url, with the method set to
 	/// POST.
@@ -240,9 +279,23 @@ pub trait BundleEntryRequestExt {
 	/// Create new `BundleEntryRequest` with only url, with the method set to
 	/// DELETE.
 	fn make_delete(url: String) -> Self;
+	/// Create new `BundleEntryRequest` with only url, with the method set to
+	/// PATCH. Returns `None` if the version does not support it.
+	fn make_patch(url: String) -> Option<Self>
+	where
+		Self: Sized;
 	/// Use the current request and return it with the if_match set to the
 	/// value.
 	fn with_if_match(self, if_match: String) -> Self;
+	/// Use the current request and return it with the if_none_match set to the
+	/// value.
+	fn with_if_none_match(self, if_none_match: String) -> Self;
+	/// Use the current request and return it with the if_modified_since set to
+	/// the value.
+	fn with_if_modified_since(self, if_modified_since: Instant) -> Self;
+	/// Use the current request and return it with the if_none_exist set to the
+	/// value.
+	fn with_if_none_exist(self, if_none_exist: String) -> Self;
 }
 
 /// Implement `BundleEntryRequestExt` for all `BundleEntry` versions.
@@ -258,6 +311,14 @@ macro_rules! impl_bundle_entry_request_ext {
 					self.method == HTTPVerb::Delete
 				}
 
+				fn method(&self) -> &str {
+					AsRef::<str>::as_ref(&self.method)
+				}
+
+				fn url(&self) -> &str {
+					&self.url
+				}
+
 				fn make_post(url: String) -> Self {
 					#[allow(clippy::unwrap_used)] // Will always succeed.
 					Self::builder().url(url).method(HTTPVerb::Post).build().unwrap()
@@ -278,13 +339,40 @@ macro_rules! impl_bundle_entry_request_ext {
 					Self::builder().url(url).method(HTTPVerb::Delete).build().unwrap()
 				}
 
+				fn make_patch(url: String) -> Option<Self> {
+					impl_bundle_entry_request_ext!(@make_patch $version url)
+				}
+
 				fn with_if_match(mut self, if_match: String) -> Self {
 					self.if_match = Some(if_match);
 					self
 				}
+
+				fn with_if_none_match(mut self, if_none_match: String) -> Self {
+					self.if_none_match = Some(if_none_match);
+					self
+				}
+
+				fn with_if_modified_since(mut self, if_modified_since: Instant) -> Self {
+					self.if_modified_since = Some(if_modified_since);
+					self
+				}
+
+				fn with_if_none_exist(mut self, if_none_exist: String) -> Self {
+					self.if_none_exist = Some(if_none_exist);
+					self
+				}
 			}
 		}
 	};
+	(@make_patch stu3 $url:expr) => {{
+		_ = $url;
+		None
+	}};
+	(@make_patch $version:ident $url:expr) => {{
+		#[allow(clippy::unwrap_used)] // Will always succeed.
+		Some(Self::builder().url($url).method(HTTPVerb::Patch).build().unwrap())
+	}};
 }
 #[cfg(feature = "builders")]
 mod bundle_entry_request_ext {
@@ -292,3 +380,56 @@ mod bundle_entry_request_ext {
 	use super::*;
 	for_all_versions!(impl_bundle_entry_request_ext);
 }
+
+/// Additional/generalized functionality on `BundleEntryResponse`s. Only implemented if "builders"
+/// feature is active.
+pub trait BundleEntryResponseExt {
+	/// Generic resource enum for this version.
+	type Resource;
+
+	/// Get the status, e.g. `201 Created`.
+	fn status(&self) -> &str;
+	/// Get the location, if there is one.
+	fn location(&self) -> Option<&str>;
+	/// Get the ETag, if there is one.
+	fn etag(&self) -> Option<&str>;
+	/// Get the outcome, if there is one.
+	fn outcome(&self) -> Option<&Self::Resource>;
+}
+
+/// Implement `BundleEntryResponseExt` for all `BundleEntry` versions.
+macro_rules! impl_bundle_entry_response_ext {
+	($version:ident) => {
+		mod $version {
+			use fhir_model::$version::resources::{BundleEntryResponse, Resource};
+
+			use super::*;
+
+			impl BundleEntryResponseExt for BundleEntryResponse {
+				type Resource = Resource;
+
+				fn status(&self) -> &str {
+					&self.status
+				}
+
+				fn location(&self) -> Option<&str> {
+					self.location.as_deref()
+				}
+
+				fn etag(&self) -> Option<&str> {
+					self.etag.as_deref()
+				}
+
+				fn outcome(&self) -> Option<&Self::Resource> {
+					self.outcome.as_ref()
+				}
+			}
+		}
+	};
+}
+#[cfg(feature = "builders")]
+mod bundle_entry_response_ext {
+	//! Module to avoid conflicts.
+	use super::*;
+	for_all_versions!(impl_bundle_entry_response_ext);
+}
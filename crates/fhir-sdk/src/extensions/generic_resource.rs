@@ -1,6 +1,6 @@
 //! Extensions on generic resource enum.
 
-use fhir_model::for_all_versions;
+use fhir_model::{for_all_versions, Instant};
 
 /// Extended/shared functionality for generic resource enums. Only implemented if "builders" feature
 /// is active.
@@ -16,6 +16,13 @@ pub trait GenericResource {
 	fn version_id(&self) -> Option<&str>;
 	/// Set the version ID of the resource.
 	fn set_version_id(&mut self, version_id: String);
+	/// Get the last updated instant of the resource.
+	fn last_updated(&self) -> Option<&Instant>;
+	/// Get the contained resources, empty if the resource is no domain
+	/// resource.
+	fn contained(&self) -> &[Self]
+	where
+		Self: Sized;
 }
 
 /// Implement the generic resource trait for all versions.
@@ -62,6 +69,19 @@ macro_rules! impl_generic_resource {
 						));
 					}
 				}
+
+				#[inline]
+				fn last_updated(&self) -> Option<&Instant> {
+					self.as_base_resource()
+						.meta()
+						.as_ref()
+						.and_then(|meta| meta.last_updated.as_ref())
+				}
+
+				#[inline]
+				fn contained(&self) -> &[Self] {
+					self.as_domain_resource().map_or(&[], |resource| resource.contained())
+				}
 			}
 		}
 	};
@@ -7,7 +7,10 @@ mod codes;
 mod generic_resource;
 mod parameters;
 mod references;
+mod subscription;
 
 pub use self::any_resource::AnyResource;
 #[allow(unused_imports)] // Feature specific.
-pub(crate) use self::{bundle::*, codes::*, generic_resource::*, parameters::*, references::*};
+pub(crate) use self::{
+	bundle::*, codes::*, generic_resource::*, parameters::*, references::*, subscription::*,
+};
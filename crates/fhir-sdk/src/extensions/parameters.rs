@@ -2,7 +2,7 @@
 
 use std::fmt::Debug;
 
-use fhir_model::for_all_versions;
+use fhir_model::{for_all_versions, DateTime};
 use serde::{de::DeserializeOwned, Serialize};
 
 /// Trait for additional functionality of Parameters. Only implemented if "builders" feature is
@@ -21,6 +21,14 @@ pub trait ParametersExt {
 
 	/// Make a `Parameters` instance using the inner parameters.
 	fn make(parameters: Vec<Option<Self::Parameter>>) -> Self;
+	/// Iterate over the inner parameters.
+	#[cfg_attr(not(feature = "websocket"), allow(dead_code))] // Feature specific.
+	fn parameters(&self) -> impl Iterator<Item = &Self::Parameter> + Send;
+	/// Find the first parameter with the given name.
+	#[cfg_attr(not(feature = "websocket"), allow(dead_code))] // Feature specific.
+	fn find_parameter(&self, name: &str) -> Option<&Self::Parameter> {
+		self.parameters().find(|parameter| parameter.name() == name)
+	}
 }
 
 /// Implement `ParametersExt` for all `Parameters` versions.
@@ -39,6 +47,11 @@ macro_rules! impl_parameters_ext {
 					#[allow(clippy::unwrap_used)] // Will always succeed.
 					Self::builder().parameter(parameters).build().unwrap()
 				}
+
+				#[inline]
+				fn parameters(&self) -> impl Iterator<Item = &Self::Parameter> + Send {
+					self.parameter.iter().flatten()
+				}
 			}
 		}
 	};
@@ -66,6 +79,12 @@ pub trait ParameterExt: Sized {
 
 	/// Make a `ParametersParameter` instance using the inner parameters.
 	fn make(name: String, value: Option<Self::Value>, part: Vec<Option<Self>>) -> Self;
+	/// Get the name of the parameter.
+	#[cfg_attr(not(feature = "websocket"), allow(dead_code))] // Feature specific.
+	fn name(&self) -> &str;
+	/// Get the `value[x]` of the parameter, if there is one.
+	#[cfg_attr(not(feature = "websocket"), allow(dead_code))] // Feature specific.
+	fn value(&self) -> Option<&Self::Value>;
 }
 
 /// Implement `ParameterExt` for all `ParametersParameter` versions.
@@ -88,6 +107,16 @@ macro_rules! impl_parameters_parameter_ext {
 						Self::builder().name(name).part(part).build().unwrap()
 					}
 				}
+
+				#[inline]
+				fn name(&self) -> &str {
+					&self.name
+				}
+
+				#[inline]
+				fn value(&self) -> Option<&Self::Value> {
+					self.value.as_ref()
+				}
 			}
 		}
 	};
@@ -110,6 +139,14 @@ pub trait ParameterValueExt {
 	/// Make a `ParametersParameterValue::Integer` instance using the inner
 	/// parameters.
 	fn make_integer(value: i32) -> Self;
+
+	/// Get the value as string, if it is any of the string-like primitive
+	/// types (e.g. `string`, `code`, `uri` or `url`).
+	#[cfg_attr(not(feature = "websocket"), allow(dead_code))] // Feature specific.
+	fn as_string(&self) -> Option<&str>;
+	/// Get the value as `dateTime`, if it is one.
+	#[cfg_attr(not(feature = "websocket"), allow(dead_code))] // Feature specific.
+	fn as_date_time(&self) -> Option<&DateTime>;
 }
 
 /// Implement `ParameterValueExt` for all `ParametersParameterValue` versions.
@@ -135,9 +172,47 @@ macro_rules! impl_parameter_value_ext {
 				fn make_integer(value: i32) -> Self {
 					Self::Integer(value)
 				}
+
+				#[inline]
+				fn as_string(&self) -> Option<&str> {
+					impl_parameter_value_ext!(@as_string $version self)
+				}
+
+				#[inline]
+				fn as_date_time(&self) -> Option<&DateTime> {
+					match self {
+						Self::DateTime(date_time) => Some(date_time),
+						_ => None,
+					}
+				}
 			}
 		}
 	};
+	(@as_string stu3 $s:expr) => {
+		match $s {
+			Self::String(value)
+			| Self::Code(value)
+			| Self::Id(value)
+			| Self::Markdown(value)
+			| Self::Oid(value)
+			| Self::Uri(value) => Some(value.as_str()),
+			_ => None,
+		}
+	};
+	(@as_string $version:ident $s:expr) => {
+		match $s {
+			Self::String(value)
+			| Self::Code(value)
+			| Self::Id(value)
+			| Self::Markdown(value)
+			| Self::Oid(value)
+			| Self::Uri(value)
+			| Self::Url(value)
+			| Self::Canonical(value)
+			| Self::Uuid(value) => Some(value.as_str()),
+			_ => None,
+		}
+	};
 }
 mod helper_module_3 {
 	//! Helper module to avoid conflicts.
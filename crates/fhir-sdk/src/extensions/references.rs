@@ -6,6 +6,8 @@ use fhir_model::{for_all_versions, ParsedReference};
 pub trait ReferenceExt {
 	/// Parse into [`ParsedReference`].
 	fn parse(&self) -> Option<ParsedReference<'_>>;
+	/// Get the raw reference string, if there is one.
+	fn reference(&self) -> Option<&str>;
 	/// Get the defined type, if there is one.
 	fn r#type(&self) -> Option<&str>;
 }
@@ -23,6 +25,10 @@ macro_rules! impl_reference_ext {
 					Self::parse(self)
 				}
 
+				fn reference(&self) -> Option<&str> {
+					self.reference.as_deref()
+				}
+
 				fn r#type(&self) -> Option<&str> {
 					impl_reference_ext!(@get_type $version self)
 				}
@@ -0,0 +1,45 @@
+//! Generalized functionality for topic-based subscriptions.
+
+use fhir_model::for_all_versions;
+
+/// Additional functionality for `SubscriptionStatus` resources. Only implemented for FHIR
+/// versions with topic-based subscriptions.
+#[cfg_attr(not(feature = "server"), allow(dead_code))] // Feature specific.
+pub trait SubscriptionStatusExt {
+	/// Whether this is a `handshake` notification.
+	fn is_handshake(&self) -> bool;
+	/// Whether this is a `heartbeat` notification.
+	fn is_heartbeat(&self) -> bool;
+	/// Get the reference to the `Subscription` this status belongs to.
+	fn subscription_reference(&self) -> Option<&str>;
+}
+
+/// Implement `SubscriptionStatusExt` for all supported versions.
+macro_rules! impl_subscription_status_ext {
+	// This version does not have topic-based subscriptions.
+	(stu3) => {};
+	($version:ident) => {
+		mod $version {
+			use fhir_model::$version::{
+				codes::SubscriptionNotificationType, resources::SubscriptionStatus,
+			};
+
+			use super::*;
+
+			impl SubscriptionStatusExt for SubscriptionStatus {
+				fn is_handshake(&self) -> bool {
+					self.r#type == SubscriptionNotificationType::Handshake
+				}
+
+				fn is_heartbeat(&self) -> bool {
+					self.r#type == SubscriptionNotificationType::Heartbeat
+				}
+
+				fn subscription_reference(&self) -> Option<&str> {
+					self.subscription.reference.as_deref()
+				}
+			}
+		}
+	};
+}
+for_all_versions!(impl_subscription_status_ext);
@@ -7,9 +7,15 @@
 	doc = "Enable the following features to see the crate-level documentation: r5, client, docs"
 )]
 
+#[cfg(feature = "patch")]
+pub mod changes;
 #[cfg(feature = "client")]
 pub mod client;
 pub mod extensions;
+#[cfg(feature = "patch")]
+pub mod patch;
+#[cfg(feature = "server")]
+pub mod server;
 mod utils;
 pub mod version;
 
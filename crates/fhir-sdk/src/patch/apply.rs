@@ -0,0 +1,748 @@
+//! Local application of patches to resources.
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Value};
+
+use super::{element::Element, FhirPathPatchOperation, JsonPatchOperation, PatchError};
+use crate::version::FhirVersion;
+
+/// Apply the `JSON Patch` operations to the JSON form of the resource and
+/// return the patched resource. Fails on the first operation that cannot be
+/// applied, e.g. a `test` operation with a different value.
+pub fn apply<R>(resource: &R, operations: &[JsonPatchOperation]) -> Result<R, PatchError>
+where
+	R: Serialize + DeserializeOwned,
+{
+	let mut document = serde_json::to_value(resource)?;
+	for (index, operation) in operations.iter().enumerate() {
+		apply_json_operation(&mut document, operation).map_err(|reason| {
+			PatchError::OperationFailed { index, path: operation.path().to_owned(), reason }
+		})?;
+	}
+	Ok(serde_json::from_value(document)?)
+}
+
+/// Apply the `FHIRPath Patch` operations to the JSON form of the resource and
+/// return the patched resource. Fails on the first operation that cannot be
+/// applied.
+///
+/// Only simple paths of element names with optional list indices are
+/// supported, e.g. `Patient.name[0].given`. Whether an added element is a list
+/// and the keys of choice elements, e.g. `deceasedBoolean`, are taken from the
+/// element definitions of the FHIR version.
+pub fn apply_fhirpath<V, R>(
+	resource: &R,
+	operations: &[FhirPathPatchOperation],
+) -> Result<R, PatchError>
+where
+	V: FhirVersion,
+	R: Serialize + DeserializeOwned,
+{
+	let mut document = serde_json::to_value(resource)?;
+	for (index, operation) in operations.iter().enumerate() {
+		apply_fhirpath_operation::<V>(&mut document, operation).map_err(|reason| {
+			PatchError::OperationFailed { index, path: operation.path().to_owned(), reason }
+		})?;
+	}
+	Ok(serde_json::from_value(document)?)
+}
+
+/// Read the `FHIRPath Patch` operations from a `Parameters` resource, e.g. one
+/// built by [`PatchViaFhir`](crate::client::PatchViaFhir::parameters).
+///
+/// Values given as `value[x]` are taken as is. Values given as `part`s are
+/// turned into objects, where the element definitions of the FHIR version
+/// decide which elements are lists and the keys of choice elements.
+pub fn fhirpath_operations<V: FhirVersion, P: Serialize>(
+	parameters: &P,
+) -> Result<Vec<FhirPathPatchOperation>, PatchError> {
+	let parameters = serde_json::to_value(parameters)?;
+	parameters
+		.get("parameter")
+		.and_then(Value::as_array)
+		.into_iter()
+		.flatten()
+		.map(parse_operation::<V>)
+		.collect()
+}
+
+/// Parse an `operation` parameter of a `FHIRPath Patch`.
+fn parse_operation<V: FhirVersion>(
+	parameter: &Value,
+) -> Result<FhirPathPatchOperation, PatchError> {
+	if parameter.get("name").and_then(Value::as_str) != Some("operation") {
+		return Err(PatchError::InvalidParameters("Expected `operation` parameter".to_owned()));
+	}
+
+	let part = |name: &str| {
+		parameter
+			.get("part")
+			.and_then(Value::as_array)
+			.into_iter()
+			.flatten()
+			.find(|part| part.get("name").and_then(Value::as_str) == Some(name))
+			.ok_or_else(|| PatchError::InvalidParameters(format!("Missing part `{name}`")))
+	};
+	let primitive = |name: &str| {
+		part(name)?
+			.as_object()
+			.and_then(|part| part.iter().find(|(key, _)| key.starts_with("value")))
+			.map(|(_, value)| value)
+			.ok_or_else(|| PatchError::InvalidParameters(format!("Part `{name}` has no value")))
+	};
+	let string = |name: &str| {
+		primitive(name)?
+			.as_str()
+			.map(ToOwned::to_owned)
+			.ok_or_else(|| PatchError::InvalidParameters(format!("Part `{name}` is not a string")))
+	};
+	let integer = |name: &str| {
+		primitive(name)?.as_i64().and_then(|integer| i32::try_from(integer).ok()).ok_or_else(|| {
+			PatchError::InvalidParameters(format!("Part `{name}` is not an integer"))
+		})
+	};
+	// The value of the element, with the type if it is a choice element.
+	let value = |element: Element| {
+		let part = part("value")?;
+		let element = typed(element, part)?;
+		Ok::<_, PatchError>((parameter_value::<V>(part, &element)?, element.choice_type()))
+	};
+
+	let path = string("path")?;
+	let operation = match string("type")?.as_str() {
+		"add" => {
+			let name = string("name")?;
+			let (value, value_type) = value(Element::resolve::<V>(&path)?.child::<V>(&name)?)?;
+			FhirPathPatchOperation::Add { path, name, value, value_type }
+		}
+		"insert" => {
+			let (value, value_type) = value(Element::resolve::<V>(&path)?)?;
+			FhirPathPatchOperation::Insert { path, value, value_type, index: integer("index")? }
+		}
+		"delete" => FhirPathPatchOperation::Delete { path },
+		"replace" => {
+			let (value, value_type) = value(Element::resolve::<V>(&path)?)?;
+			FhirPathPatchOperation::Replace { path, value, value_type }
+		}
+		"move" => FhirPathPatchOperation::Move {
+			path,
+			source: integer("source")?,
+			destination: integer("destination")?,
+		},
+		other => {
+			return Err(PatchError::InvalidParameters(format!("Unknown operation type `{other}`")))
+		}
+	};
+	Ok(operation)
+}
+
+/// Set the type of a choice element from the parameter's `value[x]`, e.g.
+/// `Quantity` for `valueQuantity`.
+fn typed(element: Element, parameter: &Value) -> Result<Element, PatchError> {
+	if !element.is_choice() {
+		return Ok(element);
+	}
+	let value_type = parameter
+		.as_object()
+		.and_then(|parameter| parameter.keys().find_map(|key| key.strip_prefix("value")));
+	element.with_type(value_type)
+}
+
+/// Get the value of a parameter for the element in its FHIR JSON form, either
+/// from its `value[x]` or `resource` or built from its `part`s.
+fn parameter_value<V: FhirVersion>(
+	parameter: &Value,
+	element: &Element,
+) -> Result<Value, PatchError> {
+	let Some(parameter) = parameter.as_object() else {
+		return Err(PatchError::InvalidParameters("Parameter is not an object".to_owned()));
+	};
+	if let Some((_, value)) = parameter.iter().find(|(key, _)| key.starts_with("value")) {
+		return Ok(value.clone());
+	}
+	if element.is_resource() {
+		return parameter.get("resource").cloned().ok_or_else(|| {
+			PatchError::InvalidParameters(format!("Missing resource for `{}`", element.path()))
+		});
+	}
+
+	let mut object = Map::new();
+	for part in parameter.get("part").and_then(Value::as_array).into_iter().flatten() {
+		let name = part
+			.get("name")
+			.and_then(Value::as_str)
+			.ok_or_else(|| PatchError::InvalidParameters("Part without name".to_owned()))?;
+		let child = typed(element.child::<V>(name)?, part)?;
+		let value = parameter_value::<V>(part, &child)?;
+		let key = child.json_key()?;
+		if child.is_array() {
+			if let Value::Array(list) =
+				object.entry(key).or_insert_with(|| Value::Array(Vec::new()))
+			{
+				list.push(value);
+			}
+		} else if object.insert(key, value).is_some() {
+			return Err(PatchError::InvalidParameters(format!(
+				"Repeated part for single element `{}`",
+				child.path()
+			)));
+		}
+	}
+	Ok(Value::Object(object))
+}
+
+/// Apply a single `JSON Patch` operation to the document.
+fn apply_json_operation(
+	document: &mut Value,
+	operation: &JsonPatchOperation,
+) -> Result<(), String> {
+	match operation {
+		JsonPatchOperation::Add { path, value } => pointer_add(document, path, value.clone()),
+		JsonPatchOperation::Remove { path } => pointer_remove(document, path).map(drop),
+		JsonPatchOperation::Replace { path, value } => {
+			let target = document.pointer_mut(path).ok_or("No value at the path")?;
+			*target = value.clone();
+			Ok(())
+		}
+		JsonPatchOperation::Move { from, path } => {
+			if path.starts_with(&format!("{from}/")) {
+				return Err(format!("Cannot move `{from}` into its own child"));
+			}
+			let value = pointer_remove(document, from)?;
+			pointer_add(document, path, value)
+		}
+		JsonPatchOperation::Copy { from, path } => {
+			let value = document.pointer(from).cloned().ok_or("No value at `from`")?;
+			pointer_add(document, path, value)
+		}
+		JsonPatchOperation::Test { path, value } => match document.pointer(path) {
+			Some(actual) if actual == value => Ok(()),
+			Some(actual) => Err(format!("Expected value {value}, found {actual}")),
+			None => Err("No value at the path".to_owned()),
+		},
+	}
+}
+
+/// Split the JSON pointer into the pointer to the parent and the unescaped
+/// last token.
+fn split_pointer(pointer: &str) -> Result<(&str, String), String> {
+	let (parent, token) =
+		pointer.rsplit_once('/').ok_or_else(|| format!("Invalid JSON pointer `{pointer}`"))?;
+	Ok((parent, token.replace("~1", "/").replace("~0", "~")))
+}
+
+/// Parse a JSON pointer token as list index.
+fn pointer_index(token: &str) -> Result<usize, String> {
+	token
+		.parse()
+		.ok()
+		.filter(|_| token == "0" || !token.starts_with('0'))
+		.ok_or_else(|| format!("Invalid list index `{token}`"))
+}
+
+/// Add the value at the JSON pointer, inserting into lists.
+fn pointer_add(document: &mut Value, pointer: &str, value: Value) -> Result<(), String> {
+	if pointer.is_empty() {
+		*document = value;
+		return Ok(());
+	}
+
+	let (parent, token) = split_pointer(pointer)?;
+	match document.pointer_mut(parent) {
+		Some(Value::Object(object)) => {
+			object.insert(token, value);
+			Ok(())
+		}
+		Some(Value::Array(list)) => {
+			let index = if token == "-" { list.len() } else { pointer_index(&token)? };
+			if index > list.len() {
+				return Err(format!("List index {index} is out of bounds"));
+			}
+			list.insert(index, value);
+			Ok(())
+		}
+		Some(_) => Err("Parent is neither an object nor a list".to_owned()),
+		None => Err("Parent does not exist".to_owned()),
+	}
+}
+
+/// Remove and return the value at the JSON pointer.
+fn pointer_remove(document: &mut Value, pointer: &str) -> Result<Value, String> {
+	let (parent, token) = split_pointer(pointer)?;
+	match document.pointer_mut(parent) {
+		Some(Value::Object(object)) => {
+			object.remove(&token).ok_or_else(|| "No value at the path".to_owned())
+		}
+		Some(Value::Array(list)) => {
+			let index = pointer_index(&token)?;
+			if index >= list.len() {
+				return Err(format!("List index {index} is out of bounds"));
+			}
+			Ok(list.remove(index))
+		}
+		_ => Err("No value at the path".to_owned()),
+	}
+}
+
+/// A step into a JSON value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+	/// Element of an object.
+	Key(String),
+	/// Element of a list.
+	Index(usize),
+}
+
+/// Get the value at the steps.
+fn value_at_mut<'v>(document: &'v mut Value, steps: &[Step]) -> Option<&'v mut Value> {
+	steps.iter().try_fold(document, |value, step| match step {
+		Step::Key(key) => value.get_mut(key.as_str()),
+		Step::Index(index) => value.get_mut(*index),
+	})
+}
+
+/// Parse a FHIRPath segment of an element name with optional list index.
+fn parse_segment(segment: &str) -> Result<(&str, Option<usize>), String> {
+	let (name, index) = match segment.split_once('[') {
+		Some((name, index)) => {
+			let index = index
+				.strip_suffix(']')
+				.and_then(|index| index.parse().ok())
+				.ok_or_else(|| format!("Unsupported FHIRPath segment `{segment}`"))?;
+			(name, Some(index))
+		}
+		None => (segment, None),
+	};
+	if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+		return Err(format!("Unsupported FHIRPath segment `{segment}`"));
+	}
+	Ok((name, index))
+}
+
+/// Resolve the simple FHIRPath to the steps into the document. Lists with a
+/// single element are stepped into implicitly and choice elements are found by
+/// their typed keys. Returns `None` if the path matches no element.
+fn resolve<V: FhirVersion>(document: &Value, path: &str) -> Result<Option<Vec<Step>>, String> {
+	let mut segments = path.split('.');
+	let resource_type = segments.next().unwrap_or_default();
+	if document.get("resourceType").and_then(Value::as_str) != Some(resource_type) {
+		return Err("Path does not start with the resource type".to_owned());
+	}
+
+	let mut steps = Vec::new();
+	let mut element = Element::root(resource_type);
+	let mut current = document;
+	for segment in segments {
+		let (name, index) = parse_segment(segment)?;
+		if let Value::Array(list) = current {
+			let [single] = list.as_slice() else {
+				return Err("Path matches multiple elements".to_owned());
+			};
+			steps.push(Step::Index(0));
+			current = single;
+		}
+
+		element = element.child::<V>(name).map_err(|error| error.to_string())?;
+		let Some((key, next)) = element.find_in(current) else { return Ok(None) };
+		steps.push(Step::Key(key));
+		current = next;
+
+		match (index, current) {
+			(Some(index), Value::Array(list)) => {
+				let Some(next) = list.get(index) else { return Ok(None) };
+				steps.push(Step::Index(index));
+				current = next;
+			}
+			(Some(index), _) if index > 0 => return Ok(None),
+			_ => {}
+		}
+	}
+	Ok(Some(steps))
+}
+
+/// Get the value of the element at the simple FHIRPath, if there is one.
+pub(crate) fn value_at<'v, V: FhirVersion>(document: &'v Value, path: &str) -> Option<&'v Value> {
+	let steps = resolve::<V>(document, path).ok()??;
+	steps.iter().try_fold(document, |value, step| match step {
+		Step::Key(key) => value.get(key.as_str()),
+		Step::Index(index) => value.get(*index),
+	})
+}
+
+/// Resolve the path to the steps of exactly one element, stepping into lists
+/// with a single element.
+fn resolve_single<V: FhirVersion>(document: &Value, path: &str) -> Result<Vec<Step>, String> {
+	let mut steps = resolve::<V>(document, path)?.ok_or("Path matches no element")?;
+	let mut current = document;
+	for step in &steps {
+		current = match step {
+			Step::Key(key) => current.get(key.as_str()),
+			Step::Index(index) => current.get(*index),
+		}
+		.ok_or("Path matches no element")?;
+	}
+	if let Value::Array(list) = current {
+		if list.len() != 1 {
+			return Err("Path matches multiple elements".to_owned());
+		}
+		steps.push(Step::Index(0));
+	}
+	Ok(steps)
+}
+
+/// Remove the value at the steps. Lists that become empty are removed as well.
+fn remove_at(document: &mut Value, steps: &[Step]) -> Result<(), String> {
+	let Some((last, parent_steps)) = steps.split_last() else {
+		return Err("Cannot delete the resource itself".to_owned());
+	};
+	match (value_at_mut(document, parent_steps), last) {
+		(Some(Value::Object(object)), Step::Key(key)) => {
+			object.remove(key);
+		}
+		(Some(Value::Array(list)), Step::Index(index)) if *index < list.len() => {
+			list.remove(*index);
+			if list.is_empty() {
+				return remove_at(document, parent_steps);
+			}
+		}
+		_ => return Err("Path matches no element".to_owned()),
+	}
+	Ok(())
+}
+
+/// Resolve the element definition of the path, with the child of the given
+/// name and the type of choice elements.
+fn element_at<V: FhirVersion>(
+	path: &str,
+	name: Option<&str>,
+	value_type: Option<&str>,
+) -> Result<Element, String> {
+	let mut element = Element::resolve::<V>(path).map_err(|error| error.to_string())?;
+	if let Some(name) = name {
+		element = element.child::<V>(name).map_err(|error| error.to_string())?;
+	}
+	element.with_type(value_type).map_err(|error| error.to_string())
+}
+
+/// Apply a single `FHIRPath Patch` operation to the document.
+fn apply_fhirpath_operation<V: FhirVersion>(
+	document: &mut Value,
+	operation: &FhirPathPatchOperation,
+) -> Result<(), String> {
+	match operation {
+		FhirPathPatchOperation::Add { path, name, value, value_type } => {
+			let steps = resolve_single::<V>(document, path)?;
+			let mut element = element_at::<V>(path, Some(name), value_type.as_deref())?;
+			let key = element.json_key().map_err(|error| error.to_string())?;
+			let Some(parent) = value_at_mut(document, &steps) else {
+				return Err("Path matches no element".to_owned());
+			};
+			let existing = element.find_in(parent).map(|(key, _)| key);
+			let Value::Object(parent) = parent else {
+				return Err("Path does not match an element with children".to_owned());
+			};
+			match (existing, element.is_array()) {
+				(Some(existing), true) => match parent.get_mut(&existing) {
+					Some(Value::Array(list)) => list.push(value.clone()),
+					_ => return Err(format!("Element `{name}` is not a list")),
+				},
+				(Some(_), false) => return Err(format!("Element `{name}` already exists")),
+				(None, true) => {
+					parent.insert(key, Value::Array(vec![value.clone()]));
+				}
+				(None, false) => {
+					parent.insert(key, value.clone());
+				}
+			}
+			Ok(())
+		}
+		FhirPathPatchOperation::Insert { path, value, index, .. } => {
+			let index = usize::try_from(*index).map_err(|_| "Negative index".to_owned())?;
+			let Some(steps) = resolve::<V>(document, path)? else {
+				if index > 0 {
+					return Err(format!("List index {index} is out of bounds"));
+				}
+				// Create the list in the parent.
+				let (parent_path, name) = path.rsplit_once('.').ok_or("Invalid path")?;
+				let steps = resolve_single::<V>(document, parent_path)?;
+				let Some(Value::Object(parent)) = value_at_mut(document, &steps) else {
+					return Err("Parent is not an element with children".to_owned());
+				};
+				parent.insert(name.to_owned(), Value::Array(vec![value.clone()]));
+				return Ok(());
+			};
+			let Some(Value::Array(list)) = value_at_mut(document, &steps) else {
+				return Err("Path does not match a list".to_owned());
+			};
+			if index > list.len() {
+				return Err(format!("List index {index} is out of bounds"));
+			}
+			list.insert(index, value.clone());
+			Ok(())
+		}
+		FhirPathPatchOperation::Delete { path } => {
+			// Deleting nothing is not an error.
+			let Some(steps) = resolve::<V>(document, path)? else { return Ok(()) };
+			if let Some(Value::Array(list)) = value_at_mut(document, &steps) {
+				if list.len() > 1 {
+					return Err("Path matches multiple elements".to_owned());
+				}
+			}
+			remove_at(document, &steps)
+		}
+		FhirPathPatchOperation::Replace { path, value, value_type } => {
+			let steps = resolve_single::<V>(document, path)?;
+			let element = element_at::<V>(path, None, value_type.as_deref())?;
+			// The type of choice elements can change and with it the key.
+			if let (Some((Step::Key(old_key), parent_steps)), Some(_)) =
+				(steps.split_last(), value_type)
+			{
+				let key = element.json_key().map_err(|error| error.to_string())?;
+				let Some(Value::Object(parent)) = value_at_mut(document, parent_steps) else {
+					return Err("Path matches no element".to_owned());
+				};
+				parent.remove(old_key);
+				parent.insert(key, value.clone());
+				return Ok(());
+			}
+			let target = value_at_mut(document, &steps).ok_or("Path matches no element")?;
+			*target = value.clone();
+			Ok(())
+		}
+		FhirPathPatchOperation::Move { path, source, destination } => {
+			let steps = resolve::<V>(document, path)?.ok_or("Path matches no element")?;
+			let Some(Value::Array(list)) = value_at_mut(document, &steps) else {
+				return Err("Path does not match a list".to_owned());
+			};
+			let source = usize::try_from(*source).ok().filter(|source| *source < list.len());
+			let destination =
+				usize::try_from(*destination).ok().filter(|destination| *destination < list.len());
+			let (Some(source), Some(destination)) = (source, destination) else {
+				return Err("List index is out of bounds".to_owned());
+			};
+			let value = list.remove(source);
+			list.insert(destination, value);
+			Ok(())
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+
+	use super::*;
+
+	#[test]
+	fn apply_json_patch() -> Result<(), PatchError> {
+		let resource = json!({
+			"resourceType": "Patient",
+			"meta": { "versionId": "1" },
+			"name": [{ "family": "Doe", "given": ["John"] }],
+			"active": true,
+		});
+		let operations = vec![
+			JsonPatchOperation::Test { path: "/meta/versionId".to_owned(), value: json!("1") },
+			JsonPatchOperation::Add { path: "/name/0/given/-".to_owned(), value: json!("Jim") },
+			JsonPatchOperation::Move { from: "/active".to_owned(), path: "/deceased".to_owned() },
+			JsonPatchOperation::Copy { from: "/name/0".to_owned(), path: "/name/1".to_owned() },
+			JsonPatchOperation::Replace { path: "/name/1/family".to_owned(), value: json!("Roe") },
+			JsonPatchOperation::Remove { path: "/meta".to_owned() },
+		];
+
+		let patched = apply(&resource, &operations)?;
+		assert_eq!(
+			patched,
+			json!({
+				"resourceType": "Patient",
+				"name": [
+					{ "family": "Doe", "given": ["John", "Jim"] },
+					{ "family": "Roe", "given": ["John", "Jim"] },
+				],
+				"deceased": true,
+			})
+		);
+
+		let failing = [
+			JsonPatchOperation::Remove { path: "/name/0/given/0".to_owned() },
+			JsonPatchOperation::Test { path: "/name/0/given/0".to_owned(), value: json!("John") },
+		];
+		let result = apply(&resource, &failing);
+		assert!(matches!(
+			result,
+			Err(PatchError::OperationFailed { index: 1, path, .. }) if path == "/name/0/given/0"
+		));
+		Ok(())
+	}
+
+	#[cfg(feature = "r5")]
+	#[test]
+	fn apply_fhirpath_patch() -> Result<(), PatchError> {
+		use crate::version::FhirR5;
+
+		let resource = json!({
+			"resourceType": "Patient",
+			"active": true,
+			"name": [{ "family": "Doe", "given": ["John", "Jim"] }],
+			"telecom": [{ "value": "1" }, { "value": "2" }],
+		});
+		let operations = vec![
+			FhirPathPatchOperation::Delete { path: "Patient.active".to_owned() },
+			FhirPathPatchOperation::Replace {
+				path: "Patient.name.family".to_owned(),
+				value: json!("Roe"),
+				value_type: None,
+			},
+			FhirPathPatchOperation::Move {
+				path: "Patient.name.given".to_owned(),
+				source: 1,
+				destination: 0,
+			},
+			FhirPathPatchOperation::Insert {
+				path: "Patient.telecom".to_owned(),
+				value: json!({ "value": "0" }),
+				value_type: None,
+				index: 0,
+			},
+			FhirPathPatchOperation::Delete { path: "Patient.telecom[2]".to_owned() },
+			FhirPathPatchOperation::Add {
+				path: "Patient".to_owned(),
+				name: "gender".to_owned(),
+				value: json!("female"),
+				value_type: None,
+			},
+		];
+
+		let patched = apply_fhirpath::<FhirR5, _>(&resource, &operations)?;
+		assert_eq!(
+			patched,
+			json!({
+				"resourceType": "Patient",
+				"gender": "female",
+				"name": [{ "family": "Roe", "given": ["Jim", "John"] }],
+				"telecom": [{ "value": "0" }, { "value": "1" }],
+			})
+		);
+
+		let failing = [FhirPathPatchOperation::Replace {
+			path: "Patient.telecom".to_owned(),
+			value: json!({ "value": "3" }),
+			value_type: None,
+		}];
+		assert!(matches!(
+			apply_fhirpath::<FhirR5, _>(&resource, &failing),
+			Err(PatchError::OperationFailed { index: 0, .. })
+		));
+		Ok(())
+	}
+
+	#[cfg(feature = "r5")]
+	#[test]
+	fn apply_fhirpath_parameters_to_typed_resource() -> anyhow::Result<()> {
+		use fhir_model::r5::resources::{Parameters, Patient};
+
+		use crate::version::FhirR5;
+
+		let patient: Patient =
+			serde_json::from_value(json!({ "resourceType": "Patient", "id": "1" }))?;
+		let parameters: Parameters = serde_json::from_value(json!({
+			"resourceType": "Parameters",
+			"parameter": [{
+				"name": "operation",
+				"part": [
+					{ "name": "type", "valueCode": "add" },
+					{ "name": "path", "valueString": "Patient" },
+					{ "name": "name", "valueString": "name" },
+					{
+						"name": "value",
+						"part": [
+							{ "name": "family", "valueString": "Doe" },
+							{ "name": "given", "valueString": "John" },
+							{ "name": "given", "valueString": "Jim" },
+						],
+					},
+				],
+			}],
+		}))?;
+
+		let operations = fhirpath_operations::<FhirR5, _>(&parameters)?;
+		let patched = apply_fhirpath::<FhirR5, _>(&patient, &operations)?;
+		assert_eq!(
+			serde_json::to_value(patched)?,
+			json!({
+				"resourceType": "Patient",
+				"id": "1",
+				"name": [{ "family": "Doe", "given": ["John", "Jim"] }],
+			})
+		);
+		Ok(())
+	}
+
+	#[cfg(feature = "r5")]
+	#[test]
+	fn apply_fhirpath_parameters_by_definitions() -> anyhow::Result<()> {
+		use fhir_model::r5::resources::{Parameters, Patient};
+
+		use crate::version::FhirR5;
+
+		let patient: Patient = serde_json::from_value(json!({
+			"resourceType": "Patient",
+			"id": "1",
+			"deceasedBoolean": false,
+		}))?;
+		let parameters: Parameters = serde_json::from_value(json!({
+			"resourceType": "Parameters",
+			"parameter": [
+				{
+					"name": "operation",
+					"part": [
+						{ "name": "type", "valueCode": "add" },
+						{ "name": "path", "valueString": "Patient" },
+						{ "name": "name", "valueString": "name" },
+						{
+							"name": "value",
+							"part": [{ "name": "given", "valueString": "John" }],
+						},
+					],
+				},
+				{
+					"name": "operation",
+					"part": [
+						{ "name": "type", "valueCode": "add" },
+						{ "name": "path", "valueString": "Patient" },
+						{ "name": "name", "valueString": "contact" },
+						{
+							"name": "value",
+							"part": [{
+								"name": "name",
+								"part": [{ "name": "given", "valueString": "Jim" }],
+							}],
+						},
+					],
+				},
+				{
+					"name": "operation",
+					"part": [
+						{ "name": "type", "valueCode": "replace" },
+						{ "name": "path", "valueString": "Patient.deceased" },
+						{ "name": "value", "valueDateTime": "2020-01-01" },
+					],
+				},
+			],
+		}))?;
+
+		let operations = fhirpath_operations::<FhirR5, _>(&parameters)?;
+		let patched = apply_fhirpath::<FhirR5, _>(&patient, &operations)?;
+		assert_eq!(
+			serde_json::to_value(patched)?,
+			json!({
+				"resourceType": "Patient",
+				"id": "1",
+				"deceasedDateTime": "2020-01-01",
+				"name": [{ "given": ["John"] }],
+				"contact": [{ "name": { "given": ["Jim"] } }],
+			})
+		);
+		Ok(())
+	}
+}
@@ -0,0 +1,387 @@
+//! Generation of patches by diffing resources.
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use super::{element::Element, FhirPathPatchOperation, JsonPatchOperation, PatchError};
+use crate::version::FhirVersion;
+
+/// Compute the `JSON Patch` operations that turn `old` into `new`, comparing
+/// their JSON form. Lists are compared element by element after skipping the
+/// common start and end, so single insertions and removals stay small.
+pub fn diff<R: Serialize>(old: &R, new: &R) -> Result<Vec<JsonPatchOperation>, serde_json::Error> {
+	let old = serde_json::to_value(old)?;
+	let new = serde_json::to_value(new)?;
+	let mut operations = Vec::new();
+	diff_json(&old, &new, "", &mut operations);
+	Ok(operations)
+}
+
+/// Compute the `FHIRPath Patch` operations that turn `old` into `new`,
+/// comparing their JSON form. Elements are resolved via the element definitions
+/// of the FHIR version, so choice elements get their FHIRPath name, e.g.
+/// `Observation.value` for `valueQuantity`, and are replaced as a whole with
+/// their value's type. Changes of primitive extensions, e.g. `_birthDate`,
+/// cannot be expressed and fail.
+pub fn diff_fhirpath<V: FhirVersion, R: Serialize>(
+	old: &R,
+	new: &R,
+) -> Result<Vec<FhirPathPatchOperation>, PatchError> {
+	let old = serde_json::to_value(old)?;
+	let new = serde_json::to_value(new)?;
+	let resource_type = new.get("resourceType").and_then(Value::as_str).unwrap_or_default();
+	if old.get("resourceType") != new.get("resourceType") {
+		return Err(PatchError::Unsupported("changing the resource type".to_owned()));
+	}
+	let mut operations = Vec::new();
+	diff_fhirpath_json::<V>(
+		&old,
+		&new,
+		resource_type,
+		&Element::root(resource_type),
+		&mut operations,
+	)?;
+	Ok(operations)
+}
+
+/// Escape a key for use in a JSON pointer.
+fn escape_pointer(key: &str) -> String {
+	key.replace('~', "~0").replace('/', "~1")
+}
+
+/// Length of the common start and common end of the lists, not overlapping.
+pub(crate) fn common_bounds(old: &[Value], new: &[Value]) -> (usize, usize) {
+	let prefix = old.iter().zip(new).take_while(|(old, new)| old == new).count();
+	let max_suffix = old.len().min(new.len()) - prefix;
+	let suffix = old
+		.iter()
+		.rev()
+		.zip(new.iter().rev())
+		.take(max_suffix)
+		.take_while(|(old, new)| old == new)
+		.count();
+	(prefix, suffix)
+}
+
+/// Recursively collect the `JSON Patch` operations for the JSON values.
+fn diff_json(old: &Value, new: &Value, pointer: &str, operations: &mut Vec<JsonPatchOperation>) {
+	if old == new {
+		return;
+	}
+
+	match (old, new) {
+		(Value::Object(old), Value::Object(new)) => {
+			for (key, old_value) in old {
+				let path = format!("{pointer}/{}", escape_pointer(key));
+				match new.get(key) {
+					Some(new_value) => diff_json(old_value, new_value, &path, operations),
+					None => operations.push(JsonPatchOperation::Remove { path }),
+				}
+			}
+			for (key, new_value) in new {
+				if !old.contains_key(key) {
+					let path = format!("{pointer}/{}", escape_pointer(key));
+					operations.push(JsonPatchOperation::Add { path, value: new_value.clone() });
+				}
+			}
+		}
+		(Value::Array(old), Value::Array(new)) => {
+			let (prefix, suffix) = common_bounds(old, new);
+			let old_changed = old.iter().skip(prefix).take(old.len() - prefix - suffix);
+			let new_changed: Vec<_> =
+				new.iter().skip(prefix).take(new.len() - prefix - suffix).collect();
+
+			let mut index = prefix;
+			let mut removed = Vec::new();
+			for (position, old_value) in old_changed.enumerate() {
+				match new_changed.get(position) {
+					Some(new_value) => {
+						diff_json(old_value, new_value, &format!("{pointer}/{index}"), operations);
+					}
+					None => removed.push(format!("{pointer}/{index}")),
+				}
+				index += 1;
+			}
+			// Remove from the back to keep the indices valid.
+			for path in removed.into_iter().rev() {
+				operations.push(JsonPatchOperation::Remove { path });
+			}
+
+			let old_changed_len = old.len() - prefix - suffix;
+			for (offset, new_value) in new_changed.into_iter().enumerate().skip(old_changed_len) {
+				let path = format!("{pointer}/{}", prefix + offset);
+				operations.push(JsonPatchOperation::Add { path, value: new_value.clone() });
+			}
+		}
+		_ => operations
+			.push(JsonPatchOperation::Replace { path: pointer.to_owned(), value: new.clone() }),
+	}
+}
+
+/// Recursively collect the `FHIRPath Patch` operations for the JSON values of
+/// the element.
+fn diff_fhirpath_json<V: FhirVersion>(
+	old: &Value,
+	new: &Value,
+	path: &str,
+	element: &Element,
+	operations: &mut Vec<FhirPathPatchOperation>,
+) -> Result<(), PatchError> {
+	if old == new {
+		return Ok(());
+	}
+
+	match (old, new) {
+		// Resources, e.g. `contained`, are replaced as a whole.
+		(Value::Object(old), Value::Object(new)) if !element.is_resource() => {
+			diff_fhirpath_object::<V>(old, new, path, element, operations)?;
+		}
+		(Value::Array(old), Value::Array(new)) => {
+			let (prefix, suffix) = common_bounds(old, new);
+			let old_changed_len = old.len() - prefix - suffix;
+			let new_changed: Vec<_> =
+				new.iter().skip(prefix).take(new.len() - prefix - suffix).collect();
+
+			for (position, old_value) in old.iter().skip(prefix).take(old_changed_len).enumerate() {
+				if let Some(new_value) = new_changed.get(position) {
+					let element_path = format!("{path}[{}]", prefix + position);
+					diff_fhirpath_json::<V>(
+						old_value,
+						new_value,
+						&element_path,
+						element,
+						operations,
+					)?;
+				}
+			}
+			if old_changed_len > new_changed.len() {
+				delete_list_elements(
+					path,
+					prefix + new_changed.len(),
+					prefix + old_changed_len,
+					operations,
+				);
+			}
+			for (offset, new_value) in new_changed.into_iter().enumerate().skip(old_changed_len) {
+				operations.push(FhirPathPatchOperation::Insert {
+					path: path.to_owned(),
+					value: new_value.clone(),
+					value_type: None,
+					index: i32::try_from(prefix + offset).unwrap_or(i32::MAX),
+				});
+			}
+		}
+		_ => operations.push(FhirPathPatchOperation::Replace {
+			path: path.to_owned(),
+			value: new.clone(),
+			value_type: element.choice_type(),
+		}),
+	}
+	Ok(())
+}
+
+/// Collect the `FHIRPath Patch` operations for the changed elements of the
+/// object. Choice elements are replaced as a whole, as paths into them would
+/// need their type.
+fn diff_fhirpath_object<V: FhirVersion>(
+	old: &Map<String, Value>,
+	new: &Map<String, Value>,
+	path: &str,
+	element: &Element,
+	operations: &mut Vec<FhirPathPatchOperation>,
+) -> Result<(), PatchError> {
+	let old_changed = changed_elements::<V>(element, old, new)?;
+	let new_changed = changed_elements::<V>(element, new, old)?;
+
+	for (name, child, old_value) in &old_changed {
+		let element_path = format!("{path}.{name}");
+		let new_entry = new_changed.iter().find(|(new_name, ..)| new_name == name);
+		match new_entry {
+			Some((_, new_child, new_value)) if child.is_choice() => {
+				operations.push(FhirPathPatchOperation::Replace {
+					path: element_path,
+					value: (*new_value).clone(),
+					value_type: new_child.choice_type(),
+				});
+			}
+			Some((_, _, new_value)) if old_value.is_array() == new_value.is_array() => {
+				diff_fhirpath_json::<V>(old_value, new_value, &element_path, child, operations)?;
+			}
+			_ => {
+				match old_value {
+					Value::Array(list) => {
+						delete_list_elements(&element_path, 0, list.len(), operations);
+					}
+					_ => operations.push(FhirPathPatchOperation::Delete { path: element_path }),
+				}
+				if let Some((_, new_child, new_value)) = new_entry {
+					add_element(path, name, new_child, new_value, operations);
+				}
+			}
+		}
+	}
+	for (name, child, new_value) in &new_changed {
+		if !old_changed.iter().any(|(old_name, ..)| old_name == name) {
+			add_element(path, name, child, new_value, operations);
+		}
+	}
+	Ok(())
+}
+
+/// Resolve the elements of `from` that are missing or different in `to`,
+/// returning their FHIRPath names, elements and values.
+fn changed_elements<'a, V: FhirVersion>(
+	element: &Element,
+	from: &'a Map<String, Value>,
+	to: &Map<String, Value>,
+) -> Result<Vec<(String, Element, &'a Value)>, PatchError> {
+	from.iter()
+		.filter(|(key, value)| {
+			key.as_str() != "resourceType" && to.get(key.as_str()) != Some(value)
+		})
+		.map(|(key, value)| {
+			let (name, child) = element.json_child::<V>(key)?;
+			Ok((name, child, value))
+		})
+		.collect()
+}
+
+/// Add the `FHIRPath Patch` operations to add the element to the parent. Lists
+/// are added element by element.
+fn add_element(
+	parent: &str,
+	name: &str,
+	element: &Element,
+	value: &Value,
+	operations: &mut Vec<FhirPathPatchOperation>,
+) {
+	let values = match value {
+		Value::Array(values) => values.as_slice(),
+		value => std::slice::from_ref(value),
+	};
+	for value in values {
+		operations.push(FhirPathPatchOperation::Add {
+			path: parent.to_owned(),
+			name: name.to_owned(),
+			value: value.clone(),
+			value_type: element.choice_type(),
+		});
+	}
+}
+
+/// Add the `FHIRPath Patch` operations to delete the list elements in the
+/// range, from the back to keep the indices valid.
+fn delete_list_elements(
+	path: &str,
+	start: usize,
+	end: usize,
+	operations: &mut Vec<FhirPathPatchOperation>,
+) {
+	for index in (start .. end).rev() {
+		operations.push(FhirPathPatchOperation::Delete { path: format!("{path}[{index}]") });
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+
+	use super::*;
+
+	#[test]
+	fn json_patch_diff() -> Result<(), serde_json::Error> {
+		let old = json!({
+			"resourceType": "Patient",
+			"id": "1",
+			"active": true,
+			"gender": "male",
+			"name": [{ "family": "Doe", "given": ["John", "Jim"] }],
+			"telecom": [{ "value": "1" }, { "value": "2" }, { "value": "3" }],
+		});
+		let new = json!({
+			"resourceType": "Patient",
+			"id": "1",
+			"gender": "female",
+			"birthDate": "2000-01-01",
+			"name": [{ "family": "Doe", "given": ["Jane", "Jim", "J/~"] }],
+			"telecom": [{ "value": "1" }, { "value": "3" }],
+		});
+
+		let operations = diff(&old, &new)?;
+		assert_eq!(
+			serde_json::to_value(operations)?,
+			json!([
+				{ "op": "remove", "path": "/active" },
+				{ "op": "replace", "path": "/gender", "value": "female" },
+				{ "op": "replace", "path": "/name/0/given/0", "value": "Jane" },
+				{ "op": "add", "path": "/name/0/given/2", "value": "J/~" },
+				{ "op": "remove", "path": "/telecom/1" },
+				{ "op": "add", "path": "/birthDate", "value": "2000-01-01" },
+			])
+		);
+		assert!(diff(&new, &new)?.is_empty());
+		Ok(())
+	}
+
+	#[cfg(feature = "r5")]
+	#[test]
+	fn fhirpath_patch_diff() -> Result<(), PatchError> {
+		use crate::version::FhirR5;
+
+		let old = json!({
+			"resourceType": "Patient",
+			"active": true,
+			"deceasedBoolean": false,
+			"name": [{ "family": "Doe", "given": ["John"] }],
+			"telecom": [{ "value": "1" }, { "value": "2" }],
+		});
+		let new = json!({
+			"resourceType": "Patient",
+			"deceasedDateTime": "2020-01-01",
+			"gender": "female",
+			"name": [{ "family": "Roe", "given": ["John", "Jim"] }],
+			"telecom": [{ "value": "2" }],
+		});
+
+		let operations = diff_fhirpath::<FhirR5, _>(&old, &new)?;
+		assert_eq!(
+			operations,
+			vec![
+				FhirPathPatchOperation::Delete { path: "Patient.active".to_owned() },
+				FhirPathPatchOperation::Replace {
+					path: "Patient.deceased".to_owned(),
+					value: json!("2020-01-01"),
+					value_type: Some("dateTime".to_owned()),
+				},
+				FhirPathPatchOperation::Replace {
+					path: "Patient.name[0].family".to_owned(),
+					value: json!("Roe"),
+					value_type: None,
+				},
+				FhirPathPatchOperation::Insert {
+					path: "Patient.name[0].given".to_owned(),
+					value: json!("Jim"),
+					value_type: None,
+					index: 1,
+				},
+				FhirPathPatchOperation::Delete { path: "Patient.telecom[0]".to_owned() },
+				FhirPathPatchOperation::Add {
+					path: "Patient".to_owned(),
+					name: "gender".to_owned(),
+					value: json!("female"),
+					value_type: None,
+				},
+			]
+		);
+
+		let extended = json!({
+			"resourceType": "Patient",
+			"_gender": { "extension": [{ "url": "http://example.com", "valueString": "x" }] },
+		});
+		assert!(matches!(
+			diff_fhirpath::<FhirR5, _>(&old, &extended),
+			Err(PatchError::Unsupported(_))
+		));
+		Ok(())
+	}
+}
@@ -0,0 +1,226 @@
+//! Resolution of elements via the element definitions of the FHIR version.
+
+use fhir_model::ElementDefinition;
+use serde_json::Value;
+
+use super::PatchError;
+use crate::version::FhirVersion;
+
+/// Types that cannot be the `value[x]` of a `Parameters` parameter, so values
+/// of them are given as `part`s instead.
+#[cfg(feature = "client")]
+const PART_TYPES: &[&str] = &["BackboneElement", "Element", "Extension", "Narrative"];
+
+/// Element of a resource, resolved via its definition.
+#[derive(Debug, Clone)]
+pub(crate) struct Element {
+	/// Path of the element, for error messages.
+	path: String,
+	/// Definition of the element, `None` for the resource itself.
+	definition: Option<ElementDefinition>,
+	/// Type of the element, `None` for choice elements if the type is not
+	/// known.
+	r#type: Option<String>,
+}
+
+impl Element {
+	/// The resource itself, as root of the paths.
+	pub(crate) fn root(resource_type: &str) -> Self {
+		Self {
+			path: resource_type.to_owned(),
+			definition: None,
+			r#type: Some(resource_type.to_owned()),
+		}
+	}
+
+	/// Resolve the simple FHIRPath of element names with optional list indices,
+	/// e.g. `Patient.name[0].given`.
+	pub(crate) fn resolve<V: FhirVersion>(path: &str) -> Result<Self, PatchError> {
+		let mut segments = path.split('.');
+		let mut element = Self::root(segments.next().unwrap_or_default());
+		for segment in segments {
+			let name = segment.split_once('[').map_or(segment, |(name, _)| name);
+			element = element.child::<V>(name)?;
+		}
+		Ok(element)
+	}
+
+	/// Get the child element by its FHIRPath name, e.g. `value` for
+	/// `value[x]`.
+	pub(crate) fn child<V: FhirVersion>(&self, name: &str) -> Result<Self, PatchError> {
+		let children = self.children()?;
+		let path = format!("{children}.{name}");
+		if let Some(definition) = V::element_definition(&path) {
+			return Ok(Self::new(path, definition));
+		}
+		let choice_path = format!("{path}[x]");
+		V::element_definition(&choice_path)
+			.map(|definition| Self::new(choice_path, definition))
+			.ok_or(PatchError::UnknownElement(path))
+	}
+
+	/// Get the child element by its key in the JSON form, e.g. `valueQuantity`.
+	/// Returns the FHIRPath name of the child as well, e.g. `value`.
+	pub(crate) fn json_child<V: FhirVersion>(
+		&self,
+		key: &str,
+	) -> Result<(String, Self), PatchError> {
+		if key.starts_with('_') {
+			return Err(PatchError::Unsupported(format!(
+				"extensions of primitive elements (`{}.{key}`)",
+				self.path
+			)));
+		}
+
+		let children = self.children()?;
+		let path = format!("{children}.{key}");
+		if let Some(definition) = V::element_definition(&path) {
+			return Ok((key.to_owned(), Self::new(path, definition)));
+		}
+
+		// Choice elements are named after their type, e.g. `valueQuantity`.
+		for (index, _) in key.char_indices().filter(|(_, c)| c.is_ascii_uppercase()) {
+			let (name, type_suffix) = key.split_at(index);
+			let choice_path = format!("{children}.{name}[x]");
+			let Some(definition) = V::element_definition(&choice_path) else { continue };
+			if let Some(r#type) =
+				definition.types.iter().find(|r#type| value_type_suffix(r#type) == type_suffix)
+			{
+				let mut element = Self::new(choice_path, definition);
+				element.r#type = Some((*r#type).to_owned());
+				return Ok((name.to_owned(), element));
+			}
+		}
+		Err(PatchError::UnknownElement(path))
+	}
+
+	/// Set the type of a choice element. Other elements must already have the
+	/// given type.
+	pub(crate) fn with_type(mut self, value_type: Option<&str>) -> Result<Self, PatchError> {
+		let Some(value_type) = value_type else { return Ok(self) };
+		let types = self.definition.map_or(&[][..], |definition| definition.types);
+		let Some(r#type) = types.iter().find(|r#type| r#type.eq_ignore_ascii_case(value_type))
+		else {
+			return Err(PatchError::Unsupported(format!(
+				"type `{value_type}` for `{}`, expected one of {types:?}",
+				self.path
+			)));
+		};
+		self.r#type = Some((*r#type).to_owned());
+		Ok(self)
+	}
+
+	/// Make the element from its definition.
+	fn new(path: String, definition: ElementDefinition) -> Self {
+		let r#type = if definition.content_reference.is_some() {
+			Some("BackboneElement".to_owned())
+		} else if definition.is_choice() {
+			None
+		} else {
+			definition.types.first().map(|r#type| (*r#type).to_owned())
+		};
+		Self { path, definition: Some(definition), r#type }
+	}
+
+	/// Path of the definitions of the children, i.e. the type for complex data
+	/// types and the path of the definition for backbone elements.
+	fn children(&self) -> Result<&str, PatchError> {
+		if let Some(reference) = self.definition.and_then(|definition| definition.content_reference)
+		{
+			return Ok(reference);
+		}
+		match self.r#type.as_deref() {
+			Some("BackboneElement" | "Element") => Ok(&self.path),
+			Some(r#type) => Ok(r#type),
+			None => Err(PatchError::Unsupported(format!(
+				"paths into choice elements of unknown type (`{}`)",
+				self.path
+			))),
+		}
+	}
+
+	/// Path of the element.
+	pub(crate) fn path(&self) -> &str {
+		&self.path
+	}
+
+	/// Type of the element, `None` for choice elements if the type is not
+	/// known.
+	#[cfg(feature = "client")] // Only needed to build `Parameters`.
+	pub(crate) fn r#type(&self) -> Option<&str> {
+		self.r#type.as_deref()
+	}
+
+	/// Type of the element's value for patch operations, if it is a choice
+	/// element.
+	pub(crate) fn choice_type(&self) -> Option<String> {
+		self.r#type.clone().filter(|_| self.is_choice())
+	}
+
+	/// Name of the element, e.g. `value` for `Observation.value[x]`.
+	fn name(&self) -> &str {
+		let name = self.path.rsplit('.').next().unwrap_or_default();
+		name.strip_suffix("[x]").unwrap_or(name)
+	}
+
+	/// Key of the element in the JSON form, e.g. `valueQuantity` for
+	/// `Observation.value[x]` of type `Quantity`.
+	pub(crate) fn json_key(&self) -> Result<String, PatchError> {
+		if !self.is_choice() {
+			return Ok(self.name().to_owned());
+		}
+		let r#type = self.r#type.as_deref().ok_or_else(|| {
+			PatchError::Unsupported(format!("choice elements of unknown type (`{}`)", self.path))
+		})?;
+		Ok(format!("{}{}", self.name(), value_type_suffix(r#type)))
+	}
+
+	/// Find the element in the JSON object of its parent, setting the type of
+	/// choice elements from the key. Returns the key and the value.
+	pub(crate) fn find_in<'v>(&mut self, parent: &'v Value) -> Option<(String, &'v Value)> {
+		if !self.is_choice() {
+			let key = self.name().to_owned();
+			return parent.get(&key).map(|value| (key, value));
+		}
+		let types = self.definition.map_or(&[][..], |definition| definition.types);
+		for r#type in types {
+			let key = format!("{}{}", self.name(), value_type_suffix(r#type));
+			if let Some(value) = parent.get(&key) {
+				self.r#type = Some((*r#type).to_owned());
+				return Some((key, value));
+			}
+		}
+		None
+	}
+
+	/// Whether the element is a list.
+	pub(crate) fn is_array(&self) -> bool {
+		self.definition.is_some_and(|definition| definition.is_array)
+	}
+
+	/// Whether the element is a choice element like `value[x]`.
+	pub(crate) fn is_choice(&self) -> bool {
+		self.definition.is_some_and(|definition| definition.is_choice())
+	}
+
+	/// Whether values of the element are given as `part`s instead of a
+	/// `value[x]` in `Parameters`.
+	#[cfg(feature = "client")] // Only needed to build `Parameters`.
+	pub(crate) fn uses_parts(&self) -> bool {
+		self.r#type.as_deref().is_some_and(|r#type| PART_TYPES.contains(&r#type))
+	}
+
+	/// Whether the element holds a whole resource, e.g. `contained`.
+	pub(crate) fn is_resource(&self) -> bool {
+		self.r#type.as_deref() == Some("Resource")
+	}
+}
+
+/// Suffix of `value[x]` for the type, e.g. `DateTime` for `dateTime`.
+pub(crate) fn value_type_suffix(r#type: &str) -> String {
+	let mut chars = r#type.chars();
+	chars
+		.next()
+		.map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+		.unwrap_or_default()
+}
@@ -0,0 +1,34 @@
+//! Patch errors.
+
+use thiserror::Error;
+
+/// Error when generating or locally applying a patch.
+#[derive(Debug, Error)]
+pub enum PatchError {
+	/// Serialization/Deserialization error.
+	#[error("JSON error: {0}")]
+	Json(#[from] serde_json::Error),
+
+	/// The `FHIRPath Patch` parameters are malformed.
+	#[error("Invalid FHIRPath Patch parameters: {0}")]
+	InvalidParameters(String),
+
+	/// The element is not defined in the FHIR version.
+	#[error("Unknown element `{0}`")]
+	UnknownElement(String),
+
+	/// The change cannot be expressed as `FHIRPath Patch`.
+	#[error("Not supported in FHIRPath Patch: {0}")]
+	Unsupported(String),
+
+	/// A patch operation could not be applied.
+	#[error("Patch operation {index} on `{path}` failed: {reason}")]
+	OperationFailed {
+		/// Index of the operation in the patch.
+		index: usize,
+		/// Path of the operation.
+		path: String,
+		/// Why the operation failed.
+		reason: String,
+	},
+}
@@ -0,0 +1,145 @@
+//! [`JSON Patch`](https://datatracker.ietf.org/doc/html/rfc6902) and
+//! [`FHIRPath Patch`](https://hl7.org/fhir/fhirpatch.html) operations, their
+//! generation by diffing resources and their local application.
+
+mod apply;
+mod diff;
+pub(crate) mod element;
+mod error;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub(crate) use self::{apply::value_at, diff::common_bounds};
+pub use self::{
+	apply::{apply, apply_fhirpath, fhirpath_operations},
+	diff::{diff, diff_fhirpath},
+	error::PatchError,
+};
+
+/// A `JSON Patch` operation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum JsonPatchOperation {
+	/// Add the value at the path.
+	Add {
+		/// JSON pointer to the target location.
+		path: String,
+		/// Value to add.
+		value: Value,
+	},
+	/// Remove the value at the path.
+	Remove {
+		/// JSON pointer to the target location.
+		path: String,
+	},
+	/// Replace the value at the path.
+	Replace {
+		/// JSON pointer to the target location.
+		path: String,
+		/// New value.
+		value: Value,
+	},
+	/// Move the value from one path to another.
+	Move {
+		/// JSON pointer to the source location.
+		from: String,
+		/// JSON pointer to the target location.
+		path: String,
+	},
+	/// Copy the value from one path to another.
+	Copy {
+		/// JSON pointer to the source location.
+		from: String,
+		/// JSON pointer to the target location.
+		path: String,
+	},
+	/// Test that the value at the path equals the given value.
+	Test {
+		/// JSON pointer to the target location.
+		path: String,
+		/// Expected value.
+		value: Value,
+	},
+}
+
+/// A `FHIRPath Patch` operation. Values are given in their FHIR JSON form. The
+/// value type is only needed for choice elements like `value[x]`, e.g.
+/// `Quantity`, otherwise it is taken from the element definition.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FhirPathPatchOperation {
+	/// Add the element `name` with the value to the element at the path.
+	Add {
+		/// FHIRPath to the parent element.
+		path: String,
+		/// Name of the element to add.
+		name: String,
+		/// Value to add.
+		value: Value,
+		/// Type of the value, for choice elements.
+		value_type: Option<String>,
+	},
+	/// Insert the value into the list at the path at the index.
+	Insert {
+		/// FHIRPath to the list.
+		path: String,
+		/// Value to insert.
+		value: Value,
+		/// Type of the value, for choice elements.
+		value_type: Option<String>,
+		/// Index to insert the value at.
+		index: i32,
+	},
+	/// Delete the element at the path.
+	Delete {
+		/// FHIRPath to the element.
+		path: String,
+	},
+	/// Replace the element at the path with the value.
+	Replace {
+		/// FHIRPath to the element.
+		path: String,
+		/// New value.
+		value: Value,
+		/// Type of the value, for choice elements.
+		value_type: Option<String>,
+	},
+	/// Move an element inside the list at the path.
+	Move {
+		/// FHIRPath to the list.
+		path: String,
+		/// Index of the element to move.
+		source: i32,
+		/// Index to move the element to.
+		destination: i32,
+	},
+}
+
+impl JsonPatchOperation {
+	/// The JSON pointer to the target location of the operation.
+	#[must_use]
+	pub fn path(&self) -> &str {
+		match self {
+			Self::Add { path, .. }
+			| Self::Remove { path }
+			| Self::Replace { path, .. }
+			| Self::Move { path, .. }
+			| Self::Copy { path, .. }
+			| Self::Test { path, .. } => path,
+		}
+	}
+}
+
+impl FhirPathPatchOperation {
+	/// The FHIRPath the operation applies to.
+	#[must_use]
+	pub fn path(&self) -> &str {
+		match self {
+			Self::Add { path, .. }
+			| Self::Insert { path, .. }
+			| Self::Delete { path }
+			| Self::Replace { path, .. }
+			| Self::Move { path, .. } => path,
+		}
+	}
+}
@@ -0,0 +1,42 @@
+//! Adapter to receive notifications in an [axum] server.
+
+use std::sync::Arc;
+
+use ::axum::{
+	body::Bytes,
+	http::HeaderMap,
+	response::{IntoResponse, Response},
+	routing::post,
+	Router,
+};
+
+use super::{NotificationCallback, NotificationReceiver, ReceiveError};
+use crate::version::SubscriptionVersion;
+
+impl<C, V> NotificationReceiver<C, V>
+where
+	V: SubscriptionVersion,
+	C: NotificationCallback<V> + 'static,
+{
+	/// Create an axum `Router` that receives notifications via `POST` requests on
+	/// the given path. Merge or nest it into your application's router.
+	pub fn into_router<S>(self, path: &str) -> Router<S>
+	where
+		S: Clone + Send + Sync + 'static,
+	{
+		let receiver = Arc::new(self);
+		Router::new().route(
+			path,
+			post(move |headers: HeaderMap, body: Bytes| async move {
+				receiver.receive(&headers, &body).await
+			}),
+		)
+	}
+}
+
+impl IntoResponse for ReceiveError {
+	fn into_response(self) -> Response {
+		tracing::warn!("Rejecting subscription notification: {self}");
+		(self.status_code(), self.to_string()).into_response()
+	}
+}
@@ -0,0 +1,42 @@
+//! Notification receiver errors.
+
+use http::StatusCode;
+use thiserror::Error;
+
+/// Error when receiving a subscription notification.
+#[derive(Debug, Error)]
+pub enum ReceiveError {
+	/// The configured header secret was missing or wrong.
+	#[error("Missing or wrong header secret")]
+	Unauthorized,
+
+	/// Serialization/Deserialization error.
+	#[error("JSON error: {0}")]
+	Json(#[from] serde_json::Error),
+
+	/// The `Bundle` is not a valid subscription notification.
+	#[error("Invalid notification: {0}")]
+	InvalidNotification(&'static str),
+
+	/// The notification belongs to a different subscription than configured.
+	#[error("Notification for unexpected subscription: {0}")]
+	UnexpectedSubscription(String),
+
+	/// Notification callback error.
+	#[error("Notification callback error: {0}")]
+	Callback(String),
+}
+
+impl ReceiveError {
+	/// The HTTP status code to respond with to the FHIR server.
+	#[must_use]
+	pub const fn status_code(&self) -> StatusCode {
+		match self {
+			Self::Unauthorized => StatusCode::UNAUTHORIZED,
+			Self::Json(_) | Self::InvalidNotification(_) | Self::UnexpectedSubscription(_) => {
+				StatusCode::BAD_REQUEST
+			}
+			Self::Callback(_) => StatusCode::INTERNAL_SERVER_ERROR,
+		}
+	}
+}
@@ -0,0 +1,14 @@
+//! Server-side helpers, e.g. for receiving rest-hook subscription notifications.
+
+#[cfg(feature = "axum")]
+mod axum;
+mod error;
+mod rest_hook;
+
+pub use self::{
+	error::ReceiveError,
+	rest_hook::{Notification, NotificationCallback, NotificationReceiver},
+};
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,195 @@
+//! Receiver for rest-hook subscription notifications.
+
+use std::{future::Future, marker::PhantomData};
+
+use http::{HeaderMap, HeaderName, HeaderValue};
+
+use super::ReceiveError;
+use crate::{
+	extensions::{BundleEntryExt, BundleExt, SubscriptionStatusExt},
+	version::{DefaultVersion, SubscriptionVersion},
+};
+
+/// Error type of the notification callback.
+type CallbackError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A decoded subscription notification.
+#[derive(Debug, Clone)]
+pub struct Notification<V: SubscriptionVersion> {
+	/// The `SubscriptionStatus` from the first entry of the notification.
+	pub status: V::SubscriptionStatus,
+	/// The full notification `Bundle`, including the status entry.
+	pub bundle: V::Bundle,
+}
+
+impl<V: SubscriptionVersion> Notification<V> {
+	/// Whether this is a `handshake` notification.
+	#[must_use]
+	pub fn is_handshake(&self) -> bool {
+		self.status.is_handshake()
+	}
+
+	/// Whether this is a `heartbeat` notification.
+	#[must_use]
+	pub fn is_heartbeat(&self) -> bool {
+		self.status.is_heartbeat()
+	}
+
+	/// Get the resources contained in the notification, i.e. all entries except
+	/// the `SubscriptionStatus`.
+	pub fn resources(&self) -> impl Iterator<Item = &V::Resource> + Send {
+		self.bundle.entries().skip(1).filter_map(BundleEntryExt::resource)
+	}
+}
+
+/// Trait for handling received notifications in the [NotificationReceiver].
+/// You can implement the function as `async fn`, no need for `impl Future`.
+///
+/// It is automatically implemented for async functions and closures:
+/// - Async functions `async fn my_callback(notification: Notification<V>) -> Result<(), MyError>`
+/// - Async closures `|notification: Notification<V>| async move { ... }`
+pub trait NotificationCallback<V: SubscriptionVersion>: Send + Sync {
+	/// Error this callback returns. Must be convertible to a `Box<dyn Error +
+	/// Send + Sync>`.
+	type Error: Into<CallbackError>;
+
+	/// This method is called for every valid notification, including handshakes
+	/// and heartbeats. Returning an error makes the receiver respond with an
+	/// internal server error.
+	fn handle(
+		&self,
+		notification: Notification<V>,
+	) -> impl Future<Output = Result<(), Self::Error>> + Send;
+}
+
+impl<V, F, Fut, E> NotificationCallback<V> for F
+where
+	V: SubscriptionVersion,
+	F: Fn(Notification<V>) -> Fut + Send + Sync,
+	Fut: Future<Output = Result<(), E>> + Send,
+	E: Into<CallbackError>,
+{
+	type Error = E;
+
+	async fn handle(&self, notification: Notification<V>) -> Result<(), Self::Error> {
+		(self)(notification).await
+	}
+}
+
+/// Framework-agnostic receiver for rest-hook subscription notifications. It
+/// validates the notification `Bundle`s and passes them on to the callback.
+///
+/// Use [Self::receive] with the headers and body of the incoming request from
+/// any HTTP server (e.g. hyper) and respond with [ReceiveError::status_code] on
+/// errors. With the `axum` feature, there is a ready-made router as well.
+pub struct NotificationReceiver<C, Version = DefaultVersion> {
+	/// Callback to pass the notifications to.
+	callback: C,
+	/// Header that needs to be present with the given value, as configured in
+	/// the `Subscription`.
+	header_secret: Option<(HeaderName, HeaderValue)>,
+	/// Subscription the notifications must belong to.
+	subscription: Option<String>,
+
+	/// FHIR version.
+	version: PhantomData<Version>,
+}
+
+impl<C, V> NotificationReceiver<C, V>
+where
+	V: SubscriptionVersion,
+	C: NotificationCallback<V>,
+{
+	/// Create a new receiver passing notifications to the given callback.
+	#[must_use]
+	pub const fn new(callback: C) -> Self {
+		Self { callback, header_secret: None, subscription: None, version: PhantomData }
+	}
+
+	/// Require a header with the given value on every notification, e.g. as set
+	/// in `Subscription.header` (R5) or `Subscription.channel.header` (R4B).
+	/// Requests without it are rejected as unauthorized.
+	#[must_use]
+	pub fn header_secret(mut self, name: HeaderName, value: HeaderValue) -> Self {
+		self.header_secret = Some((name, value));
+		self
+	}
+
+	/// Only accept notifications for the given subscription, e.g.
+	/// `Subscription/123`. Absolute references are accepted as well, as long as
+	/// they end with the given reference.
+	#[must_use]
+	pub fn subscription(mut self, reference: impl Into<String>) -> Self {
+		self.subscription = Some(reference.into());
+		self
+	}
+
+	/// Validate and decode a received notification and pass it to the callback.
+	pub async fn receive(&self, headers: &HeaderMap, body: &[u8]) -> Result<(), ReceiveError> {
+		let notification = self.decode(headers, body)?;
+		tracing::debug!("Received valid subscription notification, calling callback");
+		self.callback.handle(notification).await.map_err(|err| {
+			let err: CallbackError = err.into();
+			ReceiveError::Callback(format!("{err:#}"))
+		})
+	}
+
+	/// Validate and decode a received notification.
+	fn decode(&self, headers: &HeaderMap, body: &[u8]) -> Result<Notification<V>, ReceiveError> {
+		if let Some((name, value)) = &self.header_secret {
+			let received = headers.get(name).map_or(&[][..], HeaderValue::as_bytes);
+			if !constant_time_eq(received, value.as_bytes()) {
+				return Err(ReceiveError::Unauthorized);
+			}
+		}
+
+		let bundle: V::Bundle = serde_json::from_slice(body)?;
+		if !bundle.is_subscription_notification() {
+			return Err(ReceiveError::InvalidNotification("Wrong Bundle type"));
+		}
+
+		let status = bundle
+			.entries()
+			.next()
+			.and_then(BundleEntryExt::resource)
+			.cloned()
+			.and_then(|resource| V::SubscriptionStatus::try_from(resource).ok())
+			.ok_or(ReceiveError::InvalidNotification("First entry is not a SubscriptionStatus"))?;
+
+		if (status.is_handshake() || status.is_heartbeat()) && bundle.entries().nth(1).is_some() {
+			return Err(ReceiveError::InvalidNotification(
+				"Handshake or heartbeat must not contain further entries",
+			));
+		}
+
+		if let Some(expected) = &self.subscription {
+			let reference = status.subscription_reference().unwrap_or_default();
+			let matches = reference == expected
+				|| reference
+					.strip_suffix(expected.as_str())
+					.is_some_and(|base| base.ends_with('/'));
+			if !matches {
+				return Err(ReceiveError::UnexpectedSubscription(reference.to_owned()));
+			}
+		}
+
+		Ok(Notification { status, bundle })
+	}
+}
+
+/// Compare two byte slices without leaking the position of the first
+/// difference via timing.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+	a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+impl<C, V> std::fmt::Debug for NotificationReceiver<C, V> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("NotificationReceiver")
+			.field("callback", &"<callback>")
+			.field("header_secret", &self.header_secret.as_ref().map(|(name, _)| name))
+			.field("subscription", &self.subscription)
+			.field("version", &std::any::type_name::<V>())
+			.finish()
+	}
+}
@@ -0,0 +1,139 @@
+#![cfg(feature = "r5")]
+#![allow(clippy::unwrap_used)]
+
+use std::sync::{
+	atomic::{AtomicUsize, Ordering},
+	Arc,
+};
+
+use http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use serde_json::json;
+
+use super::*;
+use crate::version::FhirR5;
+
+/// Create a notification `Bundle` of the given type, for the given subscription.
+fn notification(notification_type: &str, subscription: &str, resources: &[&str]) -> Vec<u8> {
+	let mut entries = vec![json!({
+		"resource": {
+			"resourceType": "SubscriptionStatus",
+			"status": "active",
+			"type": notification_type,
+			"subscription": { "reference": subscription },
+			"topic": "http://example.org/topic",
+		}
+	})];
+	entries.extend(resources.iter().map(|id| {
+		json!({
+			"fullUrl": format!("http://example.org/fhir/Patient/{id}"),
+			"resource": { "resourceType": "Patient", "id": id },
+		})
+	}));
+
+	serde_json::to_vec(&json!({
+		"resourceType": "Bundle",
+		"type": "subscription-notification",
+		"entry": entries,
+	}))
+	.unwrap()
+}
+
+/// Create a receiver counting the received notifications and their resources.
+fn receiver(
+	counter: Arc<AtomicUsize>,
+) -> NotificationReceiver<impl NotificationCallback<FhirR5>, FhirR5> {
+	NotificationReceiver::new(move |notification: Notification<FhirR5>| {
+		let counter = counter.clone();
+		async move {
+			counter.fetch_add(1 + notification.resources().count(), Ordering::SeqCst);
+			anyhow::Ok(())
+		}
+	})
+	.header_secret(HeaderName::from_static("x-secret"), HeaderValue::from_static("secret"))
+	.subscription("Subscription/1")
+}
+
+/// Headers with the correct secret.
+fn headers() -> HeaderMap {
+	let mut headers = HeaderMap::new();
+	headers.insert("x-secret", HeaderValue::from_static("secret"));
+	headers
+}
+
+#[tokio::test]
+async fn receive_notifications() -> anyhow::Result<()> {
+	let counter = Arc::new(AtomicUsize::new(0));
+	let receiver = receiver(counter.clone());
+
+	receiver.receive(&headers(), &notification("handshake", "Subscription/1", &[])).await?;
+	receiver.receive(&headers(), &notification("heartbeat", "Subscription/1", &[])).await?;
+	receiver
+		.receive(
+			&headers(),
+			&notification(
+				"event-notification",
+				"http://example.org/fhir/Subscription/1",
+				&["a", "b"],
+			),
+		)
+		.await?;
+	assert_eq!(counter.load(Ordering::SeqCst), 5);
+
+	Ok(())
+}
+
+#[tokio::test]
+async fn reject_invalid_notifications() -> anyhow::Result<()> {
+	let counter = Arc::new(AtomicUsize::new(0));
+	let receiver = receiver(counter.clone());
+
+	let result = receiver
+		.receive(&HeaderMap::new(), &notification("heartbeat", "Subscription/1", &[]))
+		.await;
+	assert!(matches!(result, Err(ReceiveError::Unauthorized)));
+
+	let result =
+		receiver.receive(&headers(), &notification("heartbeat", "Subscription/11", &[])).await;
+	assert!(matches!(result, Err(ReceiveError::UnexpectedSubscription(_))));
+
+	let result =
+		receiver.receive(&headers(), &notification("heartbeat", "Subscription/1", &["a"])).await;
+	assert!(matches!(result, Err(ReceiveError::InvalidNotification(_))));
+
+	let search_bundle = json!({ "resourceType": "Bundle", "type": "searchset" });
+	let result = receiver.receive(&headers(), &serde_json::to_vec(&search_bundle)?).await;
+	assert!(matches!(result, Err(ReceiveError::InvalidNotification(_))));
+
+	let result = receiver.receive(&headers(), b"not json").await;
+	assert_eq!(result.unwrap_err().status_code(), StatusCode::BAD_REQUEST);
+
+	assert_eq!(counter.load(Ordering::SeqCst), 0);
+	Ok(())
+}
+
+#[cfg(feature = "axum")]
+#[tokio::test]
+async fn axum_router() -> anyhow::Result<()> {
+	use ::axum::body::Body;
+	use tower::ServiceExt;
+
+	let counter = Arc::new(AtomicUsize::new(0));
+	let router = receiver(counter.clone()).into_router::<()>("/notifications");
+
+	let request = http::Request::post("/notifications")
+		.header("x-secret", "secret")
+		.body(Body::from(notification("event-notification", "Subscription/1", &["a"])))?;
+	let response = router.clone().oneshot(request).await?;
+	assert_eq!(response.status(), StatusCode::OK);
+	assert_eq!(counter.load(Ordering::SeqCst), 2);
+
+	let request = http::Request::post("/notifications").body(Body::from(notification(
+		"heartbeat",
+		"Subscription/1",
+		&[],
+	)))?;
+	let response = router.oneshot(request).await?;
+	assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+	Ok(())
+}
@@ -7,11 +7,14 @@ use std::{
 	str::FromStr,
 };
 
-use fhir_model::for_all_versions;
+use fhir_model::{for_all_versions, ElementDefinition};
 use serde::{de::DeserializeOwned, Serialize};
 
 use crate::{
-	extensions::{BundleEntryExt, BundleExt, GenericResource, ParametersExt, ReferenceExt},
+	extensions::{
+		BundleEntryExt, BundleExt, GenericResource, ParametersExt, ReferenceExt,
+		SubscriptionStatusExt,
+	},
 	utils::Sealed,
 };
 
@@ -103,7 +106,8 @@ pub trait FhirVersion: Sealed + Unpin + Send + Sync + 'static {
 		+ Send
 		+ Sync;
 	/// `OperationOutcome` resource.
-	type OperationOutcome: Serialize
+	type OperationOutcome: TryFrom<Self::Resource>
+		+ Serialize
 		+ DeserializeOwned
 		+ Debug
 		+ Clone
@@ -147,6 +151,10 @@ pub trait FhirVersion: Sealed + Unpin + Send + Sync + 'static {
 		+ Unpin
 		+ Send
 		+ Sync;
+
+	/// Get the definition of an element of a complex type or resource by its
+	/// path, e.g. `Patient.birthDate`. Paths of choice elements end in `[x]`.
+	fn element_definition(path: &str) -> Option<ElementDefinition>;
 }
 
 impl Sealed for FhirStu3 {}
@@ -173,8 +181,41 @@ macro_rules! impl_fhir_version {
 			type Reference = $version::types::Reference;
 
 			type SearchComparator = $version::codes::SearchComparator;
+
+			fn element_definition(path: &str) -> Option<ElementDefinition> {
+				$version::elements::element_definition(path)
+			}
 		}
 	};
 }
 #[cfg(feature = "builders")] // Depends on trait implementations that use the builders.
 for_all_versions!(impl_fhir_version);
+
+/// FHIR versions with topic-based subscriptions, i.e. R4B (via the subscriptions backport) and R5.
+/// Only implemented if "builders" feature is activated.
+pub trait SubscriptionVersion: FhirVersion {
+	/// `SubscriptionStatus` resource.
+	type SubscriptionStatus: SubscriptionStatusExt
+		+ TryFrom<Self::Resource>
+		+ Serialize
+		+ DeserializeOwned
+		+ Debug
+		+ Clone
+		+ PartialEq
+		+ Unpin
+		+ Send
+		+ Sync;
+}
+
+/// Implement `SubscriptionVersion` for all versions with topic-based subscriptions.
+macro_rules! impl_subscription_version {
+	// This version does not have topic-based subscriptions.
+	(stu3) => {};
+	($version:ident) => {
+		impl SubscriptionVersion for fhir_version!($version) {
+			type SubscriptionStatus = fhir_model::$version::resources::SubscriptionStatus;
+		}
+	};
+}
+#[cfg(feature = "builders")]
+for_all_versions!(impl_subscription_version);
@@ -52,9 +52,9 @@ macro_rules! impl_hapi_tests {
 	($version:ident) => {
 		mod $version {
 			use fhir_sdk::$version::{
-				codes::{AdministrativeGender, EncounterStatus, IssueSeverity, SearchComparator, HTTPVerb, BundleType},
+				codes::{AdministrativeGender, EncounterStatus, SearchComparator, HTTPVerb, BundleType},
 				resources::{
-					BaseResource, Bundle, Encounter, OperationOutcome, ParametersParameter,
+					BaseResource, Encounter, ParametersParameter,
 					ParametersParameterValue, Patient, Resource, ResourceType,
 				},
 				types::{HumanName, Identifier, Reference},
@@ -75,24 +75,6 @@ macro_rules! impl_hapi_tests {
 				Ok(Client::new(base_url)?)
 			}
 
-			/// Go through all entries of the bundle, extracting the outcomes and search
-			/// for errors inside. Fail if there is any of severity error or fatal.
-			fn ensure_batch_succeeded(bundle: Bundle) {
-				let batch_errors = bundle
-					.entry
-					.iter()
-					.flatten()
-					.filter_map(|entry| entry.response.as_ref())
-					.filter_map(|response| response.outcome.as_ref())
-					.filter_map(|resource| <&OperationOutcome>::try_from(resource).ok())
-					.flat_map(|outcome| outcome.issue.iter().flatten())
-					.any(|issue| {
-						matches!(issue.severity, IssueSeverity::Error | IssueSeverity::Fatal)
-					});
-				let bundle_json = serde_json::to_string_pretty(&bundle).expect("serialize Bundle");
-				assert!(!batch_errors, "Batch failed: {bundle_json}");
-			}
-
 			#[test]
 			fn crud() -> Result<()> {
 				common::RUNTIME.block_on(crud_inner())
@@ -309,7 +291,10 @@ macro_rules! impl_hapi_tests {
 						.unwrap(),
 				);
 
-				let mut entries = transaction.send().await?.0.entry.into_iter().flatten();
+				let response = transaction.send().await?.into_result()?;
+				assert!(response.resolve_temp_id(&patient_ref).is_some());
+				let mut entries =
+					response.into_entries().into_iter().filter_map(|entry| entry.response);
 				let _delete = entries.next().expect("DELETE response");
 				let _read = entries.next().expect("GET response");
 				let _update = entries.next().expect("PUT response");
@@ -362,7 +347,7 @@ macro_rules! impl_hapi_tests {
 				for _ in 0..n {
 					batch.create(patient.clone());
 				}
-				ensure_batch_succeeded(batch.send().await?);
+				batch.send().await?.into_result()?;
 
 				println!("Starting search..");
 				let patients: Vec<Patient> = client
@@ -382,7 +367,7 @@ macro_rules! impl_hapi_tests {
 				for patient in patients {
 					batch.delete(ResourceType::Patient, patient.id.as_ref().expect("Patient.id"));
 				}
-				ensure_batch_succeeded(batch.send().await?);
+				batch.send().await?.into_result()?;
 				Ok(())
 			}
 
@@ -9,12 +9,8 @@ use anyhow::Result;
 use fhir_sdk::{
 	client::{Client, DateSearch, ResourceWrite, SearchParameters, TokenSearch},
 	r4b::{
-		codes::{
-			AdministrativeGender, BundleType, EncounterStatus, IssueSeverity, SearchComparator,
-		},
-		resources::{
-			BaseResource, Bundle, Encounter, OperationOutcome, Patient, Resource, ResourceType,
-		},
+		codes::{AdministrativeGender, BundleType, EncounterStatus, SearchComparator},
+		resources::{BaseResource, Encounter, Patient, Resource, ResourceType},
 		types::{Coding, HumanName, Reference},
 	},
 	version::FhirR4B,
@@ -87,21 +83,6 @@ async fn client() -> Result<Client<FhirR4B>> {
 	Ok(client.clone())
 }
 
-/// Go through all entries of the bundle, extracting the outcomes and search for
-/// errors inside. Fail if there is any of severity error or fatal.
-fn ensure_batch_succeeded(bundle: Bundle) {
-	let batch_errors = bundle
-		.entry
-		.iter()
-		.flatten()
-		.filter_map(|entry| entry.response.as_ref())
-		.filter_map(|response| response.outcome.as_ref())
-		.filter_map(|resource| <&OperationOutcome>::try_from(resource).ok())
-		.flat_map(|outcome| outcome.issue.iter().flatten())
-		.any(|issue| matches!(issue.severity, IssueSeverity::Error | IssueSeverity::Fatal));
-	assert!(!batch_errors);
-}
-
 #[test]
 fn crud() -> Result<()> {
 	common::RUNTIME.block_on(crud_inner())
@@ -263,7 +244,9 @@ async fn transaction_inner() -> Result<()> {
 			.unwrap(),
 	);
 
-	let mut entries = transaction.send().await?.0.entry.into_iter().flatten();
+	let response = transaction.send().await?.into_result()?;
+	assert!(response.resolve_temp_id(&patient_ref).is_some());
+	let mut entries = response.into_entries().into_iter().filter_map(|entry| entry.response);
 	let _delete = entries.next().expect("DELETE response");
 	let _read = entries.next().expect("GET response");
 	let _update = entries.next().expect("PUT response");
@@ -316,7 +299,7 @@ async fn paging_inner() -> Result<()> {
 	for _ in 0 .. n {
 		batch.create(patient.clone());
 	}
-	ensure_batch_succeeded(batch.send().await?);
+	batch.send().await?.into_result()?;
 
 	println!("Starting search..");
 	let patients: Vec<Patient> = client
@@ -336,7 +319,7 @@ async fn paging_inner() -> Result<()> {
 	for patient in patients {
 		batch.delete(ResourceType::Patient, patient.id.as_ref().expect("Patient.id"));
 	}
-	ensure_batch_succeeded(batch.send().await?);
+	batch.send().await?.into_result()?;
 
 	assert_eq!(patients_len, n);
 	Ok(())
@@ -0,0 +1,160 @@
+//! Tests for the OpenTelemetry integration of the client. These live in their
+//! own test binary, because the client registers its instruments on the global
+//! meter provider, which has to be set up before the first request.
+#![cfg(all(feature = "otel", feature = "r5"))]
+#![allow(clippy::expect_used, clippy::unwrap_used, clippy::indexing_slicing)]
+
+use std::sync::{Arc, Weak};
+
+use anyhow::Result;
+use fhir_sdk::client::{Client, RequestSettings};
+use opentelemetry::{global, trace::TracerProvider as _};
+use opentelemetry_sdk::{
+	metrics::{
+		data::{Aggregation as _, ResourceMetrics, Sum, Temporality},
+		reader::{AggregationSelector, MetricReader, TemporalitySelector},
+		Aggregation, InstrumentKind, ManualReader, Pipeline, SdkMeterProvider,
+	},
+	propagation::TraceContextPropagator,
+	trace::TracerProvider,
+	Resource,
+};
+use reqwest::{header::HeaderValue, Method, StatusCode, Url};
+use tracing::Instrument;
+use tracing_subscriber::layer::SubscriberExt;
+use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+/// Metric reader that can be registered on the meter provider and still be
+/// collected from in the test.
+#[derive(Debug, Clone)]
+struct SharedReader(Arc<ManualReader>);
+
+impl TemporalitySelector for SharedReader {
+	fn temporality(&self, kind: InstrumentKind) -> Temporality {
+		self.0.temporality(kind)
+	}
+}
+
+impl AggregationSelector for SharedReader {
+	fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+		self.0.aggregation(kind)
+	}
+}
+
+impl MetricReader for SharedReader {
+	fn register_pipeline(&self, pipeline: Weak<Pipeline>) {
+		self.0.register_pipeline(pipeline);
+	}
+
+	fn collect(&self, rm: &mut ResourceMetrics) -> opentelemetry::metrics::Result<()> {
+		self.0.collect(rm)
+	}
+
+	fn force_flush(&self) -> opentelemetry::metrics::Result<()> {
+		self.0.force_flush()
+	}
+
+	fn shutdown(&self) -> opentelemetry::metrics::Result<()> {
+		self.0.shutdown()
+	}
+}
+
+impl SharedReader {
+	/// Collect the sum of all data points of the counter with the given name.
+	fn counter(&self, name: &str) -> Result<u64> {
+		let mut metrics =
+			ResourceMetrics { resource: Resource::empty(), scope_metrics: Vec::new() };
+		self.collect(&mut metrics)?;
+		let total = metrics
+			.scope_metrics
+			.iter()
+			.flat_map(|scope| scope.metrics.iter())
+			.filter(|metric| metric.name == name)
+			.filter_map(|metric| metric.data.as_any().downcast_ref::<Sum<u64>>())
+			.flat_map(|sum| sum.data_points.iter())
+			.map(|point| point.value)
+			.sum();
+		Ok(total)
+	}
+}
+
+#[tokio::test]
+async fn propagation_and_counters() -> Result<()> {
+	let reader = SharedReader(Arc::new(ManualReader::builder().build()));
+	global::set_meter_provider(SdkMeterProvider::builder().with_reader(reader.clone()).build());
+	global::set_text_map_propagator(TraceContextPropagator::new());
+	let tracer = TracerProvider::builder().build().tracer("client-otel");
+	let subscriber =
+		tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+	let _guard = tracing::subscriber::set_default(subscriber);
+
+	let server = MockServer::start().await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/fhir/retried"))
+		.respond_with(ResponseTemplate::new(StatusCode::SERVICE_UNAVAILABLE))
+		.with_priority(1)
+		.expect(1)
+		.up_to_n_times(1)
+		.mount(&server)
+		.await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/fhir/unauthorized"))
+		.respond_with(ResponseTemplate::new(StatusCode::UNAUTHORIZED))
+		.with_priority(1)
+		.expect(1)
+		.up_to_n_times(1)
+		.mount(&server)
+		.await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::path("/fhir/missing"))
+		.respond_with(ResponseTemplate::new(StatusCode::NOT_FOUND))
+		.with_priority(1)
+		.expect(1)
+		.mount(&server)
+		.await;
+	Mock::given(matchers::method(Method::GET))
+		.and(matchers::header_exists("traceparent"))
+		.respond_with(ResponseTemplate::new(StatusCode::OK))
+		.with_priority(5)
+		.expect(2)
+		.mount(&server)
+		.await;
+
+	let client = <Client>::builder()
+		.base_url(Url::parse(&format!("{}/fhir/", server.uri()))?)
+		.request_settings(RequestSettings::default().fixed_retry(std::time::Duration::ZERO))
+		.auth_callback(|_http: reqwest::Client| async move {
+			anyhow::Ok(HeaderValue::from_static("Bearer <token>"))
+		})
+		.allow_version_mismatch()
+		.build()?;
+
+	let span = tracing::info_span!("test");
+	async {
+		for (path, status) in [
+			("retried", StatusCode::OK),
+			("unauthorized", StatusCode::OK),
+			("missing", StatusCode::NOT_FOUND),
+		] {
+			let url = format!("{}/fhir/{path}", server.uri());
+			let response = client.send_custom_request(|http| http.get(url)).await?;
+			assert_eq!(response.status(), status);
+		}
+		anyhow::Ok(())
+	}
+	.instrument(span)
+	.await?;
+
+	// Every attempt carries the trace context, otherwise the `OK` responses
+	// would not match.
+	let requests = server.received_requests().await.unwrap_or_default();
+	assert_eq!(requests.len(), 5);
+	assert!(requests.iter().all(|request| request.headers.contains_key("traceparent")));
+
+	assert_eq!(reader.counter("fhir.client.request.retries")?, 1);
+	assert_eq!(reader.counter("fhir.client.auth.refreshes")?, 1);
+	assert_eq!(reader.counter("fhir.client.request.errors")?, 1);
+
+	server.verify().await;
+	Ok(())
+}
@@ -0,0 +1,76 @@
+//! Generate the element definitions lookup.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+
+use crate::model::{
+	structures::{Field, ObjectField, Type},
+	StructureDefinitionKind,
+};
+
+/// Generate the `element_definition` function, mapping element paths of the
+/// complex types and resources to their definitions.
+pub fn generate_element_definitions(types: &[Type], resources: &[Type]) -> TokenStream {
+	let mut arms = Vec::new();
+	for ty in types.iter().chain(resources) {
+		if ty.r#abstract
+			|| !matches!(
+				ty.kind,
+				StructureDefinitionKind::ComplexType | StructureDefinitionKind::Resource
+			) {
+			continue;
+		}
+		collect_element_definitions(&ty.elements, &ty.name, &mut arms);
+	}
+
+	quote! {
+		/// Get the definition of an element of a complex type or resource by its
+		/// path, e.g. `Patient.birthDate` or `HumanName.given`. Paths of choice
+		/// elements end in `[x]`, e.g. `Observation.value[x]`.
+		#[must_use]
+		pub fn element_definition(path: &str) -> Option<ElementDefinition> {
+			let definition = match path {
+				#(#arms)*
+				_ => return None,
+			};
+			Some(definition)
+		}
+	}
+}
+
+/// Collect the match arms for the sub-fields of the object, recursively.
+fn collect_element_definitions(object: &ObjectField, path: &str, arms: &mut Vec<TokenStream>) {
+	for field in &object.fields {
+		let field_path = format!("{path}.{}", field.name());
+		let (types, content_reference) = match field {
+			Field::Standard(field) => (vec![field.r#type.clone()], None),
+			Field::Code(field) => (vec![field.r#type.clone()], None),
+			Field::Choice(field) => (field.types.clone(), None),
+			Field::Object(field) => (
+				field.r#type.clone().into_iter().collect(),
+				field
+					.content_reference
+					.as_deref()
+					.and_then(|reference| reference.rsplit('#').next())
+					.map(ToOwned::to_owned),
+			),
+		};
+		let content_reference = match content_reference {
+			Some(reference) => quote!(Some(#reference)),
+			None => quote!(None),
+		};
+		let is_array = field.is_array();
+
+		arms.push(quote! {
+			#field_path => ElementDefinition {
+				types: &[#(#types),*],
+				content_reference: #content_reference,
+				is_array: #is_array,
+			},
+		});
+
+		if let Field::Object(object) = field {
+			collect_element_definitions(object, &field_path, arms);
+		}
+	}
+}
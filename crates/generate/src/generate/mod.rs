@@ -2,6 +2,7 @@
 
 mod comments;
 mod gen_codes;
+mod gen_elements;
 mod gen_traits;
 mod gen_types;
 
@@ -55,7 +56,7 @@ pub fn generate_codes(mut codes: Vec<Code>) -> Result<(TokenStream, HashMap<Stri
 
 /// Generate the Rust code for the FHIR types.
 pub fn generate_types(
-	types: Vec<Type>,
+	types: &[Type],
 	implemented_codes: &HashMap<String, String>,
 ) -> Result<TokenStream> {
 	// Set generation variables.
@@ -188,6 +189,25 @@ pub fn generate_resources(
 	})
 }
 
+/// Generate the Rust code for the element definitions of the FHIR types and
+/// resources.
+pub fn generate_elements(types: &[Type], resources: &[Type]) -> TokenStream {
+	// Set generation variables.
+	let module_doc = " Generated code! Take a look at the generator-crate for changing this file!";
+
+	let element_definitions = gen_elements::generate_element_definitions(types, resources);
+
+	// Generate the code.
+	quote! {
+		#![doc = #module_doc]
+		#![allow(clippy::too_many_lines)]
+
+		use crate::ElementDefinition;
+
+		#element_definitions
+	}
+}
+
 /// Conversion implementations between specific resources and the Resource enum.
 fn resource_conversion_impls(names: &[Ident]) -> TokenStream {
 	quote! {